@@ -0,0 +1,285 @@
+//! Select the authentication scheme from the `Proxy-Authenticate` challenges of the proxy.
+
+#[cfg(feature = "negotiate")]
+use crate::negotiate::NegotiateAuthenticator;
+use crate::{Authenticator, netrc, netrc::BasicAuthenticator, ntlm::NtlmAuthenticator};
+use http::header::PROXY_AUTHENTICATE;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Authentication schemes, ordered from the weakest to the strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scheme {
+    Basic,
+    Ntlm,
+    #[cfg(feature = "negotiate")]
+    Negotiate,
+}
+
+impl Scheme {
+    fn parse(challenge: &str) -> Option<Self> {
+        let name = challenge.split_whitespace().next()?.to_ascii_lowercase();
+        match name.as_str() {
+            "basic" => Some(Self::Basic),
+            "ntlm" => Some(Self::Ntlm),
+            #[cfg(feature = "negotiate")]
+            "negotiate" => Some(Self::Negotiate),
+            _ => None,
+        }
+    }
+
+    /// Name of the scheme, e.g. `basic`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Basic => "basic",
+            Self::Ntlm => "ntlm",
+            #[cfg(feature = "negotiate")]
+            Self::Negotiate => "negotiate",
+        }
+    }
+}
+
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Schemes chosen so far, by proxy host.
+#[derive(Debug, Clone, Default)]
+pub struct Choices(Arc<RwLock<HashMap<String, Scheme>>>);
+
+impl Choices {
+    pub fn get(&self, proxy_fqdn: &str) -> Option<Scheme> {
+        self.0.read().unwrap().get(proxy_fqdn).copied()
+    }
+
+    fn set(&self, proxy_fqdn: &str, scheme: Scheme) {
+        self.0
+            .write()
+            .unwrap()
+            .insert(proxy_fqdn.to_owned(), scheme);
+    }
+}
+
+/// Authenticator which waits for the first `407` response to pick the scheme.
+///
+/// The strongest scheme offered by the proxy for which credentials are available is used and
+/// remembered for later connections to the same proxy. Whether Negotiate has credentials (e.g. a
+/// Kerberos ticket) is only known after its first step, if that fails the next scheme is tried.
+#[derive(Debug, Clone)]
+pub struct AutoAuthenticator {
+    proxy_fqdn: String,
+    store: netrc::Store,
+    choices: Choices,
+    selected: Arc<Mutex<Option<Authenticator>>>,
+    /// Schemes whose first step failed.
+    failed: Arc<Mutex<HashSet<Scheme>>>,
+}
+
+impl AutoAuthenticator {
+    pub fn new(proxy_fqdn: &str, store: netrc::Store, choices: Choices) -> crate::Result<Self> {
+        let this = Self {
+            proxy_fqdn: proxy_fqdn.to_owned(),
+            store,
+            choices,
+            selected: Default::default(),
+            failed: Default::default(),
+        };
+        if let Some(scheme) = this.choices.get(proxy_fqdn) {
+            *this.selected.lock().unwrap() = Some(this.make(scheme)?);
+        }
+        Ok(this)
+    }
+
+    fn make(&self, scheme: Scheme) -> crate::Result<Authenticator> {
+        match scheme {
+            Scheme::Basic => {
+                let token = self.store.get(&self.proxy_fqdn)?;
+                Ok(Authenticator::Basic(BasicAuthenticator::new(token)))
+            }
+            Scheme::Ntlm => {
+                let credentials = self.store.credentials(&self.proxy_fqdn)?;
+                Ok(Authenticator::Ntlm(NtlmAuthenticator::new(credentials)))
+            }
+            #[cfg(feature = "negotiate")]
+            Scheme::Negotiate => Ok(Authenticator::Negotiate(NegotiateAuthenticator::new(
+                &self.proxy_fqdn,
+            )?)),
+        }
+    }
+
+    /// The schemes of `headers` which may have credentials, strongest first.
+    fn candidates(&self, headers: &hyper::HeaderMap) -> Vec<Scheme> {
+        let has_credentials = self.store.credentials(&self.proxy_fqdn).is_ok();
+        let failed = self.failed.lock().unwrap();
+        let mut schemes = headers
+            .get_all(PROXY_AUTHENTICATE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(Scheme::parse)
+            .filter(|scheme| !failed.contains(scheme))
+            .filter(|scheme| match scheme {
+                Scheme::Basic | Scheme::Ntlm => has_credentials,
+                // credentials are checked by the first step
+                #[cfg(feature = "negotiate")]
+                Scheme::Negotiate => true,
+            })
+            .collect::<Vec<_>>();
+        schemes.sort_unstable_by(|a, b| b.cmp(a));
+        schemes.dedup();
+        schemes
+    }
+
+    fn selected(&self) -> Option<Authenticator> {
+        self.selected.lock().unwrap().clone()
+    }
+
+    /// Name of the selected scheme, `auto` while none is selected yet.
+    pub fn scheme(&self) -> &'static str {
+        self.selected
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or("auto", Authenticator::scheme)
+    }
+
+    pub(crate) async fn step(
+        &self,
+        last_headers: Option<hyper::HeaderMap>,
+    ) -> crate::Result<hyper::HeaderMap> {
+        if let Some(selected) = self.selected() {
+            return selected.step(last_headers).await;
+        }
+        let Some(last_headers) = last_headers else {
+            // no scheme known yet, wait for the challenges of the proxy
+            return Ok(Default::default());
+        };
+        let mut error = None;
+        for scheme in self.candidates(&last_headers) {
+            // the challenge which made us choose the scheme is not a continuation
+            let step = match self.make(scheme) {
+                Ok(selected) => selected.step(None).await.map(|headers| (selected, headers)),
+                Err(cause) => Err(cause),
+            };
+            match step {
+                Ok((selected, headers)) => {
+                    self.choices.set(&self.proxy_fqdn, scheme);
+                    *self.selected.lock().unwrap() = Some(selected);
+                    return Ok(headers);
+                }
+                Err(cause) => {
+                    self.failed.lock().unwrap().insert(scheme);
+                    error = Some(cause);
+                }
+            }
+        }
+        match error {
+            Some(cause) => Err(cause),
+            None => Ok(Default::default()),
+        }
+    }
+
+    pub(crate) fn has_challenge(&self, headers: &hyper::HeaderMap) -> bool {
+        match self.selected() {
+            Some(selected) => selected.has_challenge(headers),
+            None => !self.candidates(headers).is_empty(),
+        }
+    }
+
+    pub(crate) fn is_connection_based(&self) -> bool {
+        self.selected()
+            .is_some_and(|selected| selected.is_connection_based())
+    }
+
+    pub(crate) async fn finish(&self, headers: &hyper::HeaderMap) -> crate::Result<()> {
+        match self.selected() {
+            Some(selected) => selected.finish(headers).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{HeaderValue, header::PROXY_AUTHORIZATION};
+
+    fn challenges(schemes: &[&'static str]) -> hyper::HeaderMap {
+        let mut headers = hyper::HeaderMap::new();
+        for s in schemes {
+            headers.append(PROXY_AUTHENTICATE, HeaderValue::from_static(s));
+        }
+        headers
+    }
+
+    fn store() -> netrc::Store {
+        netrc::Store::new(std::io::Cursor::new(
+            "machine proxy.example.org\nlogin hello\npassword world\n",
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_scheme() {
+        assert_eq!(Scheme::parse("Basic realm=\"proxy\""), Some(Scheme::Basic));
+        assert_eq!(Scheme::parse("ntlm"), Some(Scheme::Ntlm));
+        assert_eq!(Scheme::parse("Digest realm=\"proxy\""), None);
+        assert_eq!(Scheme::parse(""), None);
+    }
+
+    #[tokio::test]
+    async fn choose_strongest_with_credentials() -> crate::Result<()> {
+        let choices = Choices::default();
+        let auth = AutoAuthenticator::new("proxy.example.org", store(), choices.clone())?;
+        assert!(auth.step(None).await?.is_empty());
+        assert_eq!(auth.scheme(), "auto");
+
+        let headers = challenges(&["Basic realm=\"proxy\"", "NTLM"]);
+        assert!(auth.has_challenge(&headers));
+        let headers = auth.step(Some(headers)).await?;
+        let value = headers.get(PROXY_AUTHORIZATION).unwrap().to_str()?;
+        assert!(value.starts_with("NTLM "));
+        assert_eq!(auth.scheme(), "ntlm");
+        assert_eq!(choices.get("proxy.example.org"), Some(Scheme::Ntlm));
+
+        // later authenticators for the same proxy start with the remembered scheme
+        let auth = AutoAuthenticator::new("proxy.example.org", store(), choices.clone())?;
+        let headers = auth.step(None).await?;
+        let value = headers.get(PROXY_AUTHORIZATION).unwrap().to_str()?;
+        assert!(value.starts_with("NTLM "));
+        Ok(())
+    }
+
+    #[cfg(feature = "negotiate")]
+    #[tokio::test]
+    async fn negotiate_without_credentials() -> crate::Result<()> {
+        // there is no Kerberos ticket for the proxy, hence the first Negotiate step fails
+        let choices = Choices::default();
+        let auth = AutoAuthenticator::new("proxy.example.org", store(), choices.clone())?;
+        let headers = challenges(&["Negotiate", "Basic realm=\"proxy\""]);
+        assert!(auth.has_challenge(&headers));
+        let headers = auth.step(Some(headers)).await?;
+        let value = headers.get(PROXY_AUTHORIZATION).unwrap().to_str()?;
+        assert!(value.starts_with("Basic "));
+        assert_eq!(choices.get("proxy.example.org"), Some(Scheme::Basic));
+
+        // nothing is remembered when no scheme is usable
+        let choices = Choices::default();
+        let auth = AutoAuthenticator::new("other.example.org", store(), choices.clone())?;
+        let headers = challenges(&["Negotiate"]);
+        assert!(auth.step(Some(headers.clone())).await.is_err());
+        assert!(!auth.has_challenge(&headers));
+        assert_eq!(choices.get("other.example.org"), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_credentials() -> crate::Result<()> {
+        let auth = AutoAuthenticator::new("other.example.org", store(), Choices::default())?;
+        let headers = challenges(&["Basic realm=\"proxy\"", "NTLM"]);
+        assert!(!auth.has_challenge(&headers));
+        assert!(auth.step(Some(headers)).await?.is_empty());
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
+pub mod auto;
 #[cfg(feature = "negotiate")]
 pub mod negotiate;
 pub mod netrc;
+pub mod ntlm;
 
+use self::auto::AutoAuthenticator;
 #[cfg(feature = "negotiate")]
 use self::negotiate::NegotiateAuthenticator;
 use self::netrc::BasicAuthenticator;
+use self::ntlm::NtlmAuthenticator;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
@@ -14,17 +18,71 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Authenticator {
     None,
     Basic(BasicAuthenticator),
+    Ntlm(NtlmAuthenticator),
     #[cfg(feature = "negotiate")]
     Negotiate(NegotiateAuthenticator),
+    Auto(AutoAuthenticator),
 }
 
 impl Authenticator {
+    /// Name of the authentication scheme, for `Auto` the one negotiated with the proxy.
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Basic(_) => "basic",
+            Self::Ntlm(_) => "ntlm",
+            #[cfg(feature = "negotiate")]
+            Self::Negotiate(_) => "negotiate",
+            Self::Auto(auto) => auto.scheme(),
+        }
+    }
+
     pub async fn step(&self, last_headers: Option<hyper::HeaderMap>) -> Result<hyper::HeaderMap> {
         match self {
             Self::None => Ok(Default::default()),
             Self::Basic(basic) => basic.step(last_headers).await,
+            Self::Ntlm(ntlm) => ntlm.step(last_headers).await,
             #[cfg(feature = "negotiate")]
             Self::Negotiate(spnego) => spnego.step(last_headers).await,
+            Self::Auto(auto) => Box::pin(auto.step(last_headers)).await,
+        }
+    }
+
+    /// Returns `true` if the headers of a `407` response carry a challenge which can be
+    /// answered by another `step`.
+    pub fn has_challenge(&self, headers: &hyper::HeaderMap) -> bool {
+        match self {
+            Self::None => false,
+            Self::Basic(_) => false,
+            Self::Ntlm(ntlm) => ntlm.has_challenge(headers),
+            #[cfg(feature = "negotiate")]
+            Self::Negotiate(spnego) => spnego.has_challenge(headers),
+            Self::Auto(auto) => auto.has_challenge(headers),
+        }
+    }
+
+    /// Returns `true` if a completed handshake authenticates the whole connection (NTLM and
+    /// Negotiate), such that later requests on the same connection need no credentials.
+    pub fn is_connection_based(&self) -> bool {
+        match self {
+            Self::None => false,
+            Self::Basic(_) => false,
+            Self::Ntlm(_) => true,
+            #[cfg(feature = "negotiate")]
+            Self::Negotiate(_) => true,
+            Self::Auto(auto) => auto.is_connection_based(),
+        }
+    }
+
+    /// Check the headers of the final, successful response (e.g. for mutual authentication).
+    pub async fn finish(&self, headers: &hyper::HeaderMap) -> Result<()> {
+        match self {
+            Self::None => Ok(()),
+            Self::Basic(_) => Ok(()),
+            Self::Ntlm(_) => Ok(()),
+            #[cfg(feature = "negotiate")]
+            Self::Negotiate(spnego) => spnego.finish(headers).await,
+            Self::Auto(auto) => Box::pin(auto.finish(headers)).await,
         }
     }
 }
@@ -33,8 +91,10 @@ impl Authenticator {
 pub enum AuthenticatorFactory {
     None,
     Basic(netrc::Store),
+    Ntlm(netrc::Store),
     #[cfg(feature = "negotiate")]
     Negotiate(Vec<String>),
+    Auto(netrc::Store, auto::Choices),
 }
 
 impl AuthenticatorFactory {
@@ -46,11 +106,52 @@ impl AuthenticatorFactory {
         Self::Basic(store)
     }
 
+    pub fn ntlm(store: netrc::Store) -> Self {
+        Self::Ntlm(store)
+    }
+
+    /// Choose the scheme per proxy from its `407` challenges, see [`AutoAuthenticator`].
+    pub fn auto(store: netrc::Store) -> Self {
+        Self::Auto(store, Default::default())
+    }
+
     #[cfg(feature = "negotiate")]
     pub fn negotiate(hosts: Vec<String>) -> Self {
         Self::Negotiate(hosts)
     }
 
+    /// Name of the authentication scheme, e.g. `basic`.
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Basic(_) => "basic",
+            Self::Ntlm(_) => "ntlm",
+            #[cfg(feature = "negotiate")]
+            Self::Negotiate(_) => "negotiate",
+            Self::Auto(_, _) => "auto",
+        }
+    }
+
+    /// Name of the authentication scheme used with `proxy_fqdn`, for `auto` the one negotiated
+    /// with the proxy so far.
+    pub fn negotiated_scheme(&self, proxy_fqdn: &str) -> &'static str {
+        match self {
+            Self::Auto(_, choices) => choices.get(proxy_fqdn).map_or("auto", |s| s.as_str()),
+            _ => self.scheme(),
+        }
+    }
+
+    /// Login and password for `proxy_fqdn`, used by protocols which do not authenticate via
+    /// HTTP headers (e.g. SOCKS5).
+    pub fn credentials(&self, proxy_fqdn: &str) -> Option<netrc::Credentials> {
+        match self {
+            Self::Basic(store) | Self::Ntlm(store) | Self::Auto(store, _) => {
+                store.credentials(proxy_fqdn).ok()
+            }
+            _ => None,
+        }
+    }
+
     pub fn make(&self, proxy_fqdn: &str) -> Result<Authenticator> {
         match self {
             Self::None => Ok(Authenticator::None),
@@ -58,6 +159,10 @@ impl AuthenticatorFactory {
                 let token = store.get(proxy_fqdn)?;
                 Ok(Authenticator::Basic(BasicAuthenticator::new(token)))
             }
+            Self::Ntlm(store) => {
+                let credentials = store.credentials(proxy_fqdn)?;
+                Ok(Authenticator::Ntlm(NtlmAuthenticator::new(credentials)))
+            }
             #[cfg(feature = "negotiate")]
             Self::Negotiate(hosts) => {
                 if hosts.is_empty() || hosts.iter().any(|k| k == proxy_fqdn) {
@@ -71,6 +176,11 @@ impl AuthenticatorFactory {
                     Ok(Authenticator::None)
                 }
             }
+            Self::Auto(store, choices) => Ok(Authenticator::Auto(AutoAuthenticator::new(
+                proxy_fqdn,
+                store.clone(),
+                choices.clone(),
+            )?)),
         }
     }
 }
@@ -87,6 +197,14 @@ impl std::fmt::Display for AuthenticatorFactory {
                     write!(f, "basic {}", store.hosts().join(","))?;
                 }
             }
+            Self::Ntlm(ref store) => {
+                let hosts = store.hosts();
+                if hosts.is_empty() {
+                    f.write_str("ntlm")?;
+                } else {
+                    write!(f, "ntlm {}", store.hosts().join(","))?;
+                }
+            }
             #[cfg(feature = "negotiate")]
             Self::Negotiate(ref hosts) => {
                 if hosts.is_empty() {
@@ -95,6 +213,7 @@ impl std::fmt::Display for AuthenticatorFactory {
                     write!(f, "negotiate {}", hosts.join(","))?;
                 }
             }
+            Self::Auto(_, _) => f.write_str("auto")?,
         };
         Ok(())
     }
@@ -3,6 +3,7 @@ use http::{
     HeaderValue,
     header::{PROXY_AUTHENTICATE, PROXY_AUTHORIZATION},
 };
+use std::sync::{Arc, Mutex};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -10,55 +11,100 @@ pub enum Error {
     ContextCreationFailed(Box<dyn std::error::Error + Send + Sync>),
     #[error("Failed to execute authorization step: {0}")]
     AuthorizationStepFailed(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Mutual authentication failed: {0}")]
+    MutualAuthenticationFailed(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Mutual authentication failed: no security context")]
+    NoContext,
 }
 
+/// Negotiate (SPNEGO) authentication against a proxy.
+///
+/// The security context is kept between the steps, such that tokens from `407` responses of
+/// the proxy can be fed back until the handshake completes.
 #[derive(Debug, Clone)]
 pub struct NegotiateAuthenticator {
     proxy_fqdn: String,
+    cx: Arc<Mutex<Option<spnego::Context>>>,
 }
 
 impl NegotiateAuthenticator {
     pub fn new(proxy_fqdn: &str) -> std::result::Result<Self, Error> {
         Ok(Self {
             proxy_fqdn: proxy_fqdn.to_owned(),
+            cx: Default::default(),
         })
     }
 
+    /// Compute the `Proxy-Authorization` header.
+    ///
+    /// Without a server token in `last_headers` a new handshake is started, otherwise the
+    /// server token is passed to the security context of the previous step.
     pub(crate) async fn step(
         &self,
-        _last_headers: Option<hyper::HeaderMap>,
+        last_headers: Option<hyper::HeaderMap>,
     ) -> crate::Result<hyper::HeaderMap> {
         let mut headers = hyper::HeaderMap::new();
-        // let challenge = last_headers.map(|h| server_token(&h)).flatten();
-        // let challenge = challenge.as_deref();
+        let challenge = last_headers.as_ref().and_then(server_token);
         let proxy_fqdn = self.proxy_fqdn.clone();
-        let headers: crate::Result<_> = tokio::task::spawn_blocking(move || {
-            let client_ctx = spnego::Context::new("HTTP", &proxy_fqdn);
-
-            match client_ctx {
-                Ok(mut cx) => match cx.step(None) {
-                    Ok(Some(token)) => {
-                        let b64token = base64::engine::general_purpose::STANDARD.encode(&*token);
-                        let auth_str = format!("Negotiate {b64token}");
-                        headers.append(
-                            PROXY_AUTHORIZATION,
-                            HeaderValue::from_str(&auth_str).expect("valid header value"),
-                        );
-                        Ok(headers)
-                    }
-                    Ok(None) => Ok(headers),
-                    Err(cause) => Err(cause.into()),
-                },
-                Err(cause) => Err(cause.into()),
-            }
+        let cx = self.cx.lock().unwrap().take();
+        let (cx, token) = tokio::task::spawn_blocking(move || {
+            let mut cx = match (cx, &challenge) {
+                (Some(cx), Some(_)) => cx,
+                _ => spnego::Context::new("HTTP", &proxy_fqdn)
+                    .map_err(|e| Error::ContextCreationFailed(e.into()))?,
+            };
+            let token = cx
+                .step(challenge.as_deref())
+                .map_err(|e| Error::AuthorizationStepFailed(e.into()))?;
+            Ok::<_, Error>((cx, token))
         })
-        .await?;
-        headers
+        .await??;
+        *self.cx.lock().unwrap() = Some(cx);
+
+        if let Some(token) = token {
+            let b64token = base64::engine::general_purpose::STANDARD.encode(&*token);
+            let auth_str = format!("Negotiate {b64token}");
+            headers.append(
+                PROXY_AUTHORIZATION,
+                HeaderValue::from_str(&auth_str).expect("valid header value"),
+            );
+        }
+        Ok(headers)
+    }
+
+    /// Returns `true` if `headers` contain a server token to continue the handshake with.
+    pub(crate) fn has_challenge(&self, headers: &hyper::HeaderMap) -> bool {
+        server_token(headers).is_some()
+    }
+
+    /// Verify the final server token of a successful response (mutual authentication).
+    ///
+    /// Mutual authentication is always requested, hence a response without a final token is
+    /// only accepted if the handshake already completed in a previous step.
+    pub(crate) async fn finish(&self, headers: &hyper::HeaderMap) -> crate::Result<()> {
+        let Some(token) = server_token(headers) else {
+            return match self.cx.lock().unwrap().as_ref() {
+                Some(cx) if !cx.is_complete() => Err(Error::MutualAuthenticationFailed(
+                    "no final token from the proxy".into(),
+                )
+                .into()),
+                _ => Ok(()),
+            };
+        };
+        let Some(mut cx) = self.cx.lock().unwrap().take() else {
+            return Err(Error::NoContext.into());
+        };
+        tokio::task::spawn_blocking(move || {
+            cx.step(Some(&token))
+                .map(|_| ())
+                .map_err(|e| Error::MutualAuthenticationFailed(e.into()))
+        })
+        .await??;
+        Ok(())
     }
 }
 
 // Extract the server token from "Proxy-Authenticate: Negotiate <base64>" header value
-#[allow(unused)]
 fn server_token(last_headers: &hyper::HeaderMap) -> Option<Vec<u8>> {
     last_headers
         .get_all(PROXY_AUTHENTICATE)
@@ -89,4 +135,46 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn has_challenge_test() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let auth = super::NegotiateAuthenticator::new("proxy.example.org")?;
+        let mut headers = hyper::HeaderMap::new();
+        headers.append(
+            http::header::PROXY_AUTHENTICATE,
+            http::HeaderValue::from_static("Negotiate"),
+        );
+        assert!(!auth.has_challenge(&headers));
+        // no final token, nothing to verify
+        auth.finish(&headers).await?;
+
+        headers.append(
+            http::header::PROXY_AUTHENTICATE,
+            http::HeaderValue::from_static("Negotiate SGVsbG8gV29ybGQh"),
+        );
+        assert!(auth.has_challenge(&headers));
+        // final token without a previous step
+        assert!(auth.finish(&headers).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn finish_without_final_token() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let auth = super::NegotiateAuthenticator::new("proxy.example.org")?;
+        // a handshake which did not complete yet
+        *auth.cx.lock().unwrap() = Some(spnego::Context::new("HTTP", "proxy.example.org")?);
+        let mut headers = hyper::HeaderMap::new();
+        headers.append(
+            http::header::PROXY_AUTHENTICATE,
+            http::HeaderValue::from_static("Negotiate"),
+        );
+        let error = auth.finish(&headers).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<super::Error>(),
+            Some(super::Error::MutualAuthenticationFailed(_))
+        ));
+
+        Ok(())
+    }
 }
@@ -42,12 +42,30 @@ impl BasicAuthenticator {
     }
 }
 
+/// Login and password of a .netrc entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub login: String,
+    pub password: String,
+}
+
+impl Credentials {
+    /// Generate the `Basic base64("login:password")` token.
+    fn basic_token(&self) -> String {
+        let t = format!("{}:{}", self.login, self.password);
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(t)
+        )
+    }
+}
+
 #[derive(Default)]
 struct Entries {
-    /// Maps host names to base64 encoded login strings.
-    hosts: HashMap<String, String>,
+    /// Maps host names to login credentials.
+    hosts: HashMap<String, Credentials>,
     /// Default entry of .netrc file
-    default: Option<String>,
+    default: Option<Credentials>,
 }
 
 #[derive(Clone, Default)]
@@ -63,13 +81,11 @@ impl Store {
     }
 
     fn map_from_netrc(input: impl BufRead) -> Result<Entries, Error> {
-        // Generate the `Basic base64("login:password") token
-        fn make_token(login: &str, password: &str) -> String {
-            let t = format!("{login}:{password}");
-            format!(
-                "Basic {}",
-                base64::engine::general_purpose::STANDARD.encode(t)
-            )
+        fn make_credentials(machine: &netrc::Machine) -> Credentials {
+            Credentials {
+                login: machine.login.clone(),
+                password: machine.password.clone().unwrap_or_default(),
+            }
         }
 
         let netrc = netrc::Netrc::parse(input).map_err(|_| Error::NetrcParserError)?;
@@ -77,16 +93,9 @@ impl Store {
         let hosts = netrc
             .hosts
             .iter()
-            .map(|(host, machine)| {
-                (
-                    host.to_owned(),
-                    make_token(&machine.login, machine.password.as_deref().unwrap_or("")),
-                )
-            })
+            .map(|(host, machine)| (host.to_owned(), make_credentials(machine)))
             .collect();
-        let default = netrc
-            .default
-            .map(|m| make_token(&m.login, m.password.as_deref().unwrap_or("")));
+        let default = netrc.default.as_ref().map(make_credentials);
         Ok(Entries { hosts, default })
     }
 
@@ -101,12 +110,17 @@ impl Store {
     }
 
     pub(crate) fn get(&self, k: &str) -> Result<String, Error> {
+        self.credentials(k).map(|c| c.basic_token())
+    }
+
+    /// Login and password for host `k`, or of the default entry.
+    pub fn credentials(&self, k: &str) -> Result<Credentials, Error> {
         let entries = self.entries.read().unwrap();
         let default = &entries.default;
         entries
             .hosts
             .get(k)
-            .map(|k| k.to_owned())
+            .cloned()
             .or_else(|| default.clone())
             .ok_or_else(|| Error::NoEntryForHost(k.to_string()))
     }
@@ -132,6 +146,9 @@ mod tests {
         .unwrap();
         let e = netrc.get("example.org").unwrap();
         assert_eq!(e, "Basic aGVsbG86d29ybGQ=");
+        let c = netrc.credentials("example.org").unwrap();
+        assert_eq!(c.login, "hello");
+        assert_eq!(c.password, "world");
         netrc
             .update(std::io::Cursor::new(
                 "machine example.net\nlogin Hello\npassword World\n",
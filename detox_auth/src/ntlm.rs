@@ -0,0 +1,507 @@
+//! NTLMv2 proxy authentication.
+//!
+//! See [MS-NLMP](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-nlmp/).
+//! The three messages (negotiate, challenge, authenticate) are exchanged on a single connection:
+//! the first step sends the negotiate message, the `407` response of the proxy carries the
+//! challenge, which is answered with the authenticate message.
+
+use crate::netrc::Credentials;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use http::{
+    HeaderValue,
+    header::{PROXY_AUTHENTICATE, PROXY_AUTHORIZATION},
+};
+use md4::{Digest, Md4};
+use md5::Md5;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SIGNATURE: &[u8; 8] = b"NTLMSSP\0";
+const NEGOTIATE_MESSAGE: u32 = 1;
+const CHALLENGE_MESSAGE: u32 = 2;
+const AUTHENTICATE_MESSAGE: u32 = 3;
+
+const NEGOTIATE_UNICODE: u32 = 0x0000_0001;
+const NEGOTIATE_OEM: u32 = 0x0000_0002;
+const REQUEST_TARGET: u32 = 0x0000_0004;
+const NEGOTIATE_NTLM: u32 = 0x0000_0200;
+const NEGOTIATE_ALWAYS_SIGN: u32 = 0x0000_8000;
+const NEGOTIATE_EXTENDED_SESSIONSECURITY: u32 = 0x0008_0000;
+const NEGOTIATE_TARGET_INFO: u32 = 0x0080_0000;
+const NEGOTIATE_128: u32 = 0x2000_0000;
+const NEGOTIATE_56: u32 = 0x8000_0000;
+
+const NEGOTIATE_FLAGS: u32 = NEGOTIATE_UNICODE
+    | NEGOTIATE_OEM
+    | REQUEST_TARGET
+    | NEGOTIATE_NTLM
+    | NEGOTIATE_ALWAYS_SIGN
+    | NEGOTIATE_EXTENDED_SESSIONSECURITY
+    | NEGOTIATE_128
+    | NEGOTIATE_56;
+
+/// `MsvAvTimestamp` attribute of the target info.
+const AV_TIMESTAMP: u16 = 7;
+/// `MsvAvFlags` attribute of the target info, and its bit which announces a MIC.
+const AV_FLAGS: u16 = 6;
+const AV_FLAG_MIC: u32 = 0x0000_0002;
+const AV_EOL: u16 = 0;
+
+/// Offset of the MIC in the authenticate message, after the fixed fields and the version.
+const MIC_OFFSET: usize = 72;
+
+/// Seconds between 1601-01-01 (Windows FILETIME epoch) and 1970-01-01.
+const FILETIME_UNIX_OFFSET: u64 = 11_644_473_600;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid NTLM challenge message")]
+    InvalidChallenge,
+    #[error("random number generator failed: {0}")]
+    Random(String),
+}
+
+/// Challenge message (type 2) send by the proxy.
+#[derive(Debug, PartialEq, Eq)]
+struct Challenge {
+    flags: u32,
+    server_challenge: [u8; 8],
+    target_info: Vec<u8>,
+    /// The complete message, which is covered by the MIC.
+    message: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct NtlmAuthenticator {
+    user: String,
+    domain: String,
+    password: String,
+}
+
+impl NtlmAuthenticator {
+    /// Create an authenticator from netrc credentials.
+    ///
+    /// The login may contain the domain in the `DOMAIN\user` form.
+    pub fn new(credentials: Credentials) -> Self {
+        let (domain, user) = match credentials.login.split_once('\\') {
+            Some((domain, user)) => (domain.to_owned(), user.to_owned()),
+            None => (String::new(), credentials.login),
+        };
+        Self {
+            user,
+            domain,
+            password: credentials.password,
+        }
+    }
+
+    pub(crate) async fn step(
+        &self,
+        last_headers: Option<hyper::HeaderMap>,
+    ) -> crate::Result<hyper::HeaderMap> {
+        let message = match last_headers.as_ref().and_then(server_message) {
+            Some(message) => {
+                let challenge = Challenge::parse(&message)?;
+                let mut client_challenge = [0u8; 8];
+                getrandom::fill(&mut client_challenge).map_err(|e| Error::Random(e.to_string()))?;
+                self.authenticate_message(&challenge, client_challenge, now_filetime())
+            }
+            None => negotiate_message(),
+        };
+
+        let auth_str = format!(
+            "NTLM {}",
+            base64::engine::general_purpose::STANDARD.encode(message)
+        );
+        let mut headers = hyper::HeaderMap::new();
+        headers.append(
+            PROXY_AUTHORIZATION,
+            HeaderValue::from_str(&auth_str).expect("valid header value"),
+        );
+        Ok(headers)
+    }
+
+    /// Returns `true` if `headers` contain a challenge message.
+    pub(crate) fn has_challenge(&self, headers: &hyper::HeaderMap) -> bool {
+        server_message(headers).is_some()
+    }
+
+    /// `NTOWFv2` of MS-NLMP.
+    fn response_key(&self) -> [u8; 16] {
+        let nt_hash = Md4::digest(utf16le(&self.password));
+        let identity = format!("{}{}", self.user.to_uppercase(), self.domain);
+        hmac_md5(&nt_hash, &utf16le(&identity))
+    }
+
+    /// Compute the LMv2 and NTLMv2 responses, and the session base key.
+    ///
+    /// If the challenge has a timestamp, the LMv2 response is Z(24) and the target info
+    /// announces a MIC (MS-NLMP 3.1.5.1.2).
+    fn responses(
+        &self,
+        challenge: &Challenge,
+        client_challenge: [u8; 8],
+        timestamp: u64,
+    ) -> (Vec<u8>, Vec<u8>, [u8; 16]) {
+        let key = self.response_key();
+        let with_mic = challenge.timestamp().is_some();
+
+        let mut blob = vec![1, 1, 0, 0, 0, 0, 0, 0];
+        blob.extend_from_slice(&timestamp.to_le_bytes());
+        blob.extend_from_slice(&client_challenge);
+        blob.extend_from_slice(&[0; 4]);
+        if with_mic {
+            blob.extend_from_slice(&challenge.target_info_with_mic());
+        } else {
+            blob.extend_from_slice(&challenge.target_info);
+        }
+        blob.extend_from_slice(&[0; 4]);
+
+        let mut data = challenge.server_challenge.to_vec();
+        data.extend_from_slice(&blob);
+        let nt_proof = hmac_md5(&key, &data);
+        let session_key = hmac_md5(&key, &nt_proof);
+        let mut nt_response = nt_proof.to_vec();
+        nt_response.extend_from_slice(&blob);
+
+        let lm_response = if with_mic {
+            vec![0; 24]
+        } else {
+            let mut data = challenge.server_challenge.to_vec();
+            data.extend_from_slice(&client_challenge);
+            let mut lm_response = hmac_md5(&key, &data).to_vec();
+            lm_response.extend_from_slice(&client_challenge);
+            lm_response
+        };
+
+        (lm_response, nt_response, session_key)
+    }
+
+    fn authenticate_message(
+        &self,
+        challenge: &Challenge,
+        client_challenge: [u8; 8],
+        timestamp: u64,
+    ) -> Vec<u8> {
+        // prefer the time of the server, if it tells us
+        let with_mic = challenge.timestamp().is_some();
+        let timestamp = challenge.timestamp().unwrap_or(timestamp);
+        let (lm_response, nt_response, session_key) =
+            self.responses(challenge, client_challenge, timestamp);
+        let unicode = challenge.flags & NEGOTIATE_UNICODE != 0;
+        let encode = |s: &str| {
+            if unicode {
+                utf16le(s)
+            } else {
+                s.as_bytes().to_vec()
+            }
+        };
+        let flags = (challenge.flags & NEGOTIATE_FLAGS & !NEGOTIATE_OEM)
+            | if unicode { 0 } else { NEGOTIATE_OEM };
+
+        let fields = [
+            lm_response,
+            nt_response,
+            encode(&self.domain),
+            encode(&self.user),
+            Vec::new(), // workstation
+            Vec::new(), // encrypted random session key
+        ];
+        // the version (zero, not negotiated) and the MIC follow the flags
+        let header_len = if with_mic { MIC_OFFSET + 16 } else { 64 };
+        let mut header = Vec::with_capacity(header_len);
+        let mut payload = Vec::new();
+        header.extend_from_slice(SIGNATURE);
+        header.extend_from_slice(&AUTHENTICATE_MESSAGE.to_le_bytes());
+        for field in &fields {
+            let offset = (header_len + payload.len()) as u32;
+            header.extend_from_slice(&(field.len() as u16).to_le_bytes());
+            header.extend_from_slice(&(field.len() as u16).to_le_bytes());
+            header.extend_from_slice(&offset.to_le_bytes());
+            payload.extend_from_slice(field);
+        }
+        header.extend_from_slice(&flags.to_le_bytes());
+        header.resize(header_len, 0);
+        header.extend_from_slice(&payload);
+        if with_mic {
+            // without key exchange, the exported session key is the session base key
+            let mut data = negotiate_message();
+            data.extend_from_slice(&challenge.message);
+            data.extend_from_slice(&header);
+            let mic = hmac_md5(&session_key, &data);
+            header[MIC_OFFSET..MIC_OFFSET + 16].copy_from_slice(&mic);
+        }
+        header
+    }
+}
+
+impl std::fmt::Debug for NtlmAuthenticator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NtlmAuthenticator")
+            .field("user", &self.user)
+            .field("domain", &self.domain)
+            .finish()
+    }
+}
+
+impl Challenge {
+    fn parse(msg: &[u8]) -> Result<Self, Error> {
+        let u16_at = |i: usize| -> Result<u16, Error> {
+            msg.get(i..i + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .ok_or(Error::InvalidChallenge)
+        };
+        let u32_at = |i: usize| -> Result<u32, Error> {
+            msg.get(i..i + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or(Error::InvalidChallenge)
+        };
+
+        if msg.get(..8) != Some(SIGNATURE) || u32_at(8)? != CHALLENGE_MESSAGE {
+            return Err(Error::InvalidChallenge);
+        }
+        let flags = u32_at(20)?;
+        let server_challenge = msg
+            .get(24..32)
+            .and_then(|c| c.try_into().ok())
+            .ok_or(Error::InvalidChallenge)?;
+        let target_info = if flags & NEGOTIATE_TARGET_INFO != 0 {
+            let len = u16_at(40)? as usize;
+            let offset = u32_at(44)? as usize;
+            msg.get(offset..offset + len)
+                .ok_or(Error::InvalidChallenge)?
+                .to_vec()
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            flags,
+            server_challenge,
+            target_info,
+            message: msg.to_vec(),
+        })
+    }
+
+    /// The attributes (`AV_PAIR`) of the target info, without the terminating `MsvAvEOL`.
+    fn av_pairs(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        let mut info = self.target_info.as_slice();
+        std::iter::from_fn(move || {
+            let id = u16::from_le_bytes(info.get(..2)?.try_into().ok()?);
+            let len = u16::from_le_bytes(info.get(2..4)?.try_into().ok()?) as usize;
+            let value = info.get(4..4 + len)?;
+            info = &info[4 + len..];
+            (id != AV_EOL).then_some((id, value))
+        })
+    }
+
+    /// Value of the `MsvAvTimestamp` attribute, if present.
+    fn timestamp(&self) -> Option<u64> {
+        self.av_pairs()
+            .find(|(id, _)| *id == AV_TIMESTAMP)
+            .and_then(|(_, value)| value.try_into().ok().map(u64::from_le_bytes))
+    }
+
+    /// The target info with the MIC bit set in the `MsvAvFlags` attribute.
+    fn target_info_with_mic(&self) -> Vec<u8> {
+        let mut info = Vec::with_capacity(self.target_info.len() + 8);
+        let mut push = |id: u16, value: &[u8]| {
+            info.extend_from_slice(&id.to_le_bytes());
+            info.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            info.extend_from_slice(value);
+        };
+        let mut has_flags = false;
+        for (id, value) in self.av_pairs() {
+            match (id, <[u8; 4]>::try_from(value)) {
+                (AV_FLAGS, Ok(flags)) => {
+                    has_flags = true;
+                    push(id, &(u32::from_le_bytes(flags) | AV_FLAG_MIC).to_le_bytes());
+                }
+                _ => push(id, value),
+            }
+        }
+        if !has_flags {
+            push(AV_FLAGS, &AV_FLAG_MIC.to_le_bytes());
+        }
+        push(AV_EOL, &[]);
+        info
+    }
+}
+
+fn negotiate_message() -> Vec<u8> {
+    let mut msg = Vec::with_capacity(32);
+    msg.extend_from_slice(SIGNATURE);
+    msg.extend_from_slice(&NEGOTIATE_MESSAGE.to_le_bytes());
+    msg.extend_from_slice(&NEGOTIATE_FLAGS.to_le_bytes());
+    // empty domain and workstation fields
+    msg.extend_from_slice(&[0; 16]);
+    msg
+}
+
+fn hmac_md5(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut mac = Hmac::<Md5>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn utf16le(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// Current time as Windows FILETIME (100 ns intervals since 1601-01-01).
+fn now_filetime() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (since_epoch.as_secs() + FILETIME_UNIX_OFFSET) * 10_000_000
+        + u64::from(since_epoch.subsec_nanos() / 100)
+}
+
+// Extract the message from "Proxy-Authenticate: NTLM <base64>" header value
+fn server_message(last_headers: &hyper::HeaderMap) -> Option<Vec<u8>> {
+    last_headers
+        .get_all(PROXY_AUTHENTICATE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|s| s.strip_prefix("NTLM "))
+        .find_map(|x| {
+            base64::engine::general_purpose::STANDARD
+                .decode(x.trim())
+                .ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        let s = s.replace(' ', "");
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn authenticator() -> NtlmAuthenticator {
+        NtlmAuthenticator::new(Credentials {
+            login: String::from("Domain\\User"),
+            password: String::from("Password"),
+        })
+    }
+
+    // target info (NetBIOS domain "Domain" and computer "Server") of MS-NLMP 4.2.4
+    const TARGET_INFO: &str =
+        "02000c0044006f006d00610069006e0001000c00530065007200760065007200 00000000";
+    // the same target info with a `MsvAvTimestamp` attribute
+    const TARGET_INFO_TIMESTAMP: &str = "02000c0044006f006d00610069006e0001000c00530065007200760065007200 \
+         07000800 0090d336b734c301 00000000";
+
+    fn challenge_message(target_info: &str) -> Vec<u8> {
+        let target_info = unhex(target_info);
+        let flags = NEGOTIATE_UNICODE | NEGOTIATE_NTLM | NEGOTIATE_TARGET_INFO;
+        let mut msg = SIGNATURE.to_vec();
+        msg.extend_from_slice(&CHALLENGE_MESSAGE.to_le_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0, 48, 0, 0, 0]); // empty target name
+        msg.extend_from_slice(&flags.to_le_bytes());
+        msg.extend_from_slice(&unhex("0123456789abcdef"));
+        msg.extend_from_slice(&[0; 8]);
+        msg.extend_from_slice(&(target_info.len() as u16).to_le_bytes());
+        msg.extend_from_slice(&(target_info.len() as u16).to_le_bytes());
+        msg.extend_from_slice(&48u32.to_le_bytes());
+        msg.extend_from_slice(&target_info);
+        msg
+    }
+
+    #[test]
+    fn parse_challenge() {
+        let challenge = Challenge::parse(&challenge_message(TARGET_INFO)).unwrap();
+        assert_eq!(
+            challenge.server_challenge.to_vec(),
+            unhex("0123456789abcdef")
+        );
+        assert_eq!(challenge.target_info, unhex(TARGET_INFO));
+        assert_eq!(challenge.timestamp(), None);
+        assert!(Challenge::parse(b"NTLMSSP\0\x02\0\0\0").is_err());
+        assert!(Challenge::parse(&negotiate_message()).is_err());
+    }
+
+    #[test]
+    fn ntlmv2_response() {
+        // test vectors of MS-NLMP 4.2.4
+        let auth = authenticator();
+        assert_eq!(
+            auth.response_key().to_vec(),
+            unhex("0c868a403bfd7a93a3001ef22ef02e3f")
+        );
+        let challenge = Challenge::parse(&challenge_message(TARGET_INFO)).unwrap();
+        let (lm, nt, _) = auth.responses(&challenge, [0xaa; 8], 0);
+        assert_eq!(
+            lm,
+            unhex("86c35097ac9cec102554764a57cccc19aaaaaaaaaaaaaaaa")
+        );
+        assert_eq!(nt[..16], unhex("68cd0ab851e51c96aabc927bebef6a1c"));
+    }
+
+    #[test]
+    fn ntlmv2_response_with_timestamp() {
+        let auth = authenticator();
+        let challenge = Challenge::parse(&challenge_message(TARGET_INFO_TIMESTAMP)).unwrap();
+        assert_eq!(challenge.timestamp(), Some(0x01c334b736d39000));
+        let msg = auth.authenticate_message(&challenge, [0xaa; 8], 0);
+        let field = |i: usize| {
+            let len = u16::from_le_bytes([msg[i], msg[i + 1]]) as usize;
+            let offset = u32::from_le_bytes([msg[i + 4], msg[i + 5], msg[i + 6], msg[i + 7]]);
+            &msg[offset as usize..offset as usize + len]
+        };
+        // Z(24) instead of the LMv2 response
+        assert_eq!(field(12), [0; 24]);
+        // MsvAvFlags with the MIC bit is added before MsvAvEOL
+        let nt = field(20);
+        assert_eq!(
+            nt[nt.len() - 16..],
+            unhex("06000400 02000000 00000000 00000000")
+        );
+        assert_eq!(nt[..16], unhex("dcfcd060b4aeb71ecbacc63513782dbf"));
+        assert_eq!(
+            msg[MIC_OFFSET..MIC_OFFSET + 16],
+            unhex("ad638d5ac791f306a8a3120df6124964")
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let auth = authenticator();
+        let headers = auth.step(None).await?;
+        let negotiate = headers.get(PROXY_AUTHORIZATION).unwrap().to_str()?;
+        assert_eq!(
+            negotiate,
+            format!(
+                "NTLM {}",
+                base64::engine::general_purpose::STANDARD.encode(negotiate_message())
+            )
+        );
+
+        let mut last_headers = hyper::HeaderMap::new();
+        last_headers.append(PROXY_AUTHENTICATE, HeaderValue::from_static("NTLM"));
+        assert!(!auth.has_challenge(&last_headers));
+        last_headers.append(
+            PROXY_AUTHENTICATE,
+            HeaderValue::from_str(&format!(
+                "NTLM {}",
+                base64::engine::general_purpose::STANDARD.encode(challenge_message(TARGET_INFO))
+            ))?,
+        );
+        assert!(auth.has_challenge(&last_headers));
+
+        let headers = auth.step(Some(last_headers)).await?;
+        let authenticate = headers.get(PROXY_AUTHORIZATION).unwrap().to_str()?;
+        let msg = base64::engine::general_purpose::STANDARD
+            .decode(authenticate.strip_prefix("NTLM ").unwrap())?;
+        assert_eq!(&msg[..8], SIGNATURE);
+        assert_eq!(msg[8..12], AUTHENTICATE_MESSAGE.to_le_bytes());
+        // user name field
+        let len = u16::from_le_bytes([msg[36], msg[37]]) as usize;
+        let offset = u32::from_le_bytes([msg[40], msg[41], msg[42], msg[43]]) as usize;
+        assert_eq!(msg[offset..offset + len], utf16le("User"));
+        Ok(())
+    }
+}
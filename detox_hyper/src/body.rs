@@ -0,0 +1,261 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{self, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, Request};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{BodyExt, Empty, Full, combinators::BoxBody};
+
+/// Request bodies which can be send more than once.
+pub trait Replay: Sized {
+    /// A copy of the body which can be send again, `None` if the body cannot be replayed.
+    fn replay(&self) -> Option<Self>;
+}
+
+impl<D: Buf> Replay for Empty<D> {
+    fn replay(&self) -> Option<Self> {
+        Some(Empty::new())
+    }
+}
+
+impl<D: Buf + Clone> Replay for Full<D> {
+    fn replay(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+/// Copy of a request with a replayable body.
+pub fn replay_request<B: Replay>(req: &Request<B>) -> Option<Request<B>> {
+    let mut copy = Request::new(req.body().replay()?);
+    *copy.method_mut() = req.method().clone();
+    *copy.uri_mut() = req.uri().clone();
+    *copy.version_mut() = req.version();
+    *copy.headers_mut() = req.headers().clone();
+    Some(copy)
+}
+
+/// Request body which is kept in memory up to a size limit, such that it can be replayed.
+///
+/// Bodies larger than the limit are streamed and cannot be replayed.
+pub struct BufferedBody {
+    kind: Kind,
+}
+
+enum Kind {
+    Buffered {
+        data: Bytes,
+        trailers: Option<HeaderMap>,
+        done: bool,
+    },
+    Streaming {
+        head: VecDeque<Bytes>,
+        tail: BoxBody<Bytes, hyper::Error>,
+    },
+}
+
+impl BufferedBody {
+    pub fn empty() -> Self {
+        Self::full(Bytes::new())
+    }
+
+    pub fn full(data: impl Into<Bytes>) -> Self {
+        Self {
+            kind: Kind::Buffered {
+                data: data.into(),
+                trailers: None,
+                done: false,
+            },
+        }
+    }
+
+    /// Read `body` into memory as long as it does not exceed `limit` bytes.
+    ///
+    /// When the limit is exceeded, the data read so far is kept and the remaining body is
+    /// streamed.
+    pub async fn buffer<B>(mut body: B, limit: usize) -> Result<Self, hyper::Error>
+    where
+        B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + Unpin + 'static,
+    {
+        let mut head = VecDeque::new();
+        let mut trailers = None;
+        let mut size = 0usize;
+        let too_large = body.size_hint().lower() > limit as u64;
+        while !too_large && let Some(frame) = body.frame().await {
+            match frame?.into_data() {
+                Ok(data) => {
+                    size += data.len();
+                    head.push_back(data);
+                    if size > limit {
+                        break;
+                    }
+                }
+                Err(frame) => trailers = frame.into_trailers().ok(),
+            }
+        }
+        if too_large || size > limit {
+            return Ok(Self {
+                kind: Kind::Streaming {
+                    head,
+                    tail: body.boxed(),
+                },
+            });
+        }
+        let data = if head.len() == 1 {
+            head.pop_front().unwrap_or_default()
+        } else {
+            head.into_iter().flatten().collect()
+        };
+        Ok(Self {
+            kind: Kind::Buffered {
+                data,
+                trailers,
+                done: false,
+            },
+        })
+    }
+
+    /// Stream `body` without keeping it in memory, it cannot be replayed.
+    pub fn streaming<B>(body: B) -> Self
+    where
+        B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static,
+    {
+        Self {
+            kind: Kind::Streaming {
+                head: VecDeque::new(),
+                tail: body.boxed(),
+            },
+        }
+    }
+
+    /// Returns `true` if the whole body is kept in memory.
+    pub fn is_replayable(&self) -> bool {
+        matches!(self.kind, Kind::Buffered { .. })
+    }
+}
+
+impl Replay for BufferedBody {
+    fn replay(&self) -> Option<Self> {
+        match &self.kind {
+            Kind::Buffered { data, trailers, .. } => Some(Self {
+                kind: Kind::Buffered {
+                    data: data.clone(),
+                    trailers: trailers.clone(),
+                    done: false,
+                },
+            }),
+            Kind::Streaming { .. } => None,
+        }
+    }
+}
+
+impl Default for BufferedBody {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Body for BufferedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match &mut self.get_mut().kind {
+            Kind::Buffered {
+                data,
+                trailers,
+                done,
+            } => {
+                if !data.is_empty() {
+                    return Poll::Ready(Some(Ok(Frame::data(std::mem::take(data)))));
+                }
+                *done = true;
+                Poll::Ready(trailers.take().map(|t| Ok(Frame::trailers(t))))
+            }
+            Kind::Streaming { head, tail } => match head.pop_front() {
+                Some(data) => Poll::Ready(Some(Ok(Frame::data(data)))),
+                None => Pin::new(tail).poll_frame(cx),
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.kind {
+            Kind::Buffered {
+                data,
+                trailers,
+                done,
+            } => *done || (data.is_empty() && trailers.is_none()),
+            Kind::Streaming { head, tail } => head.is_empty() && tail.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.kind {
+            Kind::Buffered { data, .. } => SizeHint::with_exact(data.len() as u64),
+            Kind::Streaming { head, tail } => {
+                let head = head.iter().map(|b| b.len() as u64).sum::<u64>();
+                let tail = tail.size_hint();
+                let mut hint = SizeHint::new();
+                hint.set_lower(head + tail.lower());
+                if let Some(upper) = tail.upper() {
+                    hint.set_upper(head + upper);
+                }
+                hint
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(chunks: &[&'static str]) -> BoxBody<Bytes, hyper::Error> {
+        let frames = chunks
+            .iter()
+            .map(|c| Ok::<_, hyper::Error>(Frame::data(Bytes::from_static(c.as_bytes()))))
+            .collect::<Vec<_>>();
+        http_body_util::StreamBody::new(futures_util::stream::iter(frames)).boxed()
+    }
+
+    #[tokio::test]
+    async fn buffered_body_replay() {
+        let body = BufferedBody::buffer(body(&["hello ", "world"]), 16)
+            .await
+            .unwrap();
+        assert!(body.is_replayable());
+        let copy = body.replay().unwrap();
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "hello world");
+        assert_eq!(copy.collect().await.unwrap().to_bytes(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn buffered_body_too_large() {
+        let body = BufferedBody::buffer(body(&["hello ", "world"]), 4)
+            .await
+            .unwrap();
+        assert!(!body.is_replayable());
+        assert!(body.replay().is_none());
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn buffered_body_streaming() {
+        let body = BufferedBody::streaming(body(&["hello ", "world"]));
+        assert!(!body.is_replayable());
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn buffered_body_empty() {
+        let body = BufferedBody::buffer(body(&[]), 0).await.unwrap();
+        assert!(body.is_replayable());
+        assert!(body.is_end_stream());
+    }
+}
@@ -8,15 +8,13 @@ use std::{
 use bytes::Bytes;
 use detox_auth::{Authenticator, AuthenticatorFactory};
 use detox_futures::FutureExt as _;
-use detox_net::{HostAndPort, TcpKeepAlive};
+use detox_net::{HostAndPort, TcpKeepAlive, dns::Resolver, socks};
 use futures_util::{FutureExt as _, future::BoxFuture};
 use http::{
-    HeaderValue, Request, Response, Uri,
-    header::{CONNECTION, HOST},
-    uri::PathAndQuery,
+    HeaderMap, HeaderValue, Request, Response, StatusCode, Uri, header::HOST, uri::PathAndQuery,
 };
 use http_body::Body;
-use http_body_util::Empty;
+use http_body_util::{BodyExt, Empty};
 use hyper::{
     body::Incoming as IncomingBody,
     client::conn::http1::{self, Builder},
@@ -34,7 +32,15 @@ use tokio_rustls::{TlsConnector, client::TlsStream};
 use tracing::field::debug;
 use tracing_attributes::instrument;
 
+use crate::body::{Replay, replay_request};
+use crate::h2;
+
 const AUTH_TIMEOUT: Duration = Duration::from_secs(2);
+/// Maximum number of requests send for one multi-round authentication handshake.
+pub(crate) const MAX_AUTH_ROUNDS: usize = 5;
+/// ALPN protocol IDs offered to HTTPS proxies.
+const ALPN_H2: &[u8] = b"h2";
+const ALPN_HTTP11: &[u8] = b"http/1.1";
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -70,6 +76,7 @@ enum AnyStream {
     HttpProxy(#[pin] TcpStream),
     HttpsProxy(#[pin] TlsStream<TcpStream>),
     HttpTunnel(#[pin] TokioIo<Upgraded>),
+    Socks(#[pin] TcpStream),
 }
 
 // #[derive(Debug)]
@@ -86,6 +93,8 @@ pub struct Connection {
 pub struct ConnectionBuilder {
     kind: ConnectionKind,
     tcp_keepalive: Option<TcpKeepAlive>,
+    h2_sessions: Option<h2::Sessions>,
+    resolver: Option<Resolver>,
 }
 
 #[derive(Debug)]
@@ -99,6 +108,7 @@ pub enum ConnectionKind {
         AuthenticatorFactory,
         HostAndPort,
     ),
+    Socks(Proxy, AuthenticatorFactory, HostAndPort),
 }
 
 pub struct SendRequest<B>
@@ -106,10 +116,11 @@ where
     B: Body + 'static,
 {
     sender: http1::SendRequest<B>,
-    conn: http1::Connection<TokioIo<AnyStream>, B>,
     host: Option<String>,
     proxy: ProxyOrDirect,
     auth: Option<Authenticator>,
+    /// A connection based authentication handshake completed on this connection.
+    authenticated: bool,
 }
 
 impl Connection {
@@ -117,6 +128,8 @@ impl Connection {
         ConnectionBuilder {
             kind: ConnectionKind::Http(dst),
             tcp_keepalive: Default::default(),
+            h2_sessions: None,
+            resolver: None,
         }
     }
 
@@ -124,6 +137,8 @@ impl Connection {
         ConnectionBuilder {
             kind: ConnectionKind::Https(dst, tls_config),
             tcp_keepalive: Default::default(),
+            h2_sessions: None,
+            resolver: None,
         }
     }
 
@@ -135,6 +150,8 @@ impl Connection {
         ConnectionBuilder {
             kind: ConnectionKind::HttpProxy(proxy, tls_config, auth),
             tcp_keepalive: Default::default(),
+            h2_sessions: None,
+            resolver: None,
         }
     }
 
@@ -147,6 +164,17 @@ impl Connection {
         ConnectionBuilder {
             kind: ConnectionKind::HttpTunnel(proxy, tls_config, auth, dst),
             tcp_keepalive: Default::default(),
+            h2_sessions: None,
+            resolver: None,
+        }
+    }
+
+    pub fn socks(proxy: Proxy, auth: AuthenticatorFactory, dst: HostAndPort) -> ConnectionBuilder {
+        ConnectionBuilder {
+            kind: ConnectionKind::Socks(proxy, auth, dst),
+            tcp_keepalive: Default::default(),
+            h2_sessions: None,
+            resolver: None,
         }
     }
 
@@ -157,12 +185,14 @@ impl Connection {
             AnyStream::HttpProxy(_) => true,
             AnyStream::HttpsProxy(_) => true,
             AnyStream::HttpTunnel(_) => false,
+            AnyStream::Socks(_) => false,
         }
     }
 
+    /// Perform the HTTP/1.1 handshake and drive the connection in a background task.
     pub async fn handshake<B>(self) -> Result<SendRequest<B>, hyper::Error>
     where
-        B: Body + 'static,
+        B: Body + Send + 'static,
         B::Data: Send,
         B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
     {
@@ -177,12 +207,19 @@ impl Connection {
             .title_case_headers(true)
             .handshake(TokioIo::new(inner))
             .await?;
+
+        tokio::spawn(async move {
+            if let Err(cause) = conn.await {
+                tracing::error!(%cause, "connection error");
+            }
+        });
+
         Ok(SendRequest {
             sender,
-            conn,
             host,
             proxy,
             auth,
+            authenticated: false,
         })
     }
 
@@ -206,13 +243,31 @@ impl ConnectionBuilder {
             ConnectionKind::Https(_, _) => ProxyOrDirect::Direct,
             ConnectionKind::HttpProxy(p, _, _) => ProxyOrDirect::Proxy(p.clone()),
             ConnectionKind::HttpTunnel(p, _, _, _) => ProxyOrDirect::Proxy(p.clone()),
+            ConnectionKind::Socks(p, _, _) => ProxyOrDirect::Proxy(p.clone()),
         }
     }
 
     pub fn with_tcp_keepalive(self, ka: TcpKeepAlive) -> Self {
         Self {
-            kind: self.kind,
             tcp_keepalive: Some(ka),
+            ..self
+        }
+    }
+
+    /// Tunnel via HTTP/2 to HTTPS proxies which support it, sharing one session per proxy.
+    pub fn with_h2_sessions(self, sessions: h2::Sessions) -> Self {
+        Self {
+            h2_sessions: Some(sessions),
+            ..self
+        }
+    }
+
+    /// Resolve the host name of direct connections with `resolver` instead of the resolver of
+    /// the operating system.
+    pub fn with_resolver(self, resolver: Resolver) -> Self {
+        Self {
+            resolver: Some(resolver),
+            ..self
         }
     }
 }
@@ -261,7 +316,7 @@ impl std::future::IntoFuture for ConnectionBuilder {
         use ConnectionKind::*;
         match self.kind {
             Http(dst) => async move {
-                let stream = TcpStream::connect(dst.to_pair()).await?;
+                let stream = connect_direct(&dst, self.resolver.as_ref()).await?;
                 stream.set_nodelay(true)?;
                 if let Some(ka) = self.tcp_keepalive {
                     ka.apply(&stream)?;
@@ -282,7 +337,7 @@ impl std::future::IntoFuture for ConnectionBuilder {
                     )
                 })?;
                 let connector = TlsConnector::from(tls_config);
-                let stream = TcpStream::connect(dst.to_pair()).await?;
+                let stream = connect_direct(&dst, self.resolver.as_ref()).await?;
                 stream.set_nodelay(true)?;
                 if let Some(ka) = self.tcp_keepalive {
                     ka.apply(&stream)?;
@@ -343,6 +398,13 @@ impl std::future::IntoFuture for ConnectionBuilder {
                     })
                 }
                 .boxed(),
+                Proxy::Socks4(_) | Proxy::Socks5(_) => async move {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("SOCKS proxy {proxy} requires a destination"),
+                    ))
+                }
+                .boxed(),
             },
             HttpTunnel(proxy, tls_config, auth, dst) => match proxy {
                 Proxy::Http(proxy) => async move {
@@ -361,20 +423,55 @@ impl std::future::IntoFuture for ConnectionBuilder {
                 }
                 .boxed(),
                 Proxy::Https(proxy) => async move {
-                    let stream = TcpStream::connect(proxy.to_pair()).await?;
-                    stream.set_nodelay(true)?;
-                    if let Some(ka) = self.tcp_keepalive {
-                        ka.apply(&stream)?;
+                    let mut session = None;
+                    let mut first_connect = None;
+                    if let Some(sessions) = &self.h2_sessions {
+                        session = sessions.get(&proxy);
+                        if session.is_none() {
+                            first_connect = Some(sessions.first_connect(&proxy).await);
+                            // a concurrent connect may have established the session meanwhile
+                            session = sessions.get(&proxy);
+                        }
                     }
-                    let domain = ServerName::try_from(proxy.host()).map_err(|e| {
-                        std::io::Error::new(
-                            std::io::ErrorKind::InvalidInput,
-                            format!("Invalid domain name: {e}"),
-                        )
-                    })?;
-                    let tls = TlsConnector::from(tls_config);
-                    let stream = tls.connect(domain.to_owned(), stream).await?;
-                    let stream = http_connect(stream, &proxy, &auth, &dst).await?;
+                    let stream = if let Some(session) = session {
+                        drop(first_connect);
+                        tracing::debug!("reuse HTTP/2 session");
+                        session.connect(&proxy, &auth, &dst).await?
+                    } else {
+                        let stream = TcpStream::connect(proxy.to_pair()).await?;
+                        stream.set_nodelay(true)?;
+                        if let Some(ka) = self.tcp_keepalive {
+                            ka.apply(&stream)?;
+                        }
+                        let domain = ServerName::try_from(proxy.host()).map_err(|e| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::InvalidInput,
+                                format!("Invalid domain name: {e}"),
+                            )
+                        })?;
+                        let tls_config = if self.h2_sessions.is_some() {
+                            let mut config = (*tls_config).clone();
+                            config.alpn_protocols = vec![ALPN_H2.to_vec(), ALPN_HTTP11.to_vec()];
+                            Arc::new(config)
+                        } else {
+                            tls_config
+                        };
+                        let tls = TlsConnector::from(tls_config);
+                        let stream = tls.connect(domain.to_owned(), stream).await?;
+                        match self.h2_sessions {
+                            Some(sessions)
+                                if stream.get_ref().1.alpn_protocol() == Some(ALPN_H2) =>
+                            {
+                                let session = sessions.handshake(&proxy, stream).await?;
+                                drop(first_connect);
+                                session.connect(&proxy, &auth, &dst).await?
+                            }
+                            _ => {
+                                drop(first_connect);
+                                http_connect(stream, &proxy, &auth, &dst).await?
+                            }
+                        }
+                    };
 
                     Ok(Connection {
                         inner: AnyStream::HttpTunnel(TokioIo::new(stream)),
@@ -384,11 +481,73 @@ impl std::future::IntoFuture for ConnectionBuilder {
                     })
                 }
                 .boxed(),
+                Proxy::Socks4(_) | Proxy::Socks5(_) => {
+                    socks_connect(proxy, auth, dst, self.tcp_keepalive).boxed()
+                }
             },
+            Socks(proxy, auth, dst) => socks_connect(proxy, auth, dst, self.tcp_keepalive).boxed(),
         }
     }
 }
 
+/// Connect to `dst` directly, trying all addresses returned by `resolver` in turn.
+async fn connect_direct(
+    dst: &HostAndPort,
+    resolver: Option<&Resolver>,
+) -> std::io::Result<TcpStream> {
+    let Some(resolver) = resolver else {
+        return TcpStream::connect(dst.to_pair()).await;
+    };
+    let mut error = std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("no address found for {}", dst.host()),
+    );
+    for ip in resolver.lookup(dst.host()).await? {
+        match TcpStream::connect((ip, dst.port())).await {
+            Ok(stream) => return Ok(stream),
+            Err(cause) => error = cause,
+        }
+    }
+    Err(error)
+}
+
+/// Establish a tunnel to `dst` via a SOCKS4a or SOCKS5 proxy.
+///
+/// Credentials are taken from the `auth` factory, if it has any for the proxy.
+async fn socks_connect(
+    proxy: Proxy,
+    auth: AuthenticatorFactory,
+    dst: HostAndPort,
+    tcp_keepalive: Option<TcpKeepAlive>,
+) -> std::io::Result<Connection> {
+    let endpoint = proxy.endpoint();
+    let credentials = auth
+        .credentials(endpoint.host())
+        .map(|c| socks::Credentials::new(c.login, c.password));
+    let mut stream = TcpStream::connect(endpoint.to_pair()).await?;
+    stream.set_nodelay(true)?;
+    if let Some(ka) = tcp_keepalive {
+        ka.apply(&stream)?;
+    }
+    match proxy {
+        Proxy::Socks4(_) => socks::socks4a_connect(&mut stream, &dst, credentials.as_ref()).await,
+        Proxy::Socks5(_) => socks::socks5_connect(&mut stream, &dst, credentials.as_ref()).await,
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("not a SOCKS proxy: {proxy}"),
+            ));
+        }
+    }
+    .map_err(|e| std::io::Error::other(format!("SOCKS error from {proxy} for {dst}: {e}")))?;
+    Ok(Connection {
+        inner: AnyStream::Socks(stream),
+        host: Some(dst.host().to_owned()),
+        proxy: ProxyOrDirect::Proxy(proxy),
+        auth: None,
+    })
+}
+
 impl AsyncWrite for AnyStream {
     fn poll_write(
         self: Pin<&mut Self>,
@@ -401,6 +560,7 @@ impl AsyncWrite for AnyStream {
             StreamProj::HttpProxy(s) => s.poll_write(cx, buf),
             StreamProj::HttpsProxy(s) => s.poll_write(cx, buf),
             StreamProj::HttpTunnel(s) => s.poll_write(cx, buf),
+            StreamProj::Socks(s) => s.poll_write(cx, buf),
         }
     }
 
@@ -411,6 +571,7 @@ impl AsyncWrite for AnyStream {
             StreamProj::HttpProxy(s) => s.poll_flush(cx),
             StreamProj::HttpsProxy(s) => s.poll_flush(cx),
             StreamProj::HttpTunnel(s) => s.poll_flush(cx),
+            StreamProj::Socks(s) => s.poll_flush(cx),
         }
     }
 
@@ -424,6 +585,7 @@ impl AsyncWrite for AnyStream {
             StreamProj::HttpProxy(s) => s.poll_shutdown(cx),
             StreamProj::HttpsProxy(s) => s.poll_shutdown(cx),
             StreamProj::HttpTunnel(s) => s.poll_shutdown(cx),
+            StreamProj::Socks(s) => s.poll_shutdown(cx),
         }
     }
 }
@@ -440,6 +602,7 @@ impl AsyncRead for AnyStream {
             StreamProj::HttpProxy(s) => s.poll_read(cx, buf),
             StreamProj::HttpsProxy(s) => s.poll_read(cx, buf),
             StreamProj::HttpTunnel(s) => s.poll_read(cx, buf),
+            StreamProj::Socks(s) => s.poll_read(cx, buf),
         }
     }
 }
@@ -450,34 +613,48 @@ where
     B::Data: Send,
     B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
 {
+    /// Returns `true` if the connection can accept another request right now.
+    pub fn is_ready(&self) -> bool {
+        self.sender.is_ready()
+    }
+
+    /// Returns `true` if the connection was closed.
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Wait until the connection is able to send another request.
+    pub async fn ready(&mut self) -> Result<(), Error> {
+        Ok(self.sender.ready().await?)
+    }
+
+    pub fn proxy(&self) -> &ProxyOrDirect {
+        &self.proxy
+    }
+
+    /// Returns `true` if requests are authenticated at the proxy, such that they may have to be
+    /// send again.
+    pub fn has_auth(&self) -> bool {
+        !matches!(self.auth, None | Some(Authenticator::None))
+    }
+
+    /// Send the request, repeating it while the proxy continues the authentication handshake.
+    ///
+    /// Once a connection based handshake (NTLM, Negotiate) completed, later requests are send
+    /// without credentials; a new handshake is only started when the proxy answers with `407`.
+    /// Only requests with a replayable body are send again.
     #[instrument(level = "debug", skip(self, req), err, fields(duration))]
     pub async fn send_request(
-        self,
+        &mut self,
         mut req: Request<B>,
-    ) -> std::result::Result<Response<IncomingBody>, Error> {
-        let SendRequest {
-            mut sender,
-            conn,
-            host,
-            proxy,
-            auth,
-        } = self;
-
-        match proxy {
-            ProxyOrDirect::Proxy(p) => {
-                if let Some(auth) = auth {
-                    let start = Instant::now();
-                    let auth_headers = auth.step(None).timeout(AUTH_TIMEOUT).await;
-                    tracing::Span::current().record("duration", debug(&start.elapsed()));
-                    tracing::debug!("auth");
-                    let auth_headers = auth_headers.map_err(|_| Error::AuthenticationTimeout)?;
-                    let auth_headers = auth_headers
-                        .map_err(|e| Error::AuthenticationFailed(p.endpoint().clone(), e))?;
-                    req.headers_mut().extend(auth_headers);
-                }
-            }
-            ProxyOrDirect::Direct => {
-                // if not proxied, remove the authority part of the URI
+    ) -> std::result::Result<Response<IncomingBody>, Error>
+    where
+        B: Replay,
+    {
+        let proxy = match &self.proxy {
+            ProxyOrDirect::Proxy(p) if !p.is_socks() => Some(p.endpoint().clone()),
+            _ => {
+                // if not proxied (or tunneled via SOCKS), remove the authority part of the URI
                 if req.method() != http::Method::CONNECT {
                     let uri = req
                         .uri()
@@ -486,29 +663,85 @@ where
                         .unwrap_or_else(|| PathAndQuery::from_static("/"));
                     *req.uri_mut() = Uri::from(uri);
                 }
+                None
             }
-        }
+        };
 
         if !req.headers().contains_key(HOST)
-            && let Some(host) = &host
+            && let Some(host) = &self.host
         {
             req.headers_mut().insert(HOST, HeaderValue::from_str(host)?);
         }
 
-        // We do not support connection pooling as of now.
-        req.headers_mut()
-            .insert(CONNECTION, HeaderValue::from_static("close"));
-
-        tokio::spawn(async move {
-            if let Err(cause) = conn.await {
-                tracing::error!(%cause, "connection error");
+        let (Some(proxy), Some(auth)) = (proxy, self.auth.clone()) else {
+            return Ok(self.sender.send_request(req).await?);
+        };
+
+        let mut last_headers = None;
+        let mut round = 0;
+        loop {
+            round += 1;
+            let authenticated = std::mem::take(&mut self.authenticated);
+            let auth_headers = if authenticated {
+                HeaderMap::new()
+            } else {
+                auth_step(&auth, last_headers.take(), &proxy).await?
+            };
+            let next = replay_request(&req);
+            req.headers_mut().extend(auth_headers);
+            let response = self.sender.send_request(req).await?;
+            let status = response.status();
+            match next {
+                Some(next)
+                    if status == StatusCode::PROXY_AUTHENTICATION_REQUIRED
+                        && round < MAX_AUTH_ROUNDS
+                        && (authenticated || auth.has_challenge(response.headers())) =>
+                {
+                    tracing::debug!(round, authenticated, "continue authentication");
+                    let (parts, body) = response.into_parts();
+                    body.collect().await?;
+                    self.sender.ready().await?;
+                    // a 407 on an authenticated connection starts a new handshake
+                    last_headers = (!authenticated).then_some(parts.headers);
+                    req = next;
+                }
+                _ => {
+                    if status.is_success() {
+                        auth.finish(response.headers())
+                            .await
+                            .map_err(|e| Error::AuthenticationFailed(proxy, e))?;
+                    }
+                    self.authenticated = status != StatusCode::PROXY_AUTHENTICATION_REQUIRED
+                        && auth.is_connection_based();
+                    return Ok(response);
+                }
             }
-        });
+        }
+    }
+}
 
-        let response = sender.send_request(req).await;
+/// Perform one authentication step, limited by `AUTH_TIMEOUT`.
+pub(crate) async fn auth_step(
+    auth: &Authenticator,
+    last_headers: Option<HeaderMap>,
+    proxy: &HostAndPort,
+) -> std::result::Result<HeaderMap, Error> {
+    let start = Instant::now();
+    let auth_headers = auth.step(last_headers).timeout(AUTH_TIMEOUT).await;
+    tracing::Span::current().record("duration", debug(&start.elapsed()));
+    tracing::debug!("auth");
+    auth_headers
+        .map_err(|_| Error::AuthenticationTimeout)?
+        .map_err(|e| Error::AuthenticationFailed(proxy.clone(), e))
+}
 
-        Ok(response?)
-    }
+/// Error of a failed authentication at a proxy, which can be told apart by its
+/// [`std::io::ErrorKind::PermissionDenied`] kind.
+pub(crate) fn auth_error<E>(error: E) -> std::io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::PermissionDenied, error)
 }
 
 #[instrument(level = "debug", skip(stream, auth), err, fields(duration))]
@@ -526,34 +759,58 @@ async fn http_connect<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
         .authority(dst.to_string())
         .build()
         .map_err(|e| std::io::Error::other(format!("Invalid authority '{dst}': {e}")))?;
-    let mut request = Request::connect(dst_uri).header(HOST, dst.host());
     let auth = auth.make(proxy.host()).map_err(|e| {
         std::io::Error::other(format!("Unable to build authenticator for '{proxy}': {e}"))
     })?;
-    let start = Instant::now();
-    let auth_headers = auth.step(None).timeout(AUTH_TIMEOUT).await;
-    tracing::Span::current().record("duration", debug(&start.elapsed()));
-    tracing::debug!("auth");
-    let auth_headers =
-        auth_headers.map_err(|_| std::io::Error::other(Error::AuthenticationTimeout))?;
-
-    let auth_headers = auth_headers
-        .map_err(|e| std::io::Error::other(Error::AuthenticationFailed(proxy.clone(), e)))?;
-    if let Some(headers) = request.headers_mut() {
-        headers.extend(auth_headers)
-    }
-    let request = request.body(Empty::<Bytes>::new()).expect("empty request");
 
     let send_request = async move {
-        let response = request_sender.send_request(request).await.map_err(|e| {
-            std::io::Error::other(format!("send request error from {proxy} for {dst}: {e}"))
-        })?;
+        let mut last_headers = None;
+        let mut round = 0;
+        let response = loop {
+            round += 1;
+            let auth_headers = auth_step(&auth, last_headers.take(), proxy)
+                .await
+                .map_err(auth_error)?;
+            let mut request = Request::connect(dst_uri.clone())
+                .header(HOST, dst.host())
+                .body(Empty::<Bytes>::new())
+                .expect("empty request");
+            request.headers_mut().extend(auth_headers);
+            let response = request_sender.send_request(request).await.map_err(|e| {
+                std::io::Error::other(format!("send request error from {proxy} for {dst}: {e}"))
+            })?;
+            if response.status() == StatusCode::PROXY_AUTHENTICATION_REQUIRED
+                && round < MAX_AUTH_ROUNDS
+                && auth.has_challenge(response.headers())
+            {
+                tracing::debug!(round, "continue authentication");
+                let (parts, body) = response.into_parts();
+                body.collect()
+                    .await
+                    .and(request_sender.ready().await)
+                    .map_err(|e| {
+                        std::io::Error::other(format!(
+                            "HTTP {} from {proxy} for {dst} error: {e}",
+                            parts.status
+                        ))
+                    })?;
+                last_headers = Some(parts.headers);
+                continue;
+            }
+            break response;
+        };
         let status = response.status();
+        if status == StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+            return Err(auth_error(format!("HTTP {status} from {proxy} for {dst}")));
+        }
         if !status.is_success() {
             return Err(std::io::Error::other(format!(
                 "HTTP {status} from {proxy} for {dst}"
             )));
         }
+        auth.finish(response.headers())
+            .await
+            .map_err(|e| auth_error(Error::AuthenticationFailed(proxy.clone(), e)))?;
         hyper::upgrade::on(response).await.map_err(|e| {
             std::io::Error::other(format!("HTTP {status} from {proxy} for {dst} error: {e}"))
         })
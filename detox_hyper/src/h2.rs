@@ -0,0 +1,161 @@
+//! HTTP/2 sessions to HTTPS proxies, each `CONNECT` tunnel becomes a stream of the session.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use detox_auth::AuthenticatorFactory;
+use detox_net::HostAndPort;
+use http::{Request, StatusCode, Uri, Version};
+use http_body_util::Empty;
+use hyper::{client::conn::http2, upgrade::Upgraded};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::conn::{MAX_AUTH_ROUNDS, auth_error, auth_step};
+
+/// Established HTTP/2 sessions, one per proxy.
+#[derive(Clone, Default)]
+pub struct Sessions(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    sessions: HashMap<HostAndPort, Session>,
+    /// Serializes the connects to each proxy until a session is established.
+    connecting: HashMap<HostAndPort, Arc<tokio::sync::Mutex<()>>>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Session {
+    sender: http2::SendRequest<Empty<Bytes>>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The session to `proxy`, unless there is none or it was closed.
+    pub(crate) fn get(&self, proxy: &HostAndPort) -> Option<Session> {
+        let sessions = &mut self.0.lock().unwrap().sessions;
+        let session = sessions.get(proxy)?;
+        if session.sender.is_closed() {
+            sessions.remove(proxy);
+            return None;
+        }
+        Some(session.clone())
+    }
+
+    /// Wait for other connects to `proxy` to finish, the guard is held until the new session is
+    /// established, or the proxy turned out to not support HTTP/2.
+    pub(crate) async fn first_connect(
+        &self,
+        proxy: &HostAndPort,
+    ) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = self
+            .0
+            .lock()
+            .unwrap()
+            .connecting
+            .entry(proxy.clone())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+
+    /// Start a new HTTP/2 session on `stream` which will be used for later tunnels to `proxy`.
+    pub(crate) async fn handshake<T>(
+        &self,
+        proxy: &HostAndPort,
+        stream: T,
+    ) -> std::io::Result<Session>
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (sender, conn) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .map_err(|e| std::io::Error::other(format!("HTTP/2 handshake error: {e}")))?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                if let Err(cause) = conn.await {
+                    tracing::error!(%cause, %proxy, "HTTP/2 connection error");
+                }
+            }
+        });
+        let session = Session { sender };
+        self.0
+            .lock()
+            .unwrap()
+            .sessions
+            .insert(proxy.clone(), session.clone());
+        Ok(session)
+    }
+}
+
+impl std::fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sessions").finish_non_exhaustive()
+    }
+}
+
+impl Session {
+    /// Open a tunnel to `dst` as new stream of this session.
+    pub(crate) async fn connect(
+        mut self,
+        proxy: &HostAndPort,
+        auth: &AuthenticatorFactory,
+        dst: &HostAndPort,
+    ) -> std::io::Result<Upgraded> {
+        let dst_uri = Uri::builder()
+            .authority(dst.to_string())
+            .build()
+            .map_err(|e| std::io::Error::other(format!("Invalid authority '{dst}': {e}")))?;
+        let auth = auth.make(proxy.host()).map_err(|e| {
+            std::io::Error::other(format!("Unable to build authenticator for '{proxy}': {e}"))
+        })?;
+
+        let mut last_headers = None;
+        let mut round = 0;
+        let response = loop {
+            round += 1;
+            let auth_headers = auth_step(&auth, last_headers.take(), proxy)
+                .await
+                .map_err(auth_error)?;
+            let mut request = Request::connect(dst_uri.clone())
+                .version(Version::HTTP_2)
+                .body(Empty::<Bytes>::new())
+                .expect("empty request");
+            request.headers_mut().extend(auth_headers);
+            let response = self.sender.send_request(request).await.map_err(|e| {
+                std::io::Error::other(format!("send request error from {proxy} for {dst}: {e}"))
+            })?;
+            if response.status() == StatusCode::PROXY_AUTHENTICATION_REQUIRED
+                && round < MAX_AUTH_ROUNDS
+                && auth.has_challenge(response.headers())
+            {
+                tracing::debug!(round, "continue authentication");
+                last_headers = Some(response.into_parts().0.headers);
+                continue;
+            }
+            break response;
+        };
+        let status = response.status();
+        if status == StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+            return Err(auth_error(format!("HTTP {status} from {proxy} for {dst}")));
+        }
+        if !status.is_success() {
+            return Err(std::io::Error::other(format!(
+                "HTTP {status} from {proxy} for {dst}"
+            )));
+        }
+        auth.finish(response.headers())
+            .await
+            .map_err(|e| auth_error(crate::conn::Error::AuthenticationFailed(proxy.clone(), e)))?;
+        hyper::upgrade::on(response).await.map_err(|e| {
+            std::io::Error::other(format!("HTTP {status} from {proxy} for {dst} error: {e}"))
+        })
+    }
+}
@@ -1,33 +1,118 @@
 use bytes::Bytes;
+use detox_auth::AuthenticatorFactory;
 use detox_net::HostAndPort;
-use http::header::{CONTENT_LENGTH, LOCATION};
+use http::HeaderValue;
+use http::header::HOST;
+use http::header::{
+    CONTENT_LENGTH, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION,
+};
+use http::uri::PathAndQuery;
 use http::{Request, Response, StatusCode, Uri};
 use http_body_util::{BodyExt, Empty};
+use hyper_util::rt::TokioIo;
+use paclib::Proxy;
+use rustls::pki_types::ServerName;
 use std::io::Error;
 use std::sync::Arc;
+use tokio_rustls::TlsConnector;
 
 use crate::conn::Connection;
 
 static MAX_SIZE: u64 = 8 * 1024 * 1024;
 
+/// Validators of a previous response, to download a file only when it was modified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    /// Value of the `ETag` header.
+    pub etag: Option<String>,
+    /// Value of the `Last-Modified` header.
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_response<B>(res: &Response<B>) -> Self {
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v: &http::HeaderValue| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Result of a conditional GET request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HttpFile {
+    /// The content and its validators.
+    Modified(String, Validators),
+    /// The file did not change since the validators were received (`304 Not Modified`).
+    NotModified,
+}
+
+/// Proxy used to download files which are not reachable directly, e.g. the PAC file.
+#[derive(Debug, Clone)]
+pub struct BootstrapProxy {
+    pub proxy: Proxy,
+    pub auth: AuthenticatorFactory,
+}
+
 async fn http_get(
     uri: Uri,
     tls_config: Arc<rustls::ClientConfig>,
+    bootstrap: Option<&BootstrapProxy>,
+    validators: &Validators,
 ) -> std::io::Result<Response<hyper::body::Incoming>> {
     let dst = HostAndPort::try_from_uri(&uri).map_err(std::io::Error::other)?;
-    let scheme = uri.scheme().unwrap_or(&http::uri::Scheme::HTTP);
-    let conn = if scheme == &http::uri::Scheme::HTTPS {
-        Connection::https(dst, tls_config)
-    } else {
-        Connection::http(dst)
-    };
-    let conn = conn.await?;
+    let is_https = uri.scheme() == Some(&http::uri::Scheme::HTTPS);
 
-    let request = Request::get(uri)
+    let mut request = Request::get(uri);
+    if let Some(ref etag) = validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(ref last_modified) = validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let request = request
         .body(Empty::<Bytes>::new())
         .map_err(|e| Error::other(format!("Invalid HTTP request: {e}")))?;
 
-    let conn = conn
+    let conn = match bootstrap {
+        None if is_https => Connection::https(dst, tls_config),
+        None => Connection::http(dst),
+        // plain HTTP is forwarded by the proxy, SOCKS proxies always need a tunnel
+        Some(b) if !is_https && !b.proxy.is_socks() => {
+            Connection::http_proxy(b.proxy.clone(), tls_config, b.auth.clone())
+        }
+        Some(b) => {
+            let conn = Connection::http_tunnel(
+                b.proxy.clone(),
+                tls_config.clone(),
+                b.auth.clone(),
+                dst.clone(),
+            )
+            .await?;
+            if is_https {
+                return https_get(conn, &dst, tls_config, request).await;
+            }
+            return send_request(conn, request).await;
+        }
+    };
+    send_request(conn.await?, request).await
+}
+
+async fn send_request(
+    conn: Connection,
+    request: Request<Empty<Bytes>>,
+) -> std::io::Result<Response<hyper::body::Incoming>> {
+    let mut conn = conn
         .handshake()
         .await
         .map_err(|e| Error::other(format!("HTTP handshake error: {e}")))?;
@@ -37,22 +122,75 @@ async fn http_get(
         .map_err(|e| Error::other(format!("HTTP error: {e}")))
 }
 
-/// We currently support only IETF RFC 2616, which requires absolute URIs in case of an redirect
-pub async fn http_file(
+/// Send `request` via TLS through the tunnel `conn`.
+async fn https_get(
+    conn: Connection,
+    dst: &HostAndPort,
+    tls_config: Arc<rustls::ClientConfig>,
+    mut request: Request<Empty<Bytes>>,
+) -> std::io::Result<Response<hyper::body::Incoming>> {
+    let domain = ServerName::try_from(dst.host().to_owned()).map_err(|e| {
+        Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid domain name: {e}"),
+        )
+    })?;
+    let tls = TlsConnector::from(tls_config).connect(domain, conn).await?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(tls))
+        .await
+        .map_err(|e| Error::other(format!("HTTP handshake error: {e}")))?;
+    tokio::spawn(async move {
+        if let Err(cause) = conn.await {
+            tracing::error!(%cause, "connection error");
+        }
+    });
+
+    let host = HeaderValue::from_str(&dst.to_string()).map_err(Error::other)?;
+    request.headers_mut().insert(HOST, host);
+    let path = request
+        .uri()
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| PathAndQuery::from_static("/"));
+    *request.uri_mut() = Uri::from(path);
+    sender
+        .send_request(request)
+        .await
+        .map_err(|e| Error::other(format!("HTTP error: {e}")))
+}
+
+/// Download the file at `uri`, following redirects.
+pub async fn http_file(uri: Uri, tls_config: Arc<rustls::ClientConfig>) -> std::io::Result<String> {
+    match http_file_if_modified(uri, tls_config, None, &Validators::default()).await? {
+        HttpFile::Modified(data, _) => Ok(data),
+        HttpFile::NotModified => Err(Error::other("unexpected 304 Not Modified")),
+    }
+}
+
+/// Like [`http_file`], but send `If-None-Match` and `If-Modified-Since` headers built from
+/// `validators` of a previous response. With a `bootstrap` proxy, the file is downloaded through
+/// it instead of connecting directly.
+pub async fn http_file_if_modified(
     mut uri: Uri,
     tls_config: Arc<rustls::ClientConfig>,
-) -> std::io::Result<String> {
+    bootstrap: Option<&BootstrapProxy>,
+    validators: &Validators,
+) -> std::io::Result<HttpFile> {
     let mut max_redirects = 10i32;
 
     loop {
-        let response = http_get(uri.clone(), tls_config.clone()).await.map_err({
-            let uri = uri.clone();
-            move |e| Error::other(format!("GET {}: {}", &uri, e))
-        })?;
+        let response = http_get(uri.clone(), tls_config.clone(), bootstrap, validators)
+            .await
+            .map_err({
+                let uri = uri.clone();
+                move |e| Error::other(format!("GET {}: {}", &uri, e))
+            })?;
 
-        let progress = HttpGetProgress::from_response(response)?;
+        let progress = HttpGetProgress::from_response(response, &uri)?;
         match progress {
+            HttpGetProgress::NotModified => break Ok(HttpFile::NotModified),
             HttpGetProgress::Complete(mut response) => {
+                let validators = Validators::from_response(&response);
                 let size = response
                     .headers()
                     .get(CONTENT_LENGTH)
@@ -79,7 +217,7 @@ pub async fn http_file(
 
                 let data = String::from_utf8(data)
                     .map_err(|e| Error::other(format!("Invalid UTF-8 data: {e}")))?;
-                break Ok(data);
+                break Ok(HttpFile::Modified(data, validators));
             }
             HttpGetProgress::Redirect(location) => uri = location,
         }
@@ -94,6 +232,7 @@ pub async fn http_file(
 #[derive(Debug)]
 enum HttpGetProgress<B> {
     Redirect(Uri),
+    NotModified,
     Complete(Response<B>),
 }
 
@@ -101,12 +240,13 @@ impl<B> HttpGetProgress<B>
 where
     B: hyper::body::Body,
 {
-    fn from_response(res: Response<B>) -> std::io::Result<Self>
+    fn from_response(res: Response<B>, base: &Uri) -> std::io::Result<Self>
     where
         B: hyper::body::Body,
     {
         match res.status() {
             StatusCode::OK => Ok(Self::Complete(res)),
+            StatusCode::NOT_MODIFIED => Ok(Self::NotModified),
             // received a redirect, we need to follow the url of the `location` header
             StatusCode::MOVED_PERMANENTLY
             | StatusCode::FOUND
@@ -117,15 +257,8 @@ where
                     let location = location.to_str().map_err(|e| {
                         Error::other(format!("location value is not a valid string: {e}"))
                     })?;
-                    // turn `location` string into an `Uri` object
-                    let location = location.parse::<Uri>().map_err(|e| {
-                        Error::other(format!("parsing URI '{}' failed: {}", &location, e))
-                    })?;
-                    if location.authority().is_none() {
-                        return Err(Error::other(format!("URI '{}' is not absolute", &location)));
-                    }
                     // use location URI for a new try
-                    Ok(Self::Redirect(location))
+                    Ok(Self::Redirect(resolve_location(base, location)?))
                 } else {
                     Err(Error::other("redirect, but location header is missing"))
                 }
@@ -139,6 +272,104 @@ where
     }
 }
 
+/// Resolve the `location` of a redirect relative to the requested URI `base`, see RFC 9110
+/// section 10.2.2.
+fn resolve_location(base: &Uri, location: &str) -> std::io::Result<Uri> {
+    let scheme = base.scheme_str().unwrap_or("http");
+    let authority = base.authority().map(|a| a.as_str()).unwrap_or_default();
+    let path = base.path();
+    // `http::Uri` accepts some characters which are not allowed in URI references
+    if location.is_empty() || location.contains(|c: char| c == '\\' || c.is_whitespace()) {
+        return Err(Error::other(format!("invalid location '{location}'")));
+    }
+    let resolved = if has_scheme(location) {
+        location.to_owned()
+    } else if location.starts_with("//") {
+        format!("{scheme}:{location}")
+    } else if location.starts_with('/') {
+        format!("{scheme}://{authority}{location}")
+    } else if location.starts_with('?') {
+        format!("{scheme}://{authority}{path}{location}")
+    } else {
+        let dir = &path[..path.rfind('/').map(|i| i + 1).unwrap_or_default()];
+        format!("{scheme}://{authority}{dir}{location}")
+    };
+    // turn `location` string into an `Uri` object
+    let uri = resolved
+        .parse::<Uri>()
+        .map_err(|e| Error::other(format!("parsing URI '{location}' failed: {e}")))?;
+    if uri.authority().is_none() {
+        return Err(Error::other(format!("URI '{location}' is not absolute")));
+    }
+    let path = remove_dot_segments(uri.path());
+    if path == uri.path() {
+        return Ok(uri);
+    }
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    let mut parts = uri.into_parts();
+    parts.path_and_query = Some(
+        path_and_query
+            .parse()
+            .map_err(|e| Error::other(format!("parsing URI '{location}' failed: {e}")))?,
+    );
+    Uri::from_parts(parts)
+        .map_err(|e| Error::other(format!("parsing URI '{location}' failed: {e}")))
+}
+
+/// Remove the `.` and `..` segments of `path`, see RFC 3986 section 5.2.4.
+/// Returns `true` if the URI reference starts with a scheme, i.e. is not a relative reference
+/// (RFC 3986 section 4.3).
+fn has_scheme(reference: &str) -> bool {
+    let Some(end) = reference.find([':', '/', '?', '#']) else {
+        return false;
+    };
+    let scheme = &reference[..end];
+    reference[end..].starts_with(':')
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output = String::with_capacity(path.len());
+    // remove the last segment and its preceding `/` from the output
+    let pop = |output: &mut String| output.truncate(output.rfind('/').unwrap_or_default());
+    while !input.is_empty() {
+        if let Some(rest) = input
+            .strip_prefix("../")
+            .or_else(|| input.strip_prefix("./"))
+        {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") {
+            input = &input[3..];
+            pop(&mut output);
+        } else if input == "/.." {
+            input = "/";
+            pop(&mut output);
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            let end = input
+                .bytes()
+                .skip(1)
+                .position(|b| b == b'/')
+                .map_or(input.len(), |i| i + 1);
+            output.push_str(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -146,23 +377,50 @@ mod tests {
     use http::StatusCode;
     use http::header::LOCATION;
 
+    fn base() -> Uri {
+        "http://example.org/dir/proxy.pac".parse().unwrap()
+    }
+
     #[test]
     fn http_get_progress_ok() {
         let res = Response::builder()
             .status(StatusCode::OK)
             .body(Empty::<Bytes>::new())
             .unwrap();
-        let progress = HttpGetProgress::from_response(res).unwrap();
+        let progress = HttpGetProgress::from_response(res, &base()).unwrap();
         assert!(matches!(progress, HttpGetProgress::Complete(_)));
     }
 
+    #[test]
+    fn http_get_progress_not_modified() {
+        let res = Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let progress = HttpGetProgress::from_response(res, &base()).unwrap();
+        assert!(matches!(progress, HttpGetProgress::NotModified));
+    }
+
+    #[test]
+    fn validators_from_response() {
+        let res = Response::builder()
+            .status(StatusCode::OK)
+            .header(ETAG, "\"abc\"")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let validators = Validators::from_response(&res);
+        assert_eq!(validators.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(validators.last_modified, None);
+        assert!(!validators.is_empty());
+    }
+
     #[test]
     fn http_get_progress_not_found() {
         let res = Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body(Empty::<Bytes>::new())
             .unwrap();
-        let progress = HttpGetProgress::from_response(res);
+        let progress = HttpGetProgress::from_response(res, &base());
         assert!(progress.is_err());
     }
 
@@ -182,7 +440,7 @@ mod tests {
                 .header(LOCATION, location)
                 .body(Empty::<Bytes>::new())
                 .unwrap();
-            let progress = HttpGetProgress::from_response(res).unwrap();
+            let progress = HttpGetProgress::from_response(res, &base()).unwrap();
             assert!(matches!(progress, HttpGetProgress::Redirect(_)));
             if let HttpGetProgress::Redirect(uri) = progress {
                 assert_eq!(uri, location_uri);
@@ -196,19 +454,54 @@ mod tests {
             .status(StatusCode::PERMANENT_REDIRECT)
             .body(Empty::<Bytes>::new())
             .unwrap();
-        let progress = HttpGetProgress::from_response(res);
+        let progress = HttpGetProgress::from_response(res, &base());
         assert!(progress.is_err());
     }
 
     #[test]
     fn http_get_progress_relative_location() {
-        let res = Response::builder()
-            .status(StatusCode::FOUND)
-            .header(LOCATION, "/index.html")
-            .body(Empty::<Bytes>::new())
-            .unwrap();
-        let progress = HttpGetProgress::from_response(res);
-        assert!(progress.is_err());
+        for (location, expected) in [
+            ("/index.html", "http://example.org/index.html"),
+            ("next.pac", "http://example.org/dir/next.pac"),
+            ("../up.pac", "http://example.org/up.pac"),
+            ("./../../up.pac?v=./..", "http://example.org/up.pac?v=./.."),
+            ("http://example.org/a/./b/../c", "http://example.org/a/c"),
+            ("?v=2", "http://example.org/dir/proxy.pac?v=2"),
+            ("//other.example.org/x", "http://other.example.org/x"),
+            // an URL in the query does not make the reference absolute
+            (
+                "/login?next=https://intranet/pac",
+                "http://example.org/login?next=https://intranet/pac",
+            ),
+            (
+                "next.pac?from=http://example.org/",
+                "http://example.org/dir/next.pac?from=http://example.org/",
+            ),
+            ("HTTPS://example.org/x", "https://example.org/x"),
+        ] {
+            let res = Response::builder()
+                .status(StatusCode::FOUND)
+                .header(LOCATION, location)
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+            let progress = HttpGetProgress::from_response(res, &base()).unwrap();
+            assert!(
+                matches!(progress, HttpGetProgress::Redirect(ref uri) if uri == expected),
+                "{location}: {progress:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn remove_dot_segments_test() {
+        // examples of RFC 3986 section 5.2.4
+        assert_eq!(remove_dot_segments("/a/b/c/./../../g"), "/a/g");
+        assert_eq!(remove_dot_segments("mid/content=5/../6"), "mid/6");
+        assert_eq!(remove_dot_segments("/b/c/.."), "/b/");
+        assert_eq!(remove_dot_segments("/../g"), "/g");
+        assert_eq!(remove_dot_segments("/b/c/g."), "/b/c/g.");
+        assert_eq!(remove_dot_segments("/b/c/..g"), "/b/c/..g");
+        assert_eq!(remove_dot_segments("/"), "/");
     }
 
     #[test]
@@ -218,7 +511,7 @@ mod tests {
             .header(LOCATION, "\\")
             .body(Empty::<Bytes>::new())
             .unwrap();
-        let progress = HttpGetProgress::from_response(res);
+        let progress = HttpGetProgress::from_response(res, &base());
         assert!(progress.is_err());
     }
 }
@@ -1,4 +1,7 @@
+pub mod body;
 pub mod conn;
+pub mod h2;
 pub mod http;
+pub mod pool;
 
-pub use http::http_file;
+pub use http::{BootstrapProxy, HttpFile, Validators, http_file, http_file_if_modified};
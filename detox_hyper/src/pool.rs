@@ -0,0 +1,248 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use detox_net::HostAndPort;
+use http::{Request, Response};
+use http_body::Body;
+use hyper::body::Incoming as IncomingBody;
+use paclib::ProxyOrDirect;
+
+use crate::body::Replay;
+use crate::conn::{Error, SendRequest};
+
+/// Time after which an idle connection is dropped, unless configured otherwise.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Number of idle connections kept per key, unless configured otherwise.
+pub const DEFAULT_MAX_IDLE_PER_KEY: usize = 8;
+
+/// Connections with the same key can be used interchangeably.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    proxy: ProxyOrDirect,
+    dst: HostAndPort,
+}
+
+impl Key {
+    pub fn new(proxy: ProxyOrDirect, dst: HostAndPort) -> Self {
+        Self { proxy, dst }
+    }
+
+    pub fn proxy(&self) -> &ProxyOrDirect {
+        &self.proxy
+    }
+
+    pub fn dst(&self) -> &HostAndPort {
+        &self.dst
+    }
+}
+
+struct Idle<B>
+where
+    B: Body + 'static,
+{
+    conn: SendRequest<B>,
+    since: Instant,
+}
+
+impl<B> Idle<B>
+where
+    B: Body + 'static,
+{
+    fn is_expired(&self, now: Instant, idle_timeout: Duration) -> bool {
+        now.saturating_duration_since(self.since) > idle_timeout
+    }
+}
+
+struct Shared<B>
+where
+    B: Body + 'static,
+{
+    idle: Mutex<HashMap<Key, VecDeque<Idle<B>>>>,
+    idle_timeout: Duration,
+    max_idle_per_key: usize,
+}
+
+/// Pool of idle HTTP/1.1 keep-alive connections.
+///
+/// Connections are keyed by the upstream proxy (or DIRECT) and the destination. A connection
+/// which is idle for longer than the idle timeout is dropped, and at most `max_idle_per_key`
+/// connections are kept per key. A `max_idle_per_key` of zero disables pooling.
+pub struct Pool<B>
+where
+    B: Body + 'static,
+{
+    shared: Arc<Shared<B>>,
+}
+
+/// A connection checked out from the pool (or a fresh one which will be returned to it).
+pub struct Pooled<B>
+where
+    B: Body + 'static,
+{
+    conn: SendRequest<B>,
+    key: Key,
+    pool: Weak<Shared<B>>,
+    reused: bool,
+}
+
+impl<B> Shared<B>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn put(&self, key: Key, conn: SendRequest<B>) {
+        if self.max_idle_per_key == 0 || !conn.is_ready() {
+            return;
+        }
+        let now = Instant::now();
+        let mut idle = self.idle.lock().unwrap();
+        // drop expired or closed connections of all keys, such that sockets do not linger around
+        idle.retain(|_, list| {
+            list.retain(|e| !e.is_expired(now, self.idle_timeout) && !e.conn.is_closed());
+            !list.is_empty()
+        });
+        let list = idle.entry(key).or_default();
+        while list.len() >= self.max_idle_per_key {
+            list.pop_front();
+        }
+        list.push_back(Idle { conn, since: now });
+    }
+}
+
+impl<B> Pool<B>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    pub fn new(idle_timeout: Duration, max_idle_per_key: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                idle: Default::default(),
+                idle_timeout,
+                max_idle_per_key,
+            }),
+        }
+    }
+
+    /// Take an idle connection for `key` out of the pool.
+    ///
+    /// Only connections which did not expire and which are ready to send a new request are
+    /// returned. The most recently used connection is preferred.
+    pub fn checkout(&self, key: &Key) -> Option<Pooled<B>> {
+        let now = Instant::now();
+        let mut idle = self.shared.idle.lock().unwrap();
+        let list = idle.get_mut(key)?;
+        let mut found = None;
+        while let Some(entry) = list.pop_back() {
+            if entry.is_expired(now, self.shared.idle_timeout) {
+                tracing::trace!(?key, "drop expired connection");
+                continue;
+            }
+            if !entry.conn.is_ready() {
+                tracing::trace!(?key, "drop unhealthy connection");
+                continue;
+            }
+            found = Some(entry.conn);
+            break;
+        }
+        list.retain(|e| !e.is_expired(now, self.shared.idle_timeout) && !e.conn.is_closed());
+        if list.is_empty() {
+            idle.remove(key);
+        }
+        found.map(|conn| Pooled {
+            conn,
+            key: key.clone(),
+            pool: Arc::downgrade(&self.shared),
+            reused: true,
+        })
+    }
+
+    /// Wrap a freshly established connection, such that it is returned to the pool after use.
+    pub fn pooled(&self, key: Key, conn: SendRequest<B>) -> Pooled<B> {
+        Pooled {
+            conn,
+            key,
+            pool: Arc::downgrade(&self.shared),
+            reused: false,
+        }
+    }
+
+    /// Number of idle connections for `key`.
+    pub fn idle(&self, key: &Key) -> usize {
+        let idle = self.shared.idle.lock().unwrap();
+        idle.get(key).map(|l| l.len()).unwrap_or_default()
+    }
+}
+
+impl<B> Default for Pool<B>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn default() -> Self {
+        Self::new(DEFAULT_IDLE_TIMEOUT, DEFAULT_MAX_IDLE_PER_KEY)
+    }
+}
+
+impl<B> Clone for Pool<B>
+where
+    B: Body + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<B> Pooled<B>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    pub fn proxy(&self) -> &ProxyOrDirect {
+        self.key.proxy()
+    }
+
+    /// Returns `true` if requests are authenticated at the proxy, see
+    /// [`SendRequest::has_auth`].
+    pub fn has_auth(&self) -> bool {
+        self.conn.has_auth()
+    }
+
+    /// Returns `true` if the connection was used before.
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Send the request and give the connection back to the pool once the response body was
+    /// consumed.
+    pub async fn send_request(self, req: Request<B>) -> Result<Response<IncomingBody>, Error>
+    where
+        B: Replay,
+    {
+        let Pooled {
+            mut conn,
+            key,
+            pool,
+            reused: _,
+        } = self;
+        let resp = conn.send_request(req).await?;
+        tokio::spawn(async move {
+            // `ready` resolves when the previous response was completely received.
+            if conn.ready().await.is_ok()
+                && let Some(pool) = pool.upgrade()
+            {
+                pool.put(key, conn);
+            }
+        });
+        Ok(resp)
+    }
+}
@@ -0,0 +1,674 @@
+//! Asynchronous stub resolver for `A` and `AAAA` records.
+//!
+//! The queries are send via UDP (TCP when the answer was truncated) to the configured name
+//! servers. Answers are cached for the TTL of their records, negative answers (the name or its
+//! addresses do not exist) for the TTL of the SOA record, at most for a separate (usually
+//! shorter) negative TTL. Name servers which do not answer are not cached. Without name servers, e.g. on platforms without
+//! `/etc/resolv.conf`, the resolver of the operating system is used instead.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use dns_parser::{Packet, QueryClass, QueryType, RData, ResponseCode};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Time for which results of the resolver of the operating system are cached, which does not
+/// tell the TTL.
+const SYSTEM_TTL: Duration = Duration::from_secs(60);
+/// Maximum size of an UDP answer without EDNS, see RFC 1035 section 2.3.4.
+const MAX_UDP_SIZE: usize = 512;
+
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Settings to apply over the system configuration when it is reloaded.
+    builder: Builder,
+    config: RwLock<Arc<Config>>,
+    cache: Mutex<HashMap<String, Entry>>,
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    nameservers: Vec<SocketAddr>,
+    search: Vec<String>,
+    ndots: usize,
+    timeout: Duration,
+    attempts: usize,
+    negative_ttl: Duration,
+    max_ttl: Duration,
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+}
+
+/// Addresses of one name, with the time they may be cached.
+#[derive(Debug, Default)]
+struct Answer {
+    addrs: Vec<IpAddr>,
+    ttl: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Builder {
+    nameservers: Vec<SocketAddr>,
+    search: Option<Vec<String>>,
+    timeout: Option<Duration>,
+    attempts: Option<usize>,
+    negative_ttl: Duration,
+    max_ttl: Duration,
+}
+
+impl Builder {
+    /// Name servers to query, by default the ones of `/etc/resolv.conf`.
+    pub fn nameservers(mut self, nameservers: Vec<SocketAddr>) -> Self {
+        self.nameservers = nameservers;
+        self
+    }
+
+    /// Domains appended to names with less dots than `ndots`, by default the ones of
+    /// `/etc/resolv.conf`.
+    pub fn search(mut self, domains: Vec<String>) -> Self {
+        self.search = Some(domains);
+        self
+    }
+
+    /// Time to wait for the answer of one query.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Number of times all name servers are tried.
+    pub fn attempts(mut self, num: usize) -> Self {
+        self.attempts = Some(num);
+        self
+    }
+
+    /// Time for which failed lookups are cached.
+    pub fn negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    /// Upper bound of the TTL of cached records.
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+
+    pub fn build(self) -> Resolver {
+        let config = self.config();
+        Resolver {
+            inner: Arc::new(Inner {
+                builder: self,
+                config: RwLock::new(Arc::new(config)),
+                cache: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// The system configuration with the settings of this builder applied.
+    fn config(&self) -> Config {
+        let mut config = Config {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+            timeout: Duration::from_secs(2),
+            attempts: 2,
+            negative_ttl: self.negative_ttl,
+            max_ttl: self.max_ttl,
+            hosts: HashMap::new(),
+        };
+        #[cfg(unix)]
+        {
+            if let Ok(content) = std::fs::read_to_string("/etc/resolv.conf") {
+                config.parse_resolv_conf(&content);
+            }
+            if let Ok(content) = std::fs::read_to_string("/etc/hosts") {
+                config.parse_hosts(&content);
+            }
+        }
+        if !self.nameservers.is_empty() {
+            config.nameservers = self.nameservers.clone();
+        }
+        if let Some(ref search) = self.search {
+            config.search = search.clone();
+        }
+        if let Some(timeout) = self.timeout {
+            config.timeout = timeout;
+        }
+        if let Some(attempts) = self.attempts {
+            config.attempts = attempts.max(1);
+        }
+        config
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            nameservers: Vec::new(),
+            search: None,
+            timeout: None,
+            attempts: None,
+            negative_ttl: Duration::from_secs(30),
+            max_ttl: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl Config {
+    fn parse_resolv_conf(&mut self, content: &str) {
+        for line in content.lines() {
+            let mut words = line
+                .split(['#', ';'])
+                .next()
+                .unwrap_or_default()
+                .split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    // scoped IPv6 addresses (`fe80::1%eth0`) are not supported
+                    if let Some(ip) = words.next().and_then(|w| w.parse::<IpAddr>().ok()) {
+                        self.nameservers.push(SocketAddr::new(ip, 53));
+                    }
+                }
+                Some("search") | Some("domain") => {
+                    self.search = words.map(|w| w.trim_end_matches('.').to_owned()).collect();
+                }
+                Some("options") => {
+                    for option in words {
+                        if let Some(ndots) = option.strip_prefix("ndots:") {
+                            self.ndots = ndots.parse().unwrap_or(self.ndots);
+                        } else if let Some(timeout) = option.strip_prefix("timeout:") {
+                            self.timeout = timeout
+                                .parse()
+                                .map(Duration::from_secs)
+                                .unwrap_or(self.timeout);
+                        } else if let Some(attempts) = option.strip_prefix("attempts:") {
+                            self.attempts = attempts.parse().unwrap_or(self.attempts).max(1);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_hosts(&mut self, content: &str) {
+        for line in content.lines() {
+            let mut words = line
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace();
+            let Some(ip) = words.next().and_then(|w| w.parse::<IpAddr>().ok()) else {
+                continue;
+            };
+            for name in words {
+                let addrs = self.hosts.entry(name.to_ascii_lowercase()).or_default();
+                if !addrs.contains(&ip) {
+                    addrs.push(ip);
+                }
+            }
+        }
+    }
+
+    /// Names to query for `host`, expanded with the search domains.
+    fn candidates(&self, host: &str) -> Vec<String> {
+        if let Some(host) = host.strip_suffix('.') {
+            return vec![host.to_owned()];
+        }
+        let searched = self.search.iter().map(|domain| format!("{host}.{domain}"));
+        if host.matches('.').count() >= self.ndots {
+            std::iter::once(host.to_owned()).chain(searched).collect()
+        } else {
+            searched.chain(std::iter::once(host.to_owned())).collect()
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl Resolver {
+    pub fn builder() -> Builder {
+        Default::default()
+    }
+
+    /// All IPv4 and IPv6 addresses of `host`, IPv4 addresses first.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] when the host has no addresses and with
+    /// [`io::ErrorKind::TimedOut`] when the name servers did not answer.
+    pub async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let key = host.to_ascii_lowercase();
+        let config = self.config();
+        if let Some(addrs) = config.hosts.get(key.trim_end_matches('.')) {
+            return Ok(addrs.clone());
+        }
+
+        let now = Instant::now();
+        let cached = self
+            .inner
+            .cache
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|entry| entry.expires > now)
+            .map(|entry| entry.addrs.clone());
+        let addrs = match cached {
+            Some(addrs) => {
+                tracing::trace!(host, ?addrs, "hit");
+                addrs
+            }
+            None => {
+                let answer = self.resolve(&config, &key).await.map_err(|cause| {
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no answer for {host}: {cause}"),
+                    )
+                })?;
+                tracing::trace!(host, addrs = ?answer.addrs, ttl = ?answer.ttl, "miss");
+                let ttl = if answer.addrs.is_empty() {
+                    answer.ttl.unwrap_or(config.negative_ttl)
+                } else {
+                    answer.ttl.unwrap_or(SYSTEM_TTL)
+                };
+                let ttl = ttl.min(config.max_ttl);
+                if !ttl.is_zero() {
+                    let mut cache = self.inner.cache.lock().unwrap();
+                    cache.retain(|_, entry| entry.expires > now);
+                    cache.insert(
+                        key,
+                        Entry {
+                            addrs: answer.addrs.clone(),
+                            expires: now + ttl,
+                        },
+                    );
+                }
+                answer.addrs
+            }
+        };
+
+        if addrs.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address found for {host}"),
+            ))
+        } else {
+            Ok(addrs)
+        }
+    }
+
+    /// Search domains appended to short host names.
+    pub fn search(&self) -> Vec<String> {
+        self.config().search.clone()
+    }
+
+    /// Remove all cached results.
+    pub fn clear(&self) {
+        self.inner.cache.lock().unwrap().clear();
+    }
+
+    /// Read `/etc/resolv.conf` and `/etc/hosts` again, e.g. after the network changed, and
+    /// remove all cached results.
+    pub fn reload(&self) {
+        let config = self.inner.builder.config();
+        tracing::debug!(nameservers = ?config.nameservers, search = ?config.search, "reload");
+        *self.inner.config.write().unwrap() = Arc::new(config);
+        self.clear();
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.inner.config.read().unwrap().clone()
+    }
+
+    /// Addresses of `host`, fails when a name server did not answer for one of the candidates.
+    async fn resolve(&self, config: &Config, host: &str) -> io::Result<Answer> {
+        if config.nameservers.is_empty() {
+            return Ok(match tokio::net::lookup_host((host, 0)).await {
+                Ok(addrs) => {
+                    let mut answer = Answer::default();
+                    for addr in addrs {
+                        answer.push(addr.ip());
+                    }
+                    answer.sort();
+                    answer
+                }
+                Err(cause) => {
+                    tracing::debug!(host, %cause, "lookup failed");
+                    Answer::default()
+                }
+            });
+        }
+
+        let mut negative = Answer::default();
+        let mut error = None;
+        for name in config.candidates(host) {
+            let (v4, v6) = tokio::join!(
+                query(config, &name, QueryType::A),
+                query(config, &name, QueryType::AAAA)
+            );
+            let mut answer = Answer::default();
+            let mut failed = false;
+            for part in [v4, v6] {
+                match part {
+                    Ok(part) => answer.merge(part),
+                    Err(cause) => {
+                        tracing::debug!(name, %cause, "query failed");
+                        failed = true;
+                        error = Some(cause);
+                    }
+                }
+            }
+            if !answer.addrs.is_empty() {
+                answer.sort();
+                return Ok(answer);
+            }
+            if !failed {
+                // the negative TTL of the last candidate, the one asked for, counts
+                negative.ttl = answer.ttl.map(|ttl| ttl.min(config.negative_ttl));
+            }
+        }
+        // without an answer for each candidate the name may exist after all
+        match error {
+            Some(cause) => Err(cause),
+            None => Ok(negative),
+        }
+    }
+}
+
+/// Query each name server until one answers.
+async fn query(config: &Config, name: &str, qtype: QueryType) -> io::Result<Answer> {
+    let mut error = io::Error::from(io::ErrorKind::TimedOut);
+    for _ in 0..config.attempts {
+        for nameserver in &config.nameservers {
+            let result = tokio::time::timeout(config.timeout, exchange(*nameserver, name, qtype))
+                .await
+                .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::TimedOut)));
+            match result {
+                Ok(answer) => return Ok(answer),
+                Err(cause) => error = cause,
+            }
+        }
+    }
+    Err(error)
+}
+
+impl Answer {
+    fn push(&mut self, ip: IpAddr) {
+        if !self.addrs.contains(&ip) {
+            self.addrs.push(ip);
+        }
+    }
+
+    fn merge(&mut self, other: Answer) {
+        for ip in other.addrs {
+            self.push(ip);
+        }
+        self.ttl = match (self.ttl, other.ttl) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    fn sort(&mut self) {
+        self.addrs.sort_by_key(|ip| ip.is_ipv6());
+    }
+}
+
+/// Send one query to `nameserver`, via TCP when the UDP answer was truncated.
+async fn exchange(nameserver: SocketAddr, name: &str, qtype: QueryType) -> io::Result<Answer> {
+    let mut id = [0u8; 2];
+    getrandom::fill(&mut id).map_err(|e| io::Error::other(e.to_string()))?;
+    let id = u16::from_be_bytes(id);
+    let mut builder = dns_parser::Builder::new_query(id, true);
+    builder.add_question(name, false, qtype, QueryClass::IN);
+    let query = builder
+        .build()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name too long"))?;
+
+    let local: SocketAddr = match nameserver {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(nameserver).await?;
+    socket.send(&query).await?;
+    let mut buf = vec![0u8; MAX_UDP_SIZE];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        match parse_answer(id, &buf[..len]) {
+            Ok(Some(answer)) => return Ok(answer),
+            // truncated
+            Ok(None) => break,
+            // not the answer to our query, wait for the next datagram
+            Err(cause) if cause.kind() == io::ErrorKind::InvalidData => continue,
+            Err(cause) => return Err(cause),
+        }
+    }
+
+    let mut stream = TcpStream::connect(nameserver).await?;
+    stream.write_u16(query.len() as u16).await?;
+    stream.write_all(&query).await?;
+    let len = stream.read_u16().await?;
+    let mut buf = vec![0u8; len as usize];
+    stream.read_exact(&mut buf).await?;
+    parse_answer(id, &buf)?.ok_or_else(|| io::Error::other("truncated answer via TCP"))
+}
+
+/// Addresses and TTL of the answer to the query `id`, `None` when the answer was truncated.
+fn parse_answer(id: u16, data: &[u8]) -> io::Result<Option<Answer>> {
+    let packet = Packet::parse(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if packet.header.id != id || packet.header.query {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected DNS message",
+        ));
+    }
+    if packet.header.truncated {
+        return Ok(None);
+    }
+
+    let mut answer = Answer::default();
+    match packet.header.response_code {
+        ResponseCode::NoError | ResponseCode::NameError => {}
+        code => return Err(io::Error::other(format!("DNS server failed: {code:?}"))),
+    }
+    for record in &packet.answers {
+        let ip = match record.data {
+            RData::A(ref a) => IpAddr::V4(a.0),
+            RData::AAAA(ref a) => IpAddr::V6(a.0),
+            _ => continue,
+        };
+        answer.push(ip);
+        let ttl = Duration::from_secs(record.ttl.into());
+        answer.ttl = Some(answer.ttl.map_or(ttl, |t| t.min(ttl)));
+    }
+    if answer.addrs.is_empty() {
+        // negative answer, cached according to the SOA record, see RFC 2308 section 5
+        answer.ttl = packet
+            .nameservers
+            .iter()
+            .find_map(|record| match record.data {
+                RData::SOA(ref soa) => {
+                    Some(Duration::from_secs(record.ttl.min(soa.minimum_ttl).into()))
+                }
+                _ => None,
+            });
+    }
+    Ok(Some(answer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_keeps_settings() {
+        let nameserver = SocketAddr::from(([192, 0, 2, 1], 53));
+        let resolver = Resolver::builder()
+            .nameservers(vec![nameserver])
+            .search(vec!["example.org".into()])
+            .build();
+        resolver.inner.cache.lock().unwrap().insert(
+            "www".into(),
+            Entry {
+                addrs: vec![IpAddr::from([192, 0, 2, 2])],
+                expires: Instant::now() + Duration::from_secs(60),
+            },
+        );
+        resolver.reload();
+        assert_eq!(resolver.config().nameservers, vec![nameserver]);
+        assert_eq!(resolver.search(), vec!["example.org"]);
+        assert!(resolver.inner.cache.lock().unwrap().is_empty());
+    }
+
+    fn config() -> Config {
+        let mut config = Resolver::builder().config();
+        config.nameservers.clear();
+        config.search.clear();
+        config.hosts.clear();
+        config.ndots = 1;
+        config
+    }
+
+    #[test]
+    fn resolv_conf() {
+        let mut config = config();
+        config.parse_resolv_conf(
+            "# comment\nnameserver 192.0.2.1\nnameserver 2001:db8::1 ; comment\n\
+             search example.org. example.com\noptions ndots:2 timeout:1 attempts:3\n",
+        );
+        assert_eq!(
+            config.nameservers,
+            vec![
+                "192.0.2.1:53".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::1]:53".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.search, vec!["example.org", "example.com"]);
+        assert_eq!(config.ndots, 2);
+        assert_eq!(config.timeout, Duration::from_secs(1));
+        assert_eq!(config.attempts, 3);
+    }
+
+    #[test]
+    fn hosts() {
+        let mut config = config();
+        config.parse_hosts("127.0.0.1 localhost Local # comment\n::1 localhost\n# 10.0.0.1 x\n");
+        assert_eq!(
+            config.hosts["localhost"],
+            vec![
+                IpAddr::from(Ipv4Addr::LOCALHOST),
+                IpAddr::from(Ipv6Addr::LOCALHOST)
+            ]
+        );
+        assert!(config.hosts.contains_key("local"));
+        assert!(!config.hosts.contains_key("x"));
+    }
+
+    #[test]
+    fn candidates() {
+        let mut config = config();
+        config.search = vec!["example.org".into()];
+        assert_eq!(config.candidates("www"), vec!["www.example.org", "www"]);
+        assert_eq!(
+            config.candidates("www.example.com"),
+            vec!["www.example.com", "www.example.com.example.org"]
+        );
+        assert_eq!(config.candidates("www."), vec!["www"]);
+    }
+
+    #[test]
+    fn answer_ttl() {
+        let mut builder = dns_parser::Builder::new_query(7, true);
+        builder.add_question("example.org", false, QueryType::A, QueryClass::IN);
+        let mut data = builder.build().unwrap();
+        // turn the query into an answer with two records
+        data[2] |= 0x80;
+        data[7] = 2;
+        for (ttl, ip) in [(300u32, [192, 0, 2, 1]), (60, [192, 0, 2, 2])] {
+            data.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+            data.extend_from_slice(&ttl.to_be_bytes());
+            data.extend_from_slice(&[0, 4]);
+            data.extend_from_slice(&ip);
+        }
+        let answer = parse_answer(7, &data).unwrap().unwrap();
+        assert_eq!(answer.addrs.len(), 2);
+        assert_eq!(answer.ttl, Some(Duration::from_secs(60)));
+        assert!(parse_answer(8, &data).is_err());
+    }
+
+    #[tokio::test]
+    async fn lookup_literal_and_hosts() {
+        let resolver = Resolver::builder().build();
+        assert_eq!(
+            resolver.lookup("[::1]").await.unwrap(),
+            vec![IpAddr::from(Ipv6Addr::LOCALHOST)]
+        );
+        assert_eq!(
+            resolver.lookup("192.0.2.1").await.unwrap(),
+            vec![IpAddr::from([192, 0, 2, 1])]
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_timeout_is_not_cached() {
+        // nothing listens on the discard port
+        let resolver = Resolver::builder()
+            .nameservers(vec!["127.0.0.1:9".parse().unwrap()])
+            .search(Vec::new())
+            .timeout(Duration::from_millis(50))
+            .attempts(1)
+            .negative_ttl(Duration::from_secs(60))
+            .build();
+        let err = resolver.lookup("example.invalid").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(resolver.inner.cache.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn lookup_name_error_is_cached() {
+        // name server which answers each query with NXDOMAIN
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let nameserver = socket.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_UDP_SIZE];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let mut data = buf[..len].to_vec();
+                data[2] |= 0x80;
+                data[3] = (data[3] & 0xf0) | 3;
+                socket.send_to(&data, peer).await.unwrap();
+            }
+        });
+        let resolver = Resolver::builder()
+            .nameservers(vec![nameserver])
+            .search(Vec::new())
+            .negative_ttl(Duration::from_secs(60))
+            .build();
+        let err = resolver.lookup("example.invalid").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(resolver.inner.cache.lock().unwrap().len(), 1);
+        server.abort();
+    }
+}
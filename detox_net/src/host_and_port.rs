@@ -29,6 +29,10 @@ pub enum Error {
 pub struct HostAndPort(String, u16);
 
 impl HostAndPort {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        HostAndPort(host.into(), port)
+    }
+
     pub fn try_from_uri(uri: &Uri) -> std::result::Result<HostAndPort, Error> {
         let Some(host) = uri.host() else {
             return Err(Error::NoHost);
@@ -18,7 +18,13 @@ struct BytesLost {
 }
 
 /// Calls tokio::io::copy_bidirectional but ignores some of the common errors.
-pub async fn copy_bidirectional<A, B>(upstream: &mut A, downstream: &mut B) -> std::io::Result<()>
+///
+/// Returns the number of bytes copied from `upstream` to `downstream` and the number of bytes
+/// copied from `downstream` to `upstream`.
+pub async fn copy_bidirectional<A, B>(
+    upstream: &mut A,
+    downstream: &mut B,
+) -> std::io::Result<(u64, u64)>
 where
     A: AsyncRead + AsyncWrite + Unpin + ?Sized,
     B: AsyncRead + AsyncWrite + Unpin + ?Sized,
@@ -39,10 +45,11 @@ where
 
     // Ignore errors which we cannot influence (e.g. peer is terminating the
     // connection without a clean shutdown/close)
+    let copied = (upstream_in, downstream_in);
     match cp {
-        Ok(_) => Ok(()),
+        Ok(_) => Ok(copied),
         Err(e) => match e.kind() {
-            ErrorKind::ConnectionReset | ErrorKind::BrokenPipe => Ok(()),
+            ErrorKind::ConnectionReset | ErrorKind::BrokenPipe => Ok(copied),
             ErrorKind::NotConnected => {
                 // https://github.com/tokio-rs/tokio/issues/4674
                 if bytes_lost {
@@ -55,7 +62,7 @@ where
                         source: e,
                     }))
                 } else {
-                    Ok(())
+                    Ok(copied)
                 }
             }
             _ => Err(e),
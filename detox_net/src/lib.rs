@@ -1,8 +1,10 @@
+pub mod dns;
 pub mod host_and_port;
 pub mod io;
 pub mod keepalive;
 pub mod metered;
 pub mod path_or_uri;
+pub mod socks;
 
 pub use host_and_port::HostAndPort;
 pub use io::copy_bidirectional;
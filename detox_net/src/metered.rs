@@ -1,27 +1,50 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{io::Result, pin::Pin, task, task::Poll};
 use tokio::io::{AsyncRead, AsyncWrite};
 
+/// Byte counters of a [`Metered`] stream, which can be read while the stream is in use.
+#[derive(Debug, Clone, Default)]
+pub struct Counters {
+    bytes_in: Arc<AtomicU64>,
+    bytes_out: Arc<AtomicU64>,
+}
+
+impl Counters {
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+}
+
 pub struct Metered<T> {
     stream: T,
-    bytes_in: u64,
-    bytes_out: u64,
+    counters: Counters,
 }
 
 impl<T> Metered<T> {
     pub fn new(stream: T) -> Self {
-        Self {
-            stream,
-            bytes_in: Default::default(),
-            bytes_out: Default::default(),
-        }
+        Self::with_counters(stream, Default::default())
+    }
+
+    /// Count the bytes of `stream` in `counters`, e.g. to share them with another task.
+    pub fn with_counters(stream: T, counters: Counters) -> Self {
+        Self { stream, counters }
+    }
+
+    pub fn counters(&self) -> &Counters {
+        &self.counters
     }
 
     pub fn bytes_read(&self) -> u64 {
-        self.bytes_in
+        self.counters.bytes_read()
     }
 
     pub fn bytes_written(&self) -> u64 {
-        self.bytes_out
+        self.counters.bytes_written()
     }
 }
 
@@ -37,7 +60,9 @@ where
         let this = self.get_mut();
         let result = Pin::new(&mut this.stream).poll_write(cx, buf);
         if let Poll::Ready(Ok(size)) = result {
-            *Pin::new(&mut this.bytes_out) += size as u64;
+            this.counters
+                .bytes_out
+                .fetch_add(size as u64, Ordering::Relaxed);
         }
         result
     }
@@ -64,7 +89,9 @@ where
         let filled = buf.filled().len();
         let result = Pin::new(&mut this.stream).poll_read(cx, buf);
         let filled = buf.filled().len() - filled;
-        *Pin::new(&mut this.bytes_in) += filled as u64;
+        this.counters
+            .bytes_in
+            .fetch_add(filled as u64, Ordering::Relaxed);
         result
     }
 }
@@ -6,6 +6,8 @@ use http::Uri;
 pub enum PathOrUri {
     Path(PathBuf),
     Uri(Uri),
+    /// Discover the URI via DHCP or DNS (Web Proxy Auto-Discovery, WPAD).
+    Auto,
 }
 
 impl Display for PathOrUri {
@@ -13,6 +15,7 @@ impl Display for PathOrUri {
         match self {
             Self::Path(p) => p.display().fmt(f),
             Self::Uri(u) => u.fmt(f),
+            Self::Auto => f.write_str("auto"),
         }
     }
 }
@@ -21,7 +24,9 @@ impl FromStr for PathOrUri {
     type Err = http::uri::InvalidUri;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.starts_with("http://") || s.starts_with("https://") {
+        if s == "auto" {
+            Ok(Self::Auto)
+        } else if s.starts_with("http://") || s.starts_with("https://") {
             Ok(Self::from(s.parse::<Uri>()?))
         } else {
             Ok(Self::from(PathBuf::from(s)))
@@ -65,6 +70,11 @@ mod tests {
             PathOrUri::Uri("https://example.org/index.html".parse::<Uri>()?),
             "https://example.org/index.html".parse::<PathOrUri>()?
         );
+        assert_eq!(PathOrUri::Auto, "auto".parse::<PathOrUri>()?);
+        assert_eq!(
+            PathOrUri::Path(PathBuf::from("./auto")),
+            "./auto".parse::<PathOrUri>()?
+        );
         Ok(())
     }
 }
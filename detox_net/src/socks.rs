@@ -0,0 +1,547 @@
+//! Client and server side of the SOCKS4a and SOCKS5 protocols.
+//!
+//! See [RFC 1928](https://www.rfc-editor.org/rfc/rfc1928) (SOCKS5),
+//! [RFC 1929](https://www.rfc-editor.org/rfc/rfc1929) (username/password authentication for
+//! SOCKS5), and [SOCKS4a](https://www.openssh.com/txt/socks4a.protocol).
+
+use crate::HostAndPort;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const SOCKS4_VERSION: u8 = 4;
+const SOCKS4_CMD_CONNECT: u8 = 1;
+const SOCKS4_REQUEST_GRANTED: u8 = 90;
+const SOCKS4_REQUEST_REJECTED: u8 = 91;
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_AUTH_NONE: u8 = 0x00;
+const SOCKS5_AUTH_PASSWORD: u8 = 0x02;
+const SOCKS5_AUTH_NO_ACCEPTABLE: u8 = 0xff;
+const SOCKS5_PASSWORD_VERSION: u8 = 1;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+const SOCKS5_REP_SUCCEEDED: u8 = 0;
+const SOCKS5_REP_FAILURE: u8 = 1;
+const SOCKS5_REP_CMD_NOT_SUPPORTED: u8 = 7;
+const SOCKS5_REP_ATYP_NOT_SUPPORTED: u8 = 8;
+
+/// Maximum length of the SOCKS4 user ID and SOCKS4a host name accepted by [`accept`].
+const SOCKS4_MAX_FIELD_LEN: usize = 255;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+    #[error("unexpected SOCKS version {0}")]
+    InvalidVersion(u8),
+    #[error("SOCKS4 request rejected with code {0}")]
+    Socks4Rejected(u8),
+    #[error("SOCKS5 request rejected: {0}")]
+    Socks5Rejected(&'static str),
+    #[error("no acceptable SOCKS5 authentication method")]
+    NoAcceptableAuthMethod,
+    #[error("SOCKS5 authentication failed")]
+    AuthenticationFailed,
+    #[error("invalid address type {0}")]
+    InvalidAddressType(u8),
+    #[error("{0} too long")]
+    TooLong(&'static str),
+    #[error("unsupported SOCKS command {0}")]
+    UnsupportedCommand(u8),
+}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            e => std::io::Error::other(e),
+        }
+    }
+}
+
+/// Username and password for SOCKS5 (and user ID for SOCKS4a) authentication.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+}
+
+/// Establish a tunnel to `dst` via a SOCKS4a proxy.
+///
+/// SOCKS4a has no password authentication, only the username of `credentials` is send as user
+/// ID. Host names are resolved by the proxy.
+pub async fn socks4a_connect<S>(
+    stream: &mut S,
+    dst: &HostAndPort,
+    credentials: Option<&Credentials>,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut req = vec![SOCKS4_VERSION, SOCKS4_CMD_CONNECT];
+    req.extend_from_slice(&dst.port().to_be_bytes());
+    let domain = match dst.host().parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.extend_from_slice(&ip.octets());
+            None
+        }
+        // SOCKS4a: an invalid IP of 0.0.0.x with x non-zero signals a domain name
+        _ => {
+            req.extend_from_slice(&[0, 0, 0, 1]);
+            Some(dst.host())
+        }
+    };
+    if let Some(c) = credentials {
+        req.extend_from_slice(c.username.as_bytes());
+    }
+    req.push(0);
+    if let Some(domain) = domain {
+        req.extend_from_slice(domain.as_bytes());
+        req.push(0);
+    }
+    stream.write_all(&req).await?;
+    stream.flush().await?;
+
+    let mut resp = [0u8; 8];
+    stream.read_exact(&mut resp).await?;
+    if resp[0] != 0 {
+        return Err(Error::InvalidVersion(resp[0]));
+    }
+    if resp[1] != SOCKS4_REQUEST_GRANTED {
+        return Err(Error::Socks4Rejected(resp[1]));
+    }
+    Ok(())
+}
+
+/// Establish a tunnel to `dst` via a SOCKS5 proxy.
+///
+/// When `credentials` are given, username/password authentication is offered to the proxy in
+/// addition to no authentication. Host names are resolved by the proxy.
+pub async fn socks5_connect<S>(
+    stream: &mut S,
+    dst: &HostAndPort,
+    credentials: Option<&Credentials>,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if credentials.is_some() {
+        stream
+            .write_all(&[SOCKS5_VERSION, 2, SOCKS5_AUTH_NONE, SOCKS5_AUTH_PASSWORD])
+            .await?;
+    } else {
+        stream
+            .write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE])
+            .await?;
+    }
+    stream.flush().await?;
+
+    let mut resp = [0u8; 2];
+    stream.read_exact(&mut resp).await?;
+    if resp[0] != SOCKS5_VERSION {
+        return Err(Error::InvalidVersion(resp[0]));
+    }
+    match (resp[1], credentials) {
+        (SOCKS5_AUTH_NONE, _) => {}
+        (SOCKS5_AUTH_PASSWORD, Some(c)) => socks5_password_auth(stream, c).await?,
+        _ => return Err(Error::NoAcceptableAuthMethod),
+    }
+
+    let mut req = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0];
+    match dst.host().parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.push(SOCKS5_ATYP_IPV4);
+            req.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            req.push(SOCKS5_ATYP_IPV6);
+            req.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let host = dst.host().as_bytes();
+            let len = u8::try_from(host.len()).map_err(|_| Error::TooLong("host name"))?;
+            req.push(SOCKS5_ATYP_DOMAIN);
+            req.push(len);
+            req.extend_from_slice(host);
+        }
+    }
+    req.extend_from_slice(&dst.port().to_be_bytes());
+    stream.write_all(&req).await?;
+    stream.flush().await?;
+
+    let mut resp = [0u8; 4];
+    stream.read_exact(&mut resp).await?;
+    if resp[0] != SOCKS5_VERSION {
+        return Err(Error::InvalidVersion(resp[0]));
+    }
+    if resp[1] != 0 {
+        return Err(Error::Socks5Rejected(socks5_reply_message(resp[1])));
+    }
+    // skip the bound address and port
+    let len = match resp[3] {
+        SOCKS5_ATYP_IPV4 => 4,
+        SOCKS5_ATYP_IPV6 => 16,
+        SOCKS5_ATYP_DOMAIN => stream.read_u8().await? as usize,
+        atyp => return Err(Error::InvalidAddressType(atyp)),
+    };
+    let mut bound = vec![0u8; len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+async fn socks5_password_auth<S>(stream: &mut S, credentials: &Credentials) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let username = credentials.username.as_bytes();
+    let password = credentials.password.as_bytes();
+    let ulen = u8::try_from(username.len()).map_err(|_| Error::TooLong("username"))?;
+    let plen = u8::try_from(password.len()).map_err(|_| Error::TooLong("password"))?;
+
+    let mut req = vec![SOCKS5_PASSWORD_VERSION, ulen];
+    req.extend_from_slice(username);
+    req.push(plen);
+    req.extend_from_slice(password);
+    stream.write_all(&req).await?;
+    stream.flush().await?;
+
+    let mut resp = [0u8; 2];
+    stream.read_exact(&mut resp).await?;
+    if resp[1] != 0 {
+        return Err(Error::AuthenticationFailed);
+    }
+    Ok(())
+}
+
+/// SOCKS version used by a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Socks4,
+    Socks5,
+}
+
+/// `CONNECT` request received by [`accept`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub version: Version,
+    pub dst: HostAndPort,
+}
+
+/// Server side of the SOCKS4, SOCKS4a, and SOCKS5 handshake until the `CONNECT` request.
+///
+/// Only SOCKS5 clients which offer no authentication are accepted. The client must be answered
+/// with [`reply`] once the tunnel is established (or failed).
+pub async fn accept<S>(stream: &mut S) -> Result<Request, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match stream.read_u8().await? {
+        SOCKS4_VERSION => socks4_accept(stream).await,
+        SOCKS5_VERSION => socks5_accept(stream).await,
+        version => Err(Error::InvalidVersion(version)),
+    }
+}
+
+/// Answer a request received by [`accept`].
+pub async fn reply<S>(stream: &mut S, version: Version, granted: bool) -> Result<(), Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    match version {
+        Version::Socks4 => {
+            let status = if granted {
+                SOCKS4_REQUEST_GRANTED
+            } else {
+                SOCKS4_REQUEST_REJECTED
+            };
+            stream.write_all(&[0, status, 0, 0, 0, 0, 0, 0]).await?;
+        }
+        Version::Socks5 => {
+            let rep = if granted {
+                SOCKS5_REP_SUCCEEDED
+            } else {
+                SOCKS5_REP_FAILURE
+            };
+            socks5_reply(stream, rep).await?;
+        }
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn socks4_accept<S>(stream: &mut S) -> Result<Request, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut req = [0u8; 7];
+    stream.read_exact(&mut req).await?;
+    let port = u16::from_be_bytes([req[1], req[2]]);
+    let ip = Ipv4Addr::new(req[3], req[4], req[5], req[6]);
+    // user ID is ignored
+    socks4_read_field(stream, "user ID").await?;
+    let host = if ip.octets()[..3] == [0, 0, 0] && ip.octets()[3] != 0 {
+        socks4_read_field(stream, "host name").await?
+    } else {
+        ip.to_string()
+    };
+    if req[0] != SOCKS4_CMD_CONNECT {
+        stream
+            .write_all(&[0, SOCKS4_REQUEST_REJECTED, 0, 0, 0, 0, 0, 0])
+            .await?;
+        return Err(Error::UnsupportedCommand(req[0]));
+    }
+    Ok(Request {
+        version: Version::Socks4,
+        dst: HostAndPort::new(host, port),
+    })
+}
+
+/// Null terminated string of a SOCKS4 request.
+async fn socks4_read_field<S>(stream: &mut S, what: &'static str) -> Result<String, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut field = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            0 => break,
+            _ if field.len() >= SOCKS4_MAX_FIELD_LEN => return Err(Error::TooLong(what)),
+            c => field.push(c),
+        }
+    }
+    Ok(String::from_utf8_lossy(&field).into_owned())
+}
+
+async fn socks5_accept<S>(stream: &mut S) -> Result<Request, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let n = stream.read_u8().await?;
+    let mut methods = vec![0u8; n as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_AUTH_NONE) {
+        stream
+            .write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_NO_ACCEPTABLE])
+            .await?;
+        return Err(Error::NoAcceptableAuthMethod);
+    }
+    stream
+        .write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_NONE])
+        .await?;
+    stream.flush().await?;
+
+    let mut req = [0u8; 4];
+    stream.read_exact(&mut req).await?;
+    if req[0] != SOCKS5_VERSION {
+        return Err(Error::InvalidVersion(req[0]));
+    }
+    let host = match req[3] {
+        SOCKS5_ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        SOCKS5_ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        SOCKS5_ATYP_DOMAIN => {
+            let n = stream.read_u8().await?;
+            let mut host = vec![0u8; n as usize];
+            stream.read_exact(&mut host).await?;
+            String::from_utf8_lossy(&host).into_owned()
+        }
+        atyp => {
+            socks5_reply(stream, SOCKS5_REP_ATYP_NOT_SUPPORTED).await?;
+            return Err(Error::InvalidAddressType(atyp));
+        }
+    };
+    let port = stream.read_u16().await?;
+    if req[1] != SOCKS5_CMD_CONNECT {
+        socks5_reply(stream, SOCKS5_REP_CMD_NOT_SUPPORTED).await?;
+        return Err(Error::UnsupportedCommand(req[1]));
+    }
+    Ok(Request {
+        version: Version::Socks5,
+        dst: HostAndPort::new(host, port),
+    })
+}
+
+/// Reply with an unspecified bound address.
+async fn socks5_reply<S>(stream: &mut S, rep: u8) -> Result<(), Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream
+        .write_all(&[SOCKS5_VERSION, rep, 0, SOCKS5_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+fn socks5_reply_message(rep: u8) -> &'static str {
+    match rep {
+        1 => "general SOCKS server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn socks4a_domain() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let dst = "example.org:443".parse::<HostAndPort>().unwrap();
+        let server = tokio::spawn(async move {
+            let mut req = vec![0u8; 8 + 5 + 12];
+            server.read_exact(&mut req).await.unwrap();
+            server.write_all(&[0, 90, 0, 0, 0, 0, 0, 0]).await.unwrap();
+            req
+        });
+        let creds = Credentials::new("user", "secret");
+        socks4a_connect(&mut client, &dst, Some(&creds))
+            .await
+            .unwrap();
+        let req = server.await.unwrap();
+        assert_eq!(&req[..8], &[4, 1, 1, 187, 0, 0, 0, 1]);
+        assert_eq!(&req[8..], b"user\0example.org\0");
+    }
+
+    #[tokio::test]
+    async fn socks4a_rejected() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let dst = "127.0.0.1:80".parse::<HostAndPort>().unwrap();
+        tokio::spawn(async move {
+            let mut req = vec![0u8; 9];
+            server.read_exact(&mut req).await.unwrap();
+            server.write_all(&[0, 91, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        });
+        let result = socks4a_connect(&mut client, &dst, None).await;
+        assert!(matches!(result, Err(Error::Socks4Rejected(91))));
+    }
+
+    #[tokio::test]
+    async fn socks5_password() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let dst = "example.org:80".parse::<HostAndPort>().unwrap();
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            server.write_all(&[5, 2]).await.unwrap();
+            let mut auth = [0u8; 3 + 4 + 6];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x06secret");
+            server.write_all(&[1, 0]).await.unwrap();
+            let mut req = [0u8; 5 + 11 + 2];
+            server.read_exact(&mut req).await.unwrap();
+            assert_eq!(&req, b"\x05\x01\x00\x03\x0bexample.org\x00\x50");
+            server
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0x1f, 0x90])
+                .await
+                .unwrap();
+        });
+        let creds = Credentials::new("user", "secret");
+        socks5_connect(&mut client, &dst, Some(&creds))
+            .await
+            .unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks5_refused() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let dst = "127.0.0.1:80".parse::<HostAndPort>().unwrap();
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server.write_all(&[5, 0]).await.unwrap();
+            let mut req = [0u8; 10];
+            server.read_exact(&mut req).await.unwrap();
+            assert_eq!(req, [5, 1, 0, 1, 127, 0, 0, 1, 0, 80]);
+            server
+                .write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+        let result = socks5_connect(&mut client, &dst, None).await;
+        assert!(matches!(
+            result,
+            Err(Error::Socks5Rejected("connection refused"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn socks4a_accept() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let dst = "example.org:443".parse::<HostAndPort>().unwrap();
+        let server = tokio::spawn(async move {
+            let request = accept(&mut server).await.unwrap();
+            reply(&mut server, request.version, true).await.unwrap();
+            request
+        });
+        let creds = Credentials::new("user", "secret");
+        socks4a_connect(&mut client, &dst, Some(&creds))
+            .await
+            .unwrap();
+        let request = server.await.unwrap();
+        assert_eq!(request.version, Version::Socks4);
+        assert_eq!(request.dst, dst);
+    }
+
+    #[tokio::test]
+    async fn socks5_accept() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let dst = HostAndPort::new("::1", 8080);
+        let server = tokio::spawn(async move {
+            let request = accept(&mut server).await.unwrap();
+            reply(&mut server, request.version, false).await.unwrap();
+            request
+        });
+        let result = socks5_connect(&mut client, &dst, None).await;
+        assert!(matches!(
+            result,
+            Err(Error::Socks5Rejected("general SOCKS server failure"))
+        ));
+        let request = server.await.unwrap();
+        assert_eq!(request.version, Version::Socks5);
+        assert_eq!(request.dst, dst);
+    }
+
+    #[tokio::test]
+    async fn socks5_accept_password_only() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        tokio::spawn(async move {
+            client.write_all(&[5, 1, 2]).await.unwrap();
+            let mut resp = [0u8; 2];
+            client.read_exact(&mut resp).await.unwrap();
+            assert_eq!(resp, [5, 0xff]);
+        });
+        let result = accept(&mut server).await;
+        assert!(matches!(result, Err(Error::NoAcceptableAuthMethod)));
+    }
+}
@@ -46,7 +46,7 @@ impl Client {
         );
 
         let conn = timeout(self.timeout, conn.into_future()).await??;
-        let request_sender = conn.handshake().await?;
+        let mut request_sender = conn.handshake().await?;
         let resp = timeout(self.timeout, request_sender.send_request(req)).await??;
         let (parts, body) = resp.into_parts();
         if parts.status != StatusCode::OK {
@@ -0,0 +1,270 @@
+//! Cache for the results of `FindProxyForURL`.
+
+use http::Uri;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::Proxies;
+
+/// Least recently used cache of `FindProxyForURL` results which expire after a fixed time.
+///
+/// The results are cached per scheme and host, unless the PAC script uses the `url` argument of
+/// `FindProxyForURL`, then the full URL is used as key.
+#[derive(Debug)]
+pub struct Cache {
+    ttl: Duration,
+    capacity: usize,
+    key_by_url: bool,
+    generation: u64,
+    tick: u64,
+    entries: HashMap<String, Entry>,
+    /// Keys ordered by their last use, the least recently used first.
+    order: BTreeMap<u64, String>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    proxies: Proxies,
+    expires: Instant,
+    tick: u64,
+}
+
+impl Cache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            key_by_url: true,
+            generation: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Returns `true` if results are cached at all.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0 && !self.ttl.is_zero()
+    }
+
+    /// Counter which is incremented on each [`Cache::clear`].
+    ///
+    /// Results evaluated before a flush must not be inserted afterwards.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Remove all entries, e.g. after the PAC script or the IP address changed.
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    /// Remove all entries and select the key for the new PAC script.
+    pub fn reset(&mut self, pac_script: Option<&str>) {
+        self.clear();
+        self.key_by_url = pac_script.is_some_and(uses_url);
+    }
+
+    pub fn key(&self, uri: &Uri) -> Option<String> {
+        if self.key_by_url {
+            Some(uri.to_string())
+        } else {
+            let scheme = uri.scheme_str().unwrap_or("http");
+            let host = uri.host()?;
+            Some(format!("{scheme}://{}", host.to_ascii_lowercase()))
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<Proxies> {
+        let now = Instant::now();
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires <= now {
+            let tick = entry.tick;
+            self.entries.remove(key);
+            self.order.remove(&tick);
+            return None;
+        }
+        self.tick += 1;
+        self.order.remove(&entry.tick);
+        self.order.insert(self.tick, key.to_owned());
+        entry.tick = self.tick;
+        Some(entry.proxies.clone())
+    }
+
+    /// Insert a result which was evaluated while [`Cache::generation`] was `generation`.
+    pub fn insert(&mut self, generation: u64, key: String, proxies: Proxies) {
+        if !self.is_enabled() || generation != self.generation {
+            return;
+        }
+        self.tick += 1;
+        let entry = Entry {
+            proxies,
+            expires: Instant::now() + self.ttl,
+            tick: self.tick,
+        };
+        if let Some(old) = self.entries.insert(key.clone(), entry) {
+            self.order.remove(&old.tick);
+        }
+        self.order.insert(self.tick, key);
+        while self.entries.len() > self.capacity {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&key);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Returns `true` unless the `url` arguments of `FindProxyForURL` and `FindProxyForURLEx` are
+/// provably unused.
+fn uses_url(pac_script: &str) -> bool {
+    let mut params = Vec::new();
+    for name in ["FindProxyForURL", "FindProxyForURLEx"] {
+        match url_param(pac_script, name) {
+            Some(Some(param)) => params.push(param),
+            Some(None) => return true,
+            None => {}
+        }
+    }
+    if params.is_empty() {
+        return true;
+    }
+    // the parameter declarations themselves are occurrences too
+    params.iter().any(|param| {
+        let declarations = params.iter().filter(|p| *p == param).count();
+        count_word(pac_script, param) > declarations
+    })
+}
+
+/// The name of the first parameter of the function declaration `name`, `None` if there is no
+/// such declaration and `Some(None)` if the declaration cannot be analyzed.
+fn url_param<'a>(pac_script: &'a str, name: &str) -> Option<Option<&'a str>> {
+    let decl = format!("function {name}");
+    let (pos, _) = pac_script.match_indices(&decl).find(|(i, _)| {
+        !pac_script[i + decl.len()..]
+            .chars()
+            .next()
+            .is_some_and(is_ident_char)
+    })?;
+    let params = pac_script[pos + decl.len()..].trim_start();
+    let Some(params) = params.strip_prefix('(') else {
+        return Some(None);
+    };
+    let param = params
+        .split([',', ')'])
+        .next()
+        .map(str::trim)
+        .unwrap_or_default();
+    if param.is_empty() || !param.chars().all(is_ident_char) {
+        return Some(None);
+    }
+    Some(Some(param))
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+fn count_word(haystack: &str, word: &str) -> usize {
+    haystack
+        .match_indices(word)
+        .filter(|(i, _)| {
+            let before = haystack[..*i].chars().next_back();
+            let after = haystack[i + word.len()..].chars().next();
+            !before.is_some_and(is_ident_char) && !after.is_some_and(is_ident_char)
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_url_test() {
+        assert!(!uses_url(
+            "function FindProxyForURL(url, host) { return \"DIRECT\"; }"
+        ));
+        assert!(!uses_url(
+            "function FindProxyForURL(u, host) { var url2 = host; return \"DIRECT\"; }"
+        ));
+        assert!(uses_url(
+            "function FindProxyForURL(url, host) { if (shExpMatch(url, \"*.pdf\")) return \"DIRECT\"; }"
+        ));
+        assert!(uses_url(
+            "var FindProxyForURL = function(url, host) { return \"DIRECT\"; }"
+        ));
+    }
+
+    #[test]
+    fn uses_url_ex_test() {
+        // the engine prefers FindProxyForURLEx
+        assert!(uses_url(
+            "function FindProxyForURL(url, host) { return \"DIRECT\"; }\n\
+             function FindProxyForURLEx(u, host) { if (shExpMatch(u, \"*.pdf\")) return \"DIRECT\"; return \"PROXY p:8080\"; }"
+        ));
+        assert!(uses_url(
+            "function FindProxyForURLEx(url, host) { if (shExpMatch(url, \"*.pdf\")) return \"DIRECT\"; }\n\
+             function FindProxyForURL(url, host) { return \"DIRECT\"; }"
+        ));
+        assert!(!uses_url(
+            "function FindProxyForURL(url, host) { return \"DIRECT\"; }\n\
+             function FindProxyForURLEx(url, host) { return \"DIRECT\"; }"
+        ));
+        assert!(!uses_url(
+            "function FindProxyForURLEx(url, host) { return \"DIRECT\"; }"
+        ));
+    }
+
+    #[test]
+    fn cache_key() {
+        let mut cache = Cache::new(Duration::from_secs(60), 8);
+        let uri = "https://Example.org/path?q".parse::<Uri>().unwrap();
+        assert_eq!(cache.key(&uri).unwrap(), "https://Example.org/path?q");
+        cache.reset(Some(
+            "function FindProxyForURL(url, host) { return \"DIRECT\"; }",
+        ));
+        assert_eq!(cache.key(&uri).unwrap(), "https://example.org");
+    }
+
+    #[test]
+    fn cache_lru() {
+        let mut cache = Cache::new(Duration::from_secs(60), 2);
+        let g = cache.generation();
+        cache.insert(g, "a".into(), Proxies::direct());
+        cache.insert(g, "b".into(), Proxies::direct());
+        assert!(cache.get("a").is_some());
+        cache.insert(g, "c".into(), Proxies::direct());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn cache_ttl_and_clear() {
+        let mut cache = Cache::new(Duration::from_millis(1), 2);
+        let g = cache.generation();
+        cache.insert(g, "a".into(), Proxies::direct());
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get("a").is_none());
+        assert!(cache.is_empty());
+
+        let mut cache = Cache::new(Duration::from_secs(60), 2);
+        let g = cache.generation();
+        cache.clear();
+        // evaluated before the flush
+        cache.insert(g, "a".into(), Proxies::direct());
+        assert!(cache.is_empty());
+    }
+}
@@ -1,89 +1,59 @@
-use std::{collections::HashMap, time::UNIX_EPOCH};
+use std::sync::Arc;
 
-use boa_engine::{JsData, class::Class};
+use boa_engine::JsData;
 use boa_gc::{Finalize, Trace};
+use detox_net::dns::Resolver;
 use tracing::instrument;
 
-pub type DnsMap = HashMap<String, (Option<String>, u64)>;
-
-#[derive(Default, Debug, Trace, Finalize, JsData)]
-pub struct DnsCache {
-    map: DnsMap,
-    cleanup_ttl: u64,
+/// Resolves host names for `dnsResolve` and friends.
+///
+/// The PAC script is evaluated synchronously, therefore the thread of the engine blocks on the
+/// asynchronous [`Resolver`] using its own single threaded runtime. The cache of the resolver
+/// is shared with all clones of it.
+#[derive(Clone, Debug, Trace, Finalize, JsData)]
+pub struct Dns {
+    #[unsafe_ignore_trace]
+    resolver: Resolver,
+    #[unsafe_ignore_trace]
+    runtime: Arc<tokio::runtime::Runtime>,
 }
 
-impl DnsCache {
-    #[instrument(skip(self))]
-    pub fn lookup(&mut self, host: &str) -> Option<String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let ttl = now + std::time::Duration::from_secs(5 * 60).as_secs();
-
-        let resolve_and_insert = |map: &mut DnsMap, host: &str| -> Option<String> {
-            let addr = resolve(host);
-            map.insert(host.into(), (addr.clone(), ttl));
-            addr
-        };
-
-        let result = if let Some(result) = self.map.get(host) {
-            if result.1 < now {
-                let addr = resolve_and_insert(&mut self.map, host);
-                tracing::trace!(?addr, "expired");
-                addr
-            } else {
-                let addr = result.0.clone();
-                tracing::trace!(?addr, "hit");
-                addr
-            }
-        } else {
-            let addr = resolve_and_insert(&mut self.map, host);
-            tracing::trace!(?addr, "miss");
-            addr
-        };
-
-        if self.cleanup_ttl < now {
-            self.cleanup(now);
-            self.cleanup_ttl = ttl;
-        };
-
-        result
+impl Dns {
+    pub fn new(resolver: Resolver) -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("DNS runtime");
+        Self {
+            resolver,
+            runtime: Arc::new(runtime),
+        }
     }
 
-    fn cleanup(&mut self, now: u64) {
-        self.map.retain(|_, v| v.1 > now);
+    pub fn resolver(&self) -> &Resolver {
+        &self.resolver
     }
 
-    pub fn map(&self) -> DnsMap {
-        self.map.clone()
+    /// First IP address of `host`.
+    pub fn lookup(&self, host: &str) -> Option<String> {
+        self.lookup_all(host).into_iter().next()
     }
-}
 
-impl Class for DnsCache {
-    const NAME: &'static str = "_DnsCache";
-
-    fn data_constructor(
-        _this: &boa_engine::JsValue,
-        _args: &[boa_engine::JsValue],
-        _context: &mut boa_engine::Context,
-    ) -> boa_engine::JsResult<Self> {
-        Ok(Default::default())
-    }
-
-    fn init(_class: &mut boa_engine::class::ClassBuilder) -> boa_engine::JsResult<()> {
-        Ok(())
+    /// All IPv4 and IPv6 addresses of `host`.
+    #[instrument(skip(self))]
+    pub fn lookup_all(&self, host: &str) -> Vec<String> {
+        match self.runtime.block_on(self.resolver.lookup(host)) {
+            Ok(addrs) => addrs.iter().map(|ip| ip.to_string()).collect(),
+            Err(cause) => {
+                tracing::trace!(%cause, "not resolvable");
+                Vec::new()
+            }
+        }
     }
 }
 
-// Resolve the host name and return the IP address as string, if resolvable.
-pub(crate) fn resolve(host: &str) -> Option<String> {
-    use std::net::ToSocketAddrs;
-
-    (host, 0u16)
-        .to_socket_addrs()
-        .ok()
-        .and_then(|mut a| a.next())
-        .map(|a| a.ip())
-        .map(|ip| ip.to_string())
+impl Default for Dns {
+    fn default() -> Self {
+        Self::new(Resolver::default())
+    }
 }
@@ -1,6 +1,8 @@
 use boa_engine::{
-    Context, JsError, JsNativeError, JsResult, JsString, JsValue, NativeFunction, Source, js_string,
+    Context, JsError, JsNativeError, JsObject, JsResult, JsString, JsValue, NativeFunction, Source,
+    js_string,
 };
+use detox_net::dns::Resolver;
 use http::Uri;
 use std::convert::Infallible;
 use std::net::IpAddr;
@@ -9,22 +11,45 @@ use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use tracing::{field::debug, instrument};
 
-use crate::dns::DnsCache;
+use crate::dns::Dns;
 use crate::{FindProxyError, PacScriptError};
-use crate::{Proxies, domain};
+use crate::{Proxies, domain, extensions};
 
 const PAC_UTILS: &str = include_str!("pac_utils.js");
 
+/// Limits for the execution of the PAC script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of loop iterations within one function call.
+    pub loop_iterations: u64,
+    /// Maximum depth of nested function calls.
+    pub recursion: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            loop_iterations: 1_000_000,
+            recursion: 512,
+        }
+    }
+}
+
 pub struct Engine {
     js: Context,
     my_ip_addr: Arc<Mutex<IpAddr>>,
+    limits: Limits,
+    dns: Dns,
 }
 
 impl Engine {
-    fn mkjs(my_ip_addr: Arc<Mutex<IpAddr>>) -> Context {
+    fn mkjs(my_ip_addr: Arc<Mutex<IpAddr>>, limits: Limits, dns: Dns) -> Context {
         let mut js = Context::default();
+        js.runtime_limits_mut()
+            .set_loop_iteration_limit(limits.loop_iterations);
+        js.runtime_limits_mut()
+            .set_recursion_limit(limits.recursion);
 
-        js.register_global_class::<DnsCache>().unwrap();
         js.register_global_class::<domain::Table>().unwrap();
         js.register_global_builtin_callable(
             js_string!("alert"),
@@ -59,12 +84,11 @@ impl Engine {
             .expect("register_global_property");
         }
 
-        let dns_cache = js
-            .eval(Source::from_bytes("new _DnsCache();"))
-            .expect("new DnsCache()");
+        extensions::register(&mut js, &my_ip_addr);
+
         js.register_global_property(
-            js_string!("_dnsCache"),
-            dns_cache,
+            js_string!("_dns"),
+            JsObject::from_proto_and_data(None, dns),
             boa_engine::property::Attribute::all(),
         )
         .expect("register_global_property");
@@ -80,9 +104,16 @@ impl Engine {
         let my_ip_addr = Arc::new(Mutex::new(IpAddr::from(std::net::Ipv4Addr::new(
             127, 0, 0, 1,
         ))));
-        let js = Self::mkjs(my_ip_addr.clone());
+        let limits = Limits::default();
+        let dns = Dns::default();
+        let js = Self::mkjs(my_ip_addr.clone(), limits, dns.clone());
 
-        Self { js, my_ip_addr }
+        Self {
+            js,
+            my_ip_addr,
+            limits,
+            dns,
+        }
     }
 
     pub fn with_pac_script(pac_script: &str) -> Result<Self, PacScriptError> {
@@ -91,8 +122,22 @@ impl Engine {
         Ok(new)
     }
 
+    /// Set the limits for the execution of the PAC script.
+    ///
+    /// Takes effect with the next call of [`Engine::set_pac_script`].
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Set the resolver used by `dnsResolve` and the related functions.
+    ///
+    /// Takes effect with the next call of [`Engine::set_pac_script`].
+    pub fn set_resolver(&mut self, resolver: Resolver) {
+        self.dns = Dns::new(resolver);
+    }
+
     pub fn set_pac_script(&mut self, pac_script: Option<&str>) -> Result<(), PacScriptError> {
-        self.js = Self::mkjs(self.my_ip_addr.clone());
+        self.js = Self::mkjs(self.my_ip_addr.clone(), self.limits, self.dns.clone());
         let pac_script = pac_script.unwrap_or(crate::DEFAULT_PAC_SCRIPT);
         self.js
             .eval(Source::from_bytes(pac_script))
@@ -112,19 +157,32 @@ impl Engine {
         let host = uri.host().ok_or(FindProxyError::NoHost)?;
 
         let start = Instant::now();
-        let find_proxy_fn = self
-            .js
-            .global_object()
-            .get(js_string!("FindProxyForURL"), &mut self.js)
+        // the IPv6 aware variant is preferred, see
+        // https://learn.microsoft.com/en-us/windows/win32/winhttp/ipv6-aware-proxy-helper-api-definitions
+        let global = self.js.global_object();
+        let find_proxy_ex_fn = global
+            .get(js_string!("FindProxyForURLEx"), &mut self.js)
             .map_err(|e| FindProxyError::InternalError(e.to_string()))?
-            .as_object()
-            .ok_or(FindProxyError::FindProxyForURLMissing)?;
+            .as_callable();
+        let find_proxy_fn = match find_proxy_ex_fn {
+            Some(f) => f,
+            None => global
+                .get(js_string!("FindProxyForURL"), &mut self.js)
+                .map_err(|e| FindProxyError::InternalError(e.to_string()))?
+                .as_object()
+                .ok_or(FindProxyError::FindProxyForURLMissing)?,
+        };
 
         let uri = JsValue::from(JsString::from(uri.to_string()));
         let host = JsValue::from(JsString::from(host));
         let proxy = find_proxy_fn
             .call(&JsValue::null(), &[uri, host], &mut self.js)
-            .map_err(|e| FindProxyError::InternalError(e.to_string()));
+            .map_err(|e| match e.as_native() {
+                Some(e) if e.is_runtime_limit() => {
+                    FindProxyError::RuntimeLimitExceeded(e.message().to_owned())
+                }
+                _ => FindProxyError::InternalError(e.to_string()),
+            });
         tracing::Span::current().record("duration", debug(&start.elapsed()));
 
         let proxy = proxy?;
@@ -165,32 +223,42 @@ fn alert(_this: &JsValue, args: &[JsValue], _ctx: &mut Context) -> JsResult<JsVa
 }
 
 fn dns_resolve(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = string_arg(args, 0, context)?;
+    let resolved = dns_lookup_all(&host, context).into_iter().next();
+    let value = match resolved {
+        Some(ip) => JsValue::from(JsString::from(ip)),
+        None => JsValue::null(),
+    };
+
+    Ok(value)
+}
+
+/// All IP addresses of `host` via the resolver of the engine.
+pub(crate) fn dns_lookup_all(host: &str, context: &mut Context) -> Vec<String> {
     let global = context.global_object();
-    let dns_cache = global
-        .get(js_string!("_dnsCache"), context)
-        .expect("_dnsCache");
-    let dns_cache = dns_cache.as_object().expect("object DnsCache");
-    let mut dns_cache = dns_cache
-        .downcast_mut::<DnsCache>()
-        .expect("downcast_mut<DnsCache>");
-
-    let Some(host) = args.first() else {
+    let dns = global.get(js_string!("_dns"), context).expect("_dns");
+    let dns = dns.as_object().expect("object Dns");
+    let dns = dns.downcast_ref::<Dns>().expect("downcast_ref<Dns>");
+    dns.lookup_all(host)
+}
+
+/// The argument at `index` converted to a string.
+pub(crate) fn string_arg(
+    args: &[JsValue],
+    index: usize,
+    context: &mut Context,
+) -> JsResult<String> {
+    let Some(arg) = args.get(index) else {
         return Err(JsNativeError::typ()
-            .with_message("first argument is missing")
+            .with_message(format!("argument {} is missing", index + 1))
             .into());
     };
-    let Ok(host) = host.to_string(context) else {
+    let Ok(arg) = arg.to_string(context) else {
         return Err(JsNativeError::typ()
-            .with_message("first argument must be string")
+            .with_message(format!("argument {} must be string", index + 1))
             .into());
     };
-    let resolved = dns_cache.lookup(&host.to_std_string_escaped());
-    let value = match resolved {
-        Some(ip) => JsValue::from(JsString::from(ip)),
-        None => JsValue::null(),
-    };
-
-    Ok(value)
+    Ok(arg.to_std_string_escaped())
 }
 
 fn sh_exp_match(_this: &JsValue, args: &[JsValue], _ctx: &mut Context) -> JsResult<JsValue> {
@@ -228,8 +296,9 @@ fn my_ip_address(
 
 #[cfg(test)]
 mod tests {
-    use super::Engine;
     use super::Uri;
+    use super::{Engine, Limits};
+    use crate::FindProxyError;
     use crate::Proxies;
     use crate::ProxyOrDirect;
 
@@ -243,6 +312,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_runtime_limits() -> Result<(), Box<dyn std::error::Error>> {
+        let mut eval = Engine::new();
+        eval.set_limits(Limits {
+            loop_iterations: 1000,
+            recursion: 64,
+        });
+        eval.set_pac_script(Some(
+            "function FindProxyForURL(url, host) { while (true) {} }",
+        ))?;
+        assert!(matches!(
+            eval.find_proxy(&"http://localhost/".parse::<Uri>().unwrap()),
+            Err(FindProxyError::RuntimeLimitExceeded(_))
+        ));
+        eval.set_pac_script(Some(
+            "function f(n) { return f(n + 1); } function FindProxyForURL(url, host) { return f(0); }",
+        ))?;
+        assert!(matches!(
+            eval.find_proxy(&"http://localhost/".parse::<Uri>().unwrap()),
+            Err(FindProxyError::RuntimeLimitExceeded(_))
+        ));
+        Ok(())
+    }
+
     #[test]
     fn test_alert() -> Result<(), Box<dyn std::error::Error>> {
         let mut eval = Engine::with_pac_script(
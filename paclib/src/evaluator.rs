@@ -1,17 +1,62 @@
 use http::Uri;
 use std::convert::Infallible;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
+use std::time::Duration;
 use tokio::sync::oneshot;
 
 use crate::Proxies;
-use crate::engine::Engine;
+use crate::cache::Cache;
+use crate::engine::{Engine, Limits};
 use crate::{FindProxyError, PacScriptError};
+use detox_net::dns::Resolver;
 
+/// Evaluates the PAC script on a pool of worker threads, each with its own [`Engine`].
+///
+/// A worker which exceeds the timeout is replaced by a new thread, while the old thread keeps
+/// running until the script terminates. At most as many replaced threads as workers are kept,
+/// beyond that a stuck worker is not used until its script terminates.
 pub struct Evaluator {
-    _worker: Arc<thread::JoinHandle<()>>,
-    sender: Mutex<Option<mpsc::Sender<Action>>>,
+    workers: Vec<Worker>,
+    cache: Mutex<Cache>,
+    config: Mutex<Config>,
+    timeout: Duration,
+    /// Number of replaced threads which are still running.
+    replaced: Arc<AtomicUsize>,
+}
+
+/// State of the engines, such that a worker can be restarted.
+#[derive(Debug, Clone)]
+struct Config {
+    pac_script: Option<String>,
+    my_ip_address: Option<IpAddr>,
+    limits: Limits,
+    resolver: Resolver,
+}
+
+struct Worker {
+    index: usize,
+    handle: Mutex<WorkerHandle>,
+}
+
+#[derive(Clone)]
+struct WorkerHandle {
+    sender: Option<mpsc::Sender<Action>>,
+    /// Number of actions send to this worker, which are not finished yet.
+    pending: Arc<AtomicUsize>,
+    /// Exceeded the timeout but was not replaced, no new actions are send until it finishes.
+    stuck: Arc<AtomicBool>,
+    /// Replaced by a new thread, terminates once its current action finishes.
+    retired: Arc<AtomicBool>,
+}
+
+/// Why no result was received from a worker.
+#[derive(Debug)]
+enum WaitError {
+    Timeout(Duration),
+    Terminated,
 }
 
 type FindProxyResult = Result<Proxies, FindProxyError>;
@@ -24,92 +69,408 @@ enum Action {
     SetMyIpAddress(IpAddr, oneshot::Sender<SetMyIpAddressResult>),
 }
 
-impl Evaluator {
-    pub fn new() -> Self {
-        let (sender, receiver) = mpsc::channel::<Action>();
+#[derive(Debug)]
+pub struct Builder {
+    pac_script: Option<String>,
+    workers: usize,
+    cache_ttl: Duration,
+    cache_size: usize,
+    limits: Limits,
+    timeout: Duration,
+    resolver: Option<Resolver>,
+}
 
-        let worker = thread::Builder::new()
-            .name("pac-eval-worker".into())
-            .spawn(move || Self::run(receiver, None))
-            .expect("create thread");
+impl Builder {
+    /// PAC script to use, if `None` FindProxy will evaluate to DIRECT.
+    pub fn pac_script(mut self, pac_script: Option<String>) -> Self {
+        self.pac_script = pac_script;
+        self
+    }
+
+    /// Number of worker threads which evaluate the PAC script in parallel.
+    pub fn workers(mut self, num: usize) -> Self {
+        self.workers = num;
+        self
+    }
+
+    /// Cache the results of `FindProxyForURL` for `ttl`, a zero duration disables the cache.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Maximum number of cached results, the least recently used results are removed first.
+    pub fn cache_size(mut self, num: usize) -> Self {
+        self.cache_size = num;
+        self
+    }
+
+    /// Loop iteration and recursion limits of the PAC script.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Maximum time of one `FindProxyForURL` call. The worker is replaced by a new one when the
+    /// time is exceeded.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Resolver used by `dnsResolve` and the related functions, shared by all workers.
+    pub fn resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    pub fn build(self) -> Evaluator {
+        let mut cache = Cache::new(self.cache_ttl, self.cache_size);
+        cache.reset(self.pac_script.as_deref());
+        let config = Config {
+            pac_script: self.pac_script,
+            my_ip_address: None,
+            limits: self.limits,
+            resolver: self.resolver.unwrap_or_default(),
+        };
+        let replaced = Arc::new(AtomicUsize::new(0));
+        let workers = (0..self.workers.max(1))
+            .map(|index| Worker {
+                index,
+                handle: Mutex::new(WorkerHandle::spawn(index, config.clone(), &replaced)),
+            })
+            .collect();
+        Evaluator {
+            workers,
+            cache: Mutex::new(cache),
+            config: Mutex::new(config),
+            timeout: self.timeout,
+            replaced,
+        }
+    }
+}
 
+impl Default for Builder {
+    fn default() -> Self {
         Self {
-            _worker: Arc::new(worker),
-            sender: Mutex::new(Some(sender)),
+            pac_script: None,
+            workers: 1,
+            cache_ttl: Duration::ZERO,
+            cache_size: 1024,
+            limits: Limits::default(),
+            timeout: Duration::from_secs(10),
+            resolver: None,
         }
     }
+}
+
+impl Worker {
+    fn handle(&self) -> WorkerHandle {
+        self.handle.lock().unwrap().clone()
+    }
 
-    pub fn with_pac_script(pac_script: &str) -> Result<Self, PacScriptError> {
-        let pac_script = pac_script.to_owned();
+    /// Replace the thread of `stuck` by a new one, unless this was already done.
+    ///
+    /// Returns false if the thread can not be replaced, since already `max` replaced threads are
+    /// running.
+    fn restart(
+        &self,
+        stuck: &WorkerHandle,
+        config: Config,
+        replaced: &Arc<AtomicUsize>,
+        max: usize,
+    ) -> bool {
+        let mut handle = self.handle.lock().unwrap();
+        if !Arc::ptr_eq(&handle.pending, &stuck.pending) {
+            return true;
+        }
+        if replaced
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .is_err()
+        {
+            return false;
+        }
+        handle.retired.store(true, Ordering::SeqCst);
+        *handle = WorkerHandle::spawn(self.index, config, replaced);
+        true
+    }
+}
+
+impl WorkerHandle {
+    fn spawn(index: usize, config: Config, replaced: &Arc<AtomicUsize>) -> Self {
         let (sender, receiver) = mpsc::channel::<Action>();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let stuck = Arc::new(AtomicBool::new(false));
+        let retired = Arc::new(AtomicBool::new(false));
 
-        let worker = thread::Builder::new()
-            .name("pac-eval-worker".into())
-            .spawn(move || Self::run(receiver, Some(pac_script)))
+        thread::Builder::new()
+            .name(format!("pac-eval-worker-{index}"))
+            .spawn({
+                let pending = pending.clone();
+                let stuck = stuck.clone();
+                let retired = retired.clone();
+                let replaced = replaced.clone();
+                move || {
+                    Self::run(receiver, config, &pending, &stuck);
+                    if retired.load(Ordering::SeqCst) {
+                        replaced.fetch_sub(1, Ordering::SeqCst);
+                    }
+                }
+            })
             .expect("create thread");
 
-        let new = Self {
-            _worker: Arc::new(worker),
-            sender: Mutex::new(Some(sender)),
-        };
-        Ok(new)
+        Self {
+            sender: Some(sender),
+            pending,
+            stuck,
+            retired,
+        }
     }
 
-    fn run(receiver: mpsc::Receiver<Action>, pac_script: Option<String>) {
+    fn run(
+        receiver: mpsc::Receiver<Action>,
+        config: Config,
+        pending: &AtomicUsize,
+        stuck: &AtomicBool,
+    ) {
         let mut engine = Engine::new();
-        engine.set_pac_script(pac_script.as_deref()).ok();
+        engine.set_limits(config.limits);
+        engine.set_resolver(config.resolver);
+        engine.set_pac_script(config.pac_script.as_deref()).ok();
+        if let Some(addr) = config.my_ip_address {
+            engine.set_my_ip_address(addr).ok();
+        }
 
+        // the action is finished before its result is send
+        let finished = || {
+            pending.fetch_sub(1, Ordering::SeqCst);
+            stuck.store(false, Ordering::SeqCst);
+        };
         while let Ok(action) = receiver.recv() {
             match action {
                 Action::FindProxy(ref uri, result) => {
                     let r = engine.find_proxy(uri);
+                    finished();
                     result.send(r).ok();
                 }
                 Action::SetPacScript(ref script, result) => {
                     let r = engine.set_pac_script(script.as_deref());
+                    finished();
                     result.send(r).ok();
                 }
                 Action::SetMyIpAddress(addr, result) => {
                     let r = engine.set_my_ip_address(addr);
+                    finished();
                     result.send(r).ok();
                 }
             }
         }
     }
 
+    /// Queue `action`, if the thread terminated the result sender of the action is dropped.
+    fn send(&self, action: Action) {
+        if let Some(ref sender) = self.sender {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            if sender.send(action).is_err() {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl From<WaitError> for FindProxyError {
+    fn from(e: WaitError) -> Self {
+        match e {
+            WaitError::Timeout(timeout) => {
+                Self::RuntimeLimitExceeded(format!("execution time of {timeout:?}"))
+            }
+            WaitError::Terminated => Self::InternalError("worker terminated".into()),
+        }
+    }
+}
+
+impl From<WaitError> for PacScriptError {
+    fn from(e: WaitError) -> Self {
+        match e {
+            WaitError::Timeout(timeout) => {
+                Self::RuntimeLimitExceeded(format!("execution time of {timeout:?}"))
+            }
+            WaitError::Terminated => Self::InternalError("worker terminated".into()),
+        }
+    }
+}
+
+impl Evaluator {
+    pub fn builder() -> Builder {
+        Default::default()
+    }
+
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn with_pac_script(pac_script: &str) -> Result<Self, PacScriptError> {
+        Ok(Self::builder()
+            .pac_script(Some(pac_script.to_owned()))
+            .build())
+    }
+
+    /// Number of worker threads.
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Evaluate `FindProxyForURL` on the worker with the fewest pending requests, unless the
+    /// result is cached.
     pub async fn find_proxy(&self, uri: Uri) -> FindProxyResult {
+        let cached = {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.is_enabled() {
+                let key = cache.key(&uri);
+                if let Some(proxies) = key.as_deref().and_then(|k| cache.get(k)) {
+                    tracing::trace!(%uri, %proxies, "cached FindProxyForURL result");
+                    return Ok(proxies);
+                }
+                key.map(|key| (key, cache.generation()))
+            } else {
+                None
+            }
+        };
+        let result = self.eval_find_proxy(uri).await;
+        if let (Ok(proxies), Some((key, generation))) = (&result, cached) {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(generation, key, proxies.clone());
+        }
+        result
+    }
+
+    async fn eval_find_proxy(&self, uri: Uri) -> FindProxyResult {
         let (tx, rx) = oneshot::channel::<FindProxyResult>();
-        {
-            let sender = self.sender.lock().unwrap();
-            if let Some(ref sender) = *sender {
-                sender.send(Action::FindProxy(uri, tx)).expect("send");
+        let (worker, handle) = self
+            .workers
+            .iter()
+            .map(|w| (w, w.handle()))
+            .filter(|(_, h)| !h.stuck.load(Ordering::SeqCst))
+            .min_by_key(|(_, h)| h.pending.load(Ordering::SeqCst))
+            .ok_or_else(|| {
+                FindProxyError::RuntimeLimitExceeded(
+                    "all workers exceeded the execution time".into(),
+                )
+            })?;
+        handle.send(Action::FindProxy(uri.clone(), tx));
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        match self.wait(worker, &handle, rx, deadline).await {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::error!(%uri, error = ?e, "FindProxyForURL failed");
+                Err(e.into())
             }
         }
-        rx.await.expect("receive")
     }
 
+    /// Wait until `deadline` for the result of an action send to `worker`, the worker is
+    /// restarted if it does not answer in time.
+    async fn wait<T>(
+        &self,
+        worker: &Worker,
+        handle: &WorkerHandle,
+        rx: oneshot::Receiver<T>,
+        deadline: tokio::time::Instant,
+    ) -> Result<T, WaitError> {
+        match tokio::time::timeout_at(deadline, rx).await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) => Err(WaitError::Terminated),
+            Err(_) => {
+                let config = self.config.lock().unwrap().clone();
+                if worker.restart(handle, config, &self.replaced, self.workers.len()) {
+                    tracing::error!(timeout = ?self.timeout, worker = worker.index, "PAC script exceeded execution time, restart worker");
+                } else {
+                    tracing::error!(timeout = ?self.timeout, worker = worker.index, "PAC script exceeded execution time, too many workers restarted already");
+                    handle.stuck.store(true, Ordering::SeqCst);
+                    // the worker may have finished meanwhile
+                    if handle.pending.load(Ordering::SeqCst) == 0 {
+                        handle.stuck.store(false, Ordering::SeqCst);
+                    }
+                }
+                Err(WaitError::Timeout(self.timeout))
+            }
+        }
+    }
+
+    /// Update the PAC script of all workers.
+    ///
+    /// The script is only kept when all workers accepted it, otherwise the workers load the
+    /// previous script again.
     pub async fn set_pac_script(&self, pac_script: Option<String>) -> SetPacScriptResult {
-        let (tx, rx) = oneshot::channel::<SetPacScriptResult>();
-        {
-            let sender = self.sender.lock().unwrap();
-            if let Some(ref sender) = *sender {
-                sender
-                    .send(Action::SetPacScript(pac_script, tx))
-                    .expect("send");
+        let result = self.load_pac_script(&pac_script).await;
+        if result.is_ok() {
+            self.config.lock().unwrap().pac_script = pac_script.clone();
+            self.cache.lock().unwrap().reset(pac_script.as_deref());
+        } else {
+            // workers which failed are left without a script, restarted workers use the previous
+            let previous = self.config.lock().unwrap().pac_script.clone();
+            if let Err(cause) = self.load_pac_script(&previous).await {
+                tracing::error!(%cause, "failed to restore the previous PAC script");
             }
         }
-        rx.await.expect("receive")
+        result
     }
 
-    pub async fn set_my_ip_address(&self, addr: IpAddr) -> SetMyIpAddressResult {
-        let (tx, rx) = oneshot::channel::<SetMyIpAddressResult>();
+    /// Load `pac_script` into all workers.
+    async fn load_pac_script(&self, pac_script: &Option<String>) -> SetPacScriptResult {
+        let results = self
+            .workers
+            .iter()
+            .map(|worker| {
+                let (tx, rx) = oneshot::channel::<SetPacScriptResult>();
+                let handle = worker.handle();
+                handle.send(Action::SetPacScript(pac_script.clone(), tx));
+                (worker, handle, rx)
+            })
+            .collect::<Vec<_>>();
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut result = Ok(());
+        for (worker, handle, rx) in results {
+            let r = self
+                .wait(worker, &handle, rx, deadline)
+                .await
+                .unwrap_or_else(|e| Err(e.into()));
+            result = result.and(r);
+        }
+        result
+    }
+
+    /// Update the IP address returned by `myIpAddress` of all workers.
+    pub async fn set_my_ip_address(&self, addr: IpAddr) -> Result<(), PacScriptError> {
         {
-            let sender = self.sender.lock().unwrap();
-            if let Some(ref sender) = *sender {
-                sender.send(Action::SetMyIpAddress(addr, tx)).expect("send");
+            let mut config = self.config.lock().unwrap();
+            config.my_ip_address = Some(addr);
+            // answers of the previous network might be wrong in the new one
+            config.resolver.clear();
+        }
+        let results = self
+            .workers
+            .iter()
+            .map(|worker| {
+                let (tx, rx) = oneshot::channel::<SetMyIpAddressResult>();
+                let handle = worker.handle();
+                handle.send(Action::SetMyIpAddress(addr, tx));
+                (worker, handle, rx)
+            })
+            .collect::<Vec<_>>();
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut result = Ok(());
+        for (worker, handle, rx) in results {
+            if let Err(e) = self.wait(worker, &handle, rx, deadline).await {
+                result = Err(e.into());
             }
         }
-        rx.await.expect("receive")
+        self.cache.lock().unwrap().clear();
+        result
     }
 }
 
@@ -121,8 +482,197 @@ impl Default for Evaluator {
 
 impl Drop for Evaluator {
     fn drop(&mut self) {
-        let mut sender = self.sender.lock().unwrap();
-        let _ = sender.take();
-        // self.worker.join();
+        for worker in &self.workers {
+            let mut handle = worker.handle.lock().unwrap();
+            let _ = handle.sender.take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn set_pac_script_on_all_workers() {
+        let eval = Arc::new(
+            Evaluator::builder()
+                .workers(3)
+                .limits(Limits {
+                    loop_iterations: u64::MAX,
+                    recursion: 512,
+                })
+                .build(),
+        );
+        assert_eq!(eval.workers(), 3);
+        eval.set_pac_script(Some(
+            "function FindProxyForURL(url, host) { var end = Date.now() + 500; while (Date.now() < end) {} return \"PROXY proxy.example.org:8080\"; }"
+                .into(),
+        ))
+        .await
+        .unwrap();
+
+        let uri = "http://example.org/".parse::<Uri>().unwrap();
+        let requests = (0..3)
+            .map(|_| {
+                tokio::spawn({
+                    let eval = eval.clone();
+                    let uri = uri.clone();
+                    async move { eval.find_proxy(uri).await }
+                })
+            })
+            .collect::<Vec<_>>();
+        // let the requests be dispatched to the workers
+        tokio::task::yield_now().await;
+
+        // concurrent requests are distributed over all workers
+        for worker in &eval.workers {
+            assert_eq!(worker.handle().pending.load(Ordering::Relaxed), 1);
+        }
+        for request in requests {
+            let proxies = request.await.unwrap().unwrap();
+            assert_eq!(proxies.to_string(), "HTTP proxy.example.org:8080");
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_worker_after_timeout() {
+        let eval = Evaluator::builder()
+            .limits(Limits {
+                loop_iterations: u64::MAX,
+                recursion: 512,
+            })
+            .timeout(Duration::from_secs(1))
+            .pac_script(Some(
+                "function FindProxyForURL(url, host) { if (host == \"loop.example.org\") { while (true) {} } return \"DIRECT\"; }"
+                    .into(),
+            ))
+            .build();
+        let result = eval
+            .find_proxy("http://loop.example.org/".parse::<Uri>().unwrap())
+            .await;
+        assert!(matches!(
+            result,
+            Err(FindProxyError::RuntimeLimitExceeded(_))
+        ));
+        // the new worker uses the same PAC script
+        let proxies = eval
+            .find_proxy("http://example.org/".parse::<Uri>().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(proxies.to_string(), "DIRECT");
+    }
+
+    #[tokio::test]
+    async fn restarted_workers_bounded() {
+        let eval = Evaluator::builder()
+            .limits(Limits {
+                loop_iterations: u64::MAX,
+                recursion: 512,
+            })
+            .timeout(Duration::from_millis(200))
+            .pac_script(Some(
+                "function FindProxyForURL(url, host) { while (true) {} }".into(),
+            ))
+            .build();
+        let uri = "http://example.org/".parse::<Uri>().unwrap();
+        for _ in 0..4 {
+            let result = eval.find_proxy(uri.clone()).await;
+            assert!(matches!(
+                result,
+                Err(FindProxyError::RuntimeLimitExceeded(_))
+            ));
+        }
+        // one replaced thread beside the stuck worker, which is not used any more
+        assert_eq!(eval.replaced.load(Ordering::SeqCst), 1);
+        assert!(eval.workers[0].handle().stuck.load(Ordering::SeqCst));
+        let start = std::time::Instant::now();
+        let result = eval.find_proxy(uri).await;
+        assert!(matches!(
+            result,
+            Err(FindProxyError::RuntimeLimitExceeded(_))
+        ));
+        assert!(start.elapsed() < Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn set_pac_script_timeout() {
+        let eval = Evaluator::builder()
+            .limits(Limits {
+                loop_iterations: u64::MAX,
+                recursion: 512,
+            })
+            .timeout(Duration::from_millis(200))
+            .build();
+        let result = eval.set_pac_script(Some("while (true) {}".into())).await;
+        assert!(matches!(
+            result,
+            Err(PacScriptError::RuntimeLimitExceeded(_))
+        ));
+        assert_eq!(eval.replaced.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn keep_previous_script_on_error() {
+        let eval = Arc::new(
+            Evaluator::builder()
+                .workers(2)
+                .pac_script(Some(
+                    "function FindProxyForURL(url, host) { return \"PROXY a.example.org:8080\"; }"
+                        .into(),
+                ))
+                .build(),
+        );
+        let previous = eval.config.lock().unwrap().pac_script.clone();
+        let result = eval
+            .set_pac_script(Some("function FindProxyForURL(url, host) {".into()))
+            .await;
+        assert!(result.is_err());
+        // restarted workers load the script of the config
+        assert_eq!(eval.config.lock().unwrap().pac_script, previous);
+
+        // all workers still answer with the previous script
+        let requests = (0..4)
+            .map(|_| {
+                let eval = eval.clone();
+                tokio::spawn(async move {
+                    eval.find_proxy("http://example.org/".parse::<Uri>().unwrap())
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        for request in requests {
+            let proxies = request.await.unwrap().unwrap();
+            assert_eq!(proxies.to_string(), "HTTP a.example.org:8080");
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_flushed_on_update() {
+        let eval = Evaluator::builder()
+            .cache_ttl(Duration::from_secs(60))
+            .pac_script(Some(
+                "function FindProxyForURL(url, host) { return \"PROXY a.example.org:8080\"; }"
+                    .into(),
+            ))
+            .build();
+        let uri = "http://example.org/".parse::<Uri>().unwrap();
+        let proxies = eval.find_proxy(uri.clone()).await.unwrap();
+        assert_eq!(proxies.to_string(), "HTTP a.example.org:8080");
+        assert_eq!(eval.cache.lock().unwrap().len(), 1);
+
+        eval.set_pac_script(Some(
+            "function FindProxyForURL(url, host) { return \"PROXY b.example.org:8080\"; }".into(),
+        ))
+        .await
+        .unwrap();
+        assert!(eval.cache.lock().unwrap().is_empty());
+        let proxies = eval.find_proxy(uri.clone()).await.unwrap();
+        assert_eq!(proxies.to_string(), "HTTP b.example.org:8080");
+
+        eval.set_my_ip_address("10.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(eval.cache.lock().unwrap().is_empty());
     }
 }
@@ -0,0 +1,233 @@
+//! IPv6 aware extensions of the PAC functions as defined by Microsoft.
+//!
+//! See <https://learn.microsoft.com/en-us/windows/win32/winhttp/ipv6-extensions-to-navigator-auto-config-file-format>.
+
+use boa_engine::{Context, JsResult, JsString, JsValue, NativeFunction, js_string};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::engine::{dns_lookup_all, string_arg};
+
+/// Version of the extensions returned by `getClientVersion`.
+const CLIENT_VERSION: &str = "1.0";
+
+pub(crate) fn register(js: &mut Context, my_ip_addr: &Arc<Mutex<IpAddr>>) {
+    let functions: [(JsString, usize, NativeFunction); 5] = [
+        (
+            js_string!("dnsResolveEx"),
+            1,
+            NativeFunction::from_fn_ptr(dns_resolve_ex),
+        ),
+        (
+            js_string!("isResolvableEx"),
+            1,
+            NativeFunction::from_fn_ptr(is_resolvable_ex),
+        ),
+        (
+            js_string!("isInNetEx"),
+            2,
+            NativeFunction::from_fn_ptr(is_in_net_ex),
+        ),
+        (
+            js_string!("sortIpAddressList"),
+            1,
+            NativeFunction::from_fn_ptr(sort_ip_address_list),
+        ),
+        (
+            js_string!("getClientVersion"),
+            0,
+            NativeFunction::from_fn_ptr(get_client_version),
+        ),
+    ];
+    for (name, length, function) in functions {
+        js.register_global_builtin_callable(name, length, function)
+            .expect("register_global_builtin_callable");
+    }
+
+    // # Safety
+    // We do not capture any varaibles which would require tracing.
+    unsafe {
+        js.register_global_builtin_callable(
+            js_string!("myIpAddressEx"),
+            0,
+            NativeFunction::from_closure({
+                let ip = my_ip_addr.clone();
+                move |_this, _args, _ctx| {
+                    let ip = ip
+                        .lock()
+                        .map(|ip| *ip)
+                        .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+                    let addrs = my_ip_addresses(ip, &interface_addresses());
+                    Ok(JsValue::from(JsString::from(addrs)))
+                }
+            }),
+        )
+        .expect("register_global_builtin_callable myIpAddressEx");
+    }
+}
+
+/// Addresses of all network interfaces which are up, except the loopback interfaces.
+fn interface_addresses() -> Vec<IpAddr> {
+    default_net::get_interfaces()
+        .into_iter()
+        .filter(|i| i.is_up() && !i.is_loopback())
+        .flat_map(|i| {
+            let ipv4 = i.ipv4.into_iter().map(|n| IpAddr::from(n.addr));
+            let ipv6 = i.ipv6.into_iter().map(|n| IpAddr::from(n.addr));
+            ipv4.chain(ipv6).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The address returned by `myIpAddress`, followed by the other addresses of the host, separated
+/// by semicolons.
+fn my_ip_addresses(my_ip_addr: IpAddr, interface_addrs: &[IpAddr]) -> String {
+    let mut addrs = vec![my_ip_addr];
+    for addr in interface_addrs {
+        if !addrs.contains(addr) {
+            addrs.push(*addr);
+        }
+    }
+    addrs
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// All IPv4 and IPv6 addresses of the host separated by semicolons, empty if not resolvable.
+fn dns_resolve_ex(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = string_arg(args, 0, context)?;
+    let addrs = dns_lookup_all(&host, context).join(";");
+    Ok(JsValue::from(JsString::from(addrs)))
+}
+
+fn is_resolvable_ex(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = string_arg(args, 0, context)?;
+    Ok(JsValue::from(!dns_lookup_all(&host, context).is_empty()))
+}
+
+/// Returns `true` if the IP address (or any address of the host name) is in the prefix.
+fn is_in_net_ex(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = string_arg(args, 0, context)?;
+    let prefix = string_arg(args, 1, context)?;
+    let addrs = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(addr) => vec![addr],
+        Err(_) => dns_lookup_all(&host, context)
+            .iter()
+            .filter_map(|a| a.parse().ok())
+            .collect(),
+    };
+    let result = addrs
+        .into_iter()
+        .any(|addr| is_in_net(addr, &prefix).unwrap_or(false));
+    Ok(JsValue::from(result))
+}
+
+/// Sorted list of IP addresses separated by semicolons, `false` if the list is invalid.
+fn sort_ip_address_list(
+    _this: &JsValue,
+    args: &[JsValue],
+    context: &mut Context,
+) -> JsResult<JsValue> {
+    let list = string_arg(args, 0, context)?;
+    Ok(match sort_ip_addresses(&list) {
+        Some(sorted) => JsValue::from(JsString::from(sorted)),
+        None => JsValue::from(false),
+    })
+}
+
+fn get_client_version(
+    _this: &JsValue,
+    _args: &[JsValue],
+    _context: &mut Context,
+) -> JsResult<JsValue> {
+    Ok(JsValue::from(js_string!(CLIENT_VERSION)))
+}
+
+/// Returns `Some(true)` if `addr` is part of `prefix` (e.g. `10.0.0.0/8` or `fe80::/10`), `None`
+/// if `prefix` is invalid.
+fn is_in_net(addr: IpAddr, prefix: &str) -> Option<bool> {
+    let (net, len) = prefix.split_once('/')?;
+    let net = net.trim().parse::<IpAddr>().ok()?;
+    let len = len.trim().parse::<u32>().ok()?;
+    let (addr, net, bits) = match (addr, net) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+        (IpAddr::V6(a), IpAddr::V6(n)) => (u128::from(a), u128::from(n), 128),
+        (IpAddr::V6(a), IpAddr::V4(n)) => match a.to_ipv4_mapped() {
+            Some(a) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+            None => return Some(false),
+        },
+        (IpAddr::V4(_), IpAddr::V6(_)) => return Some(false),
+    };
+    if len > bits {
+        return None;
+    }
+    let mask = if len == 0 {
+        0
+    } else {
+        (u128::MAX << (bits - len)) & (u128::MAX >> (128 - bits))
+    };
+    Some(addr & mask == net & mask)
+}
+
+/// IPv6 addresses first, then IPv4 addresses, each in ascending order.
+fn sort_ip_addresses(list: &str) -> Option<String> {
+    let mut addrs = list
+        .split(';')
+        .map(|a| a.trim().parse::<IpAddr>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    addrs.sort_by_key(|a| (a.is_ipv4(), *a));
+    Some(
+        addrs
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .join(";"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_in_net_test() {
+        let v4 = "198.95.249.79".parse().unwrap();
+        assert_eq!(is_in_net(v4, "198.95.0.0/16"), Some(true));
+        assert_eq!(is_in_net(v4, "198.94.0.0/16"), Some(false));
+        assert_eq!(is_in_net(v4, "0.0.0.0/0"), Some(true));
+        assert_eq!(is_in_net(v4, "3ffe:8311:ffff::/48"), Some(false));
+        assert_eq!(is_in_net(v4, "198.95.0.0/33"), None);
+        assert_eq!(is_in_net(v4, "198.95.0.0"), None);
+
+        let v6 = "3ffe:8311:ffff:1::1".parse().unwrap();
+        assert_eq!(is_in_net(v6, "3ffe:8311:ffff::/48"), Some(true));
+        assert_eq!(is_in_net(v6, "3ffe:8311:fffe::/48"), Some(false));
+        assert_eq!(is_in_net(v6, "3ffe:8311:ffff:1::1/128"), Some(true));
+
+        let mapped = "::ffff:10.1.2.3".parse().unwrap();
+        assert_eq!(is_in_net(mapped, "10.0.0.0/8"), Some(true));
+    }
+
+    #[test]
+    fn my_ip_addresses_test() {
+        let my_ip_addr = "10.0.0.1".parse().unwrap();
+        let interface_addrs =
+            ["192.168.1.2", "10.0.0.1", "fe80::1"].map(|a| a.parse::<IpAddr>().unwrap());
+        assert_eq!(
+            my_ip_addresses(my_ip_addr, &interface_addrs),
+            "10.0.0.1;192.168.1.2;fe80::1"
+        );
+        assert_eq!(my_ip_addresses(my_ip_addr, &[]), "10.0.0.1");
+    }
+
+    #[test]
+    fn sort_ip_addresses_test() {
+        assert_eq!(
+            sort_ip_addresses("10.2.3.9;2001:4898:28:3:201:2ff:feea:fc14;::1;127.0.0.1").unwrap(),
+            "::1;2001:4898:28:3:201:2ff:feea:fc14;10.2.3.9;127.0.0.1"
+        );
+        assert!(sort_ip_addresses("10.2.3.9;example.org").is_none());
+    }
+}
@@ -1,10 +1,13 @@
+pub mod cache;
 pub mod dns;
 pub mod domain;
 pub mod engine;
 pub mod evaluator;
+mod extensions;
 pub mod proxy;
 
 pub use crate::engine::Engine;
+pub use crate::engine::Limits;
 pub use crate::evaluator::Evaluator;
 pub use crate::proxy::Proxies;
 pub use crate::proxy::Proxy;
@@ -33,6 +36,8 @@ pub enum PacScriptError {
         #[source]
         std::io::Error,
     ),
+    #[error("PAC script exceeded runtime limit: {0}")]
+    RuntimeLimitExceeded(String),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -49,4 +54,6 @@ pub enum FindProxyError {
     EmptyResult,
     #[error("internal error when processing PAC script: {0}")]
     InternalError(String),
+    #[error("PAC script exceeded runtime limit: {0}")]
+    RuntimeLimitExceeded(String),
 }
@@ -4,7 +4,7 @@ use detox_net::HostAndPort;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("unknown directive {0}, expected DIRECT, PROXY, HTTP, or HTTPS")]
+    #[error("unknown directive {0}, expected DIRECT, PROXY, HTTP, HTTPS, SOCKS, SOCKS4, or SOCKS5")]
     UnknowDirective(String),
     #[error("empty entry ")]
     EmptyEntry,
@@ -98,11 +98,13 @@ pub enum ProxyOrDirect {
     Proxy(Proxy),
 }
 
-/// A HTTP or HTTTPS proxy endpoint.
+/// A HTTP, HTTTPS, or SOCKS proxy endpoint.
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub enum Proxy {
     Http(HostAndPort),
     Https(HostAndPort),
+    Socks4(HostAndPort),
+    Socks5(HostAndPort),
 }
 
 impl Proxy {
@@ -110,9 +112,16 @@ impl Proxy {
         match *self {
             Self::Http(ref ep) => ep,
             Self::Https(ref ep) => ep,
+            Self::Socks4(ref ep) => ep,
+            Self::Socks5(ref ep) => ep,
         }
     }
 
+    /// Returns `true` for SOCKS4a and SOCKS5 proxies.
+    pub fn is_socks(&self) -> bool {
+        matches!(self, Self::Socks4(_) | Self::Socks5(_))
+    }
+
     pub fn host(&self) -> &str {
         self.endpoint().host()
     }
@@ -142,6 +151,14 @@ impl fmt::Display for Proxy {
                 f.write_str("HTTPS ")?;
                 f.write_str(&endpoint.to_string())
             }
+            Self::Socks4(ref endpoint) => {
+                f.write_str("SOCKS4 ")?;
+                f.write_str(&endpoint.to_string())
+            }
+            Self::Socks5(ref endpoint) => {
+                f.write_str("SOCKS5 ")?;
+                f.write_str(&endpoint.to_string())
+            }
         }
     }
 }
@@ -151,7 +168,14 @@ impl std::str::FromStr for ProxyOrDirect {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.trim();
-        if let Some(host_port) = s.strip_prefix("HTTPS") {
+        if let Some(host_port) = s.strip_prefix("SOCKS5") {
+            Ok(Self::Proxy(Proxy::Socks5(host_port.parse()?)))
+        } else if let Some(host_port) = s.strip_prefix("SOCKS4") {
+            Ok(Self::Proxy(Proxy::Socks4(host_port.parse()?)))
+        } else if let Some(host_port) = s.strip_prefix("SOCKS") {
+            // like browsers, treat a plain `SOCKS` as SOCKS version 4
+            Ok(Self::Proxy(Proxy::Socks4(host_port.parse()?)))
+        } else if let Some(host_port) = s.strip_prefix("HTTPS") {
             Ok(Self::Proxy(Proxy::Https(host_port.parse()?)))
         } else if let Some(host_port) = s.strip_prefix("PROXY") {
             Ok(Self::Proxy(Proxy::Http(host_port.parse()?)))
@@ -190,6 +214,18 @@ mod tests {
             "HTTPS 127.0.0.1:3128".parse::<ProxyOrDirect>()?,
             ProxyOrDirect::Proxy(Proxy::Https("127.0.0.1:3128".parse()?))
         );
+        assert_eq!(
+            "SOCKS 127.0.0.1:1080".parse::<ProxyOrDirect>()?,
+            ProxyOrDirect::Proxy(Proxy::Socks4("127.0.0.1:1080".parse()?))
+        );
+        assert_eq!(
+            "SOCKS4 127.0.0.1:1080".parse::<ProxyOrDirect>()?,
+            ProxyOrDirect::Proxy(Proxy::Socks4("127.0.0.1:1080".parse()?))
+        );
+        assert_eq!(
+            "SOCKS5 127.0.0.1:1080".parse::<ProxyOrDirect>()?,
+            ProxyOrDirect::Proxy(Proxy::Socks5("127.0.0.1:1080".parse()?))
+        );
         assert!("PROXY 127.0.0.1:abc".parse::<ProxyOrDirect>().is_err());
         Ok(())
     }
@@ -228,6 +264,13 @@ mod tests {
                 ProxyOrDirect::Direct
             ])
         );
+        assert_eq!(
+            "SOCKS5 localhost:1080; PROXY localhost:3128".parse::<Proxies>()?,
+            Proxies::new(vec![
+                ProxyOrDirect::Proxy(Proxy::Socks5("localhost:1080".parse()?)),
+                ProxyOrDirect::Proxy(Proxy::Http("localhost:3128".parse()?)),
+            ])
+        );
         Ok(())
     }
 }
@@ -96,3 +96,49 @@ fn test_my_ip_address() {
             .first()
     );
 }
+
+#[test]
+fn test_is_resolvable_ex() {
+    find_proxy(r#"isResolvableEx(host)"#, "localhost", "host.invalid");
+}
+
+#[test]
+fn test_is_in_net_ex() {
+    find_proxy(
+        r#"isInNetEx(host, "fe80::/10")"#,
+        "http://[fe80::1]/",
+        "http://[2001:db8::1]/",
+    );
+}
+
+#[test]
+fn test_dns_resolve_ex() {
+    find_proxy(
+        r#"dnsResolveEx(host).split(";").indexOf("127.0.0.1") != -1"#,
+        "http://localhost/",
+        "http://host.invalid/",
+    );
+}
+
+#[test]
+fn test_find_proxy_for_url_ex() {
+    let mut eval = Engine::with_pac_script(
+        r#"
+        function FindProxyForURL(url, host) { return "PROXY legacy.example.org:3128"; }
+        function FindProxyForURLEx(url, host) {
+            var addrs = myIpAddressEx();
+            if (getClientVersion() == "1.0" && addrs.split(";")[0] == "127.0.0.1" && sortIpAddressList(addrs)) {
+                return "DIRECT";
+            }
+            return "PROXY ex.example.org:3128";
+        }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        ProxyOrDirect::Direct,
+        eval.find_proxy(&"http://example.org/".parse::<Uri>().unwrap())
+            .unwrap()
+            .first()
+    );
+}
@@ -9,8 +9,16 @@ use detox_auth::AuthenticatorFactory;
 use detox_auth::netrc;
 use futures_util::future;
 use futures_util::stream;
+use futures_util::{StreamExt, TryStreamExt};
+#[cfg(target_os = "linux")]
+use options::NetworkChange;
 use options::{Authorization, Options};
-use proxydetoxlib::{server::Server, socket};
+#[cfg(target_os = "linux")]
+use proxydetoxlib::netwatch;
+use proxydetoxlib::{
+    server::{Accepted, Protocol, Server},
+    socket,
+};
 use std::fs::File;
 use std::net::IpAddr;
 use std::result::Result;
@@ -140,11 +148,40 @@ async fn run(config: Arc<Options>) -> Result<(), proxydetoxlib::Error> {
             };
             AuthenticatorFactory::basic(store)
         }
+        Authorization::Ntlm(netrc_file) => {
+            let store = match File::open(netrc_file) {
+                Ok(file) => netrc::Store::new(std::io::BufReader::new(file))?,
+                _ => netrc::Store::default(),
+            };
+            AuthenticatorFactory::ntlm(store)
+        }
+        Authorization::Auto(netrc_file) => {
+            let store = match File::open(netrc_file) {
+                Ok(file) => netrc::Store::new(std::io::BufReader::new(file))?,
+                _ => netrc::Store::default(),
+            };
+            AuthenticatorFactory::auto(store)
+        }
     };
     tracing::debug!(%auth, "authorization");
 
     let context = proxydetoxlib::Context::builder()
         .pac_file(config.pac_file.clone())
+        .pac_workers(config.pac_workers)
+        .pac_cache_ttl(config.pac_cache_ttl)
+        .pac_cache_size(config.pac_cache_size)
+        .pac_loop_limit(config.pac_loop_limit)
+        .pac_recursion_limit(config.pac_recursion_limit)
+        .pac_timeout(config.pac_timeout)
+        .pac_refresh_interval(config.pac_refresh_interval)
+        .pac_backup_file(config.pac_backup_file.clone())
+        .pac_proxy(config.pac_proxy.clone())
+        .pac_sha256(config.pac_sha256.clone())
+        .pac_public_key(config.pac_public_key.clone())
+        .dns_servers(config.dns_servers.clone())
+        .dns_search(config.dns_search.clone())
+        .dns_timeout(config.dns_timeout)
+        .dns_negative_ttl(config.dns_negative_ttl)
         .authenticator_factory(Some(auth))
         .proxytunnel(config.proxytunnel)
         .connect_timeout(config.connect_timeout)
@@ -152,6 +189,19 @@ async fn run(config: Arc<Options>) -> Result<(), proxydetoxlib::Error> {
         .parallel_connect(config.parallel_connect)
         .direct_fallback(config.direct_fallback)
         .client_tcp_keepalive(config.client_tcp_keepalive.clone())
+        .pool_idle_timeout(config.pool_idle_timeout)
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .request_buffer_size(config.request_buffer_size)
+        .access_log(
+            config
+                .access_log
+                .clone()
+                .map(|config| {
+                    proxydetoxlib::accesslog::Writer::open(config)
+                        .and_then(proxydetoxlib::accesslog::Writer::spawn)
+                })
+                .transpose()?,
+        )
         .build();
 
     if let Some(my_ip) = config.my_ip_address {
@@ -178,11 +228,37 @@ async fn run(config: Arc<Options>) -> Result<(), proxydetoxlib::Error> {
         .map(|k| k.local_addr())
         .collect::<Result<Vec<_>, _>>()?;
 
-    let listeners = listeners
+    let mut listeners = listeners
         .into_iter()
-        .map(TcpListenerStream::new)
+        .map(|l| TcpListenerStream::new(l).map_ok(Accepted::from).boxed())
         .collect::<Vec<_>>();
 
+    for addr in &config.socks_listen {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!(listening=?listener.local_addr()?, "SOCKS proxy");
+        listeners.push(
+            TcpListenerStream::new(listener)
+                .map_ok(|s| Accepted::new(s, Protocol::Socks))
+                .boxed(),
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    for addr in &config.transparent_listen {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!(listening=?listener.local_addr()?, "transparent proxy");
+        listeners.push(
+            TcpListenerStream::new(listener)
+                .map_ok(|s| Accepted::new(s, Protocol::Transparent))
+                .boxed(),
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.on_network_change != NetworkChange::Ignore {
+        tokio::spawn(watch_network(context.clone(), config.clone()));
+    }
+
     let listeners = stream::select_all(listeners);
     let (server, control) = Server::new(listeners, context.clone());
 
@@ -193,11 +269,19 @@ async fn run(config: Arc<Options>) -> Result<(), proxydetoxlib::Error> {
     let joiner = loop {
         tokio::select! {
             _ = reload_trigger() => {
-                context.load_pac_file(&config.pac_file).await?;
+                context.reload_resolver();
+                // e.g. WPAD finds nothing after moving to another network, keep on running
+                if let Err(cause) = context.load_pac_file(&config.pac_file).await {
+                    tracing::error!(%cause, pac_file = ?config.pac_file, "failed to reload PAC file");
+                }
+                context.set_direct_mode(false);
+                if let Err(cause) = context.reopen_access_log().await {
+                    tracing::error!(%cause, "failed to reopen access log");
+                }
                 context.set_my_ip_address(my_ip_address()).await?;
             },
             _ = direct_mode_trigger() => {
-                context.load_pac_file(&None).await?;
+                context.set_direct_mode(true);
                 context.set_my_ip_address(my_ip_address()).await?;
             },
             _ = shutdown_trigger() => {
@@ -291,6 +375,37 @@ async fn shutdown_trigger() {
     tokio::signal::ctrl_c().await.expect("ctrl_c event");
 }
 
+/// Update the address of `myIpAddress` and reload the PAC file when the network changed.
+#[cfg(target_os = "linux")]
+async fn watch_network(context: Arc<proxydetoxlib::Context>, config: Arc<Options>) {
+    let mut watcher = match netwatch::Watcher::new(config.network_change_delay) {
+        Ok(watcher) => watcher,
+        Err(cause) => {
+            tracing::error!(%cause, "unable to watch for network changes");
+            return;
+        }
+    };
+    loop {
+        if let Err(cause) = watcher.changed().await {
+            tracing::error!(%cause, "watching for network changes failed");
+            break;
+        }
+        tracing::info!("network changed");
+        context.reload_resolver();
+        // an address given with `--my-ip-address` is kept
+        if config.my_ip_address.is_none()
+            && let Err(cause) = context.set_my_ip_address(my_ip_address()).await
+        {
+            tracing::error!(%cause, "failed to update my IP address");
+        }
+        if config.on_network_change == NetworkChange::Reload
+            && let Err(cause) = context.reload_pac_file().await
+        {
+            tracing::error!(%cause, "failed to reload PAC file");
+        }
+    }
+}
+
 fn my_ip_address() -> IpAddr {
     let ipv4 = default_net::get_default_interface()
         .ok()
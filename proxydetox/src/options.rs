@@ -8,7 +8,10 @@ use std::{
 };
 
 use clap::{Arg, ArgAction, ArgMatches, Command};
-use detox_net::{PathOrUri, TcpKeepAlive};
+use detox_net::{HostAndPort, PathOrUri, TcpKeepAlive};
+use paclib::{Proxy, ProxyOrDirect};
+use proxydetoxlib::accesslog;
+use proxydetoxlib::integrity::{PublicKey, Sha256};
 use tracing_subscriber::filter::LevelFilter;
 
 lazy_static::lazy_static! {
@@ -20,30 +23,67 @@ lazy_static::lazy_static! {
 #[derive(Debug, PartialEq, Eq)]
 pub enum Authorization {
     Basic(PathBuf),
+    Ntlm(PathBuf),
+    Auto(PathBuf),
     #[allow(dead_code)]
     Negotiate(Vec<String>),
 }
 
+/// What to do when the IP address or the default route changes.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkChange {
+    Ignore,
+    MyIpAddress,
+    Reload,
+}
+
 #[derive(Debug)]
 pub struct Options {
     #[cfg(target_family = "windows")]
     pub attach_console: bool,
     pub log_level: LevelFilter,
     pub log_filepath: Option<PathBuf>,
+    pub access_log: Option<accesslog::Config>,
     pub pac_file: Option<PathOrUri>,
     pub my_ip_address: Option<IpAddr>,
     pub authorization: Authorization,
     pub connect_timeout: Duration,
     pub race_connect: bool,
     pub parallel_connect: usize,
+    pub pac_workers: usize,
+    pub pac_cache_ttl: Duration,
+    pub pac_cache_size: usize,
+    pub pac_loop_limit: u64,
+    pub pac_recursion_limit: usize,
+    pub pac_timeout: Duration,
+    pub pac_refresh_interval: Duration,
+    pub pac_backup_file: Option<PathBuf>,
+    pub pac_proxy: Option<Proxy>,
+    pub pac_sha256: Vec<Sha256>,
+    pub pac_public_key: Option<PublicKey>,
+    pub dns_servers: Vec<SocketAddr>,
+    pub dns_search: Vec<String>,
+    pub dns_timeout: Duration,
+    pub dns_negative_ttl: Duration,
     pub direct_fallback: bool,
     pub proxytunnel: bool,
     pub activate_socket: Option<String>,
     pub listen: Vec<SocketAddr>,
+    pub socks_listen: Vec<SocketAddr>,
+    #[cfg(target_os = "linux")]
+    pub transparent_listen: Vec<SocketAddr>,
+    #[cfg(target_os = "linux")]
+    pub on_network_change: NetworkChange,
+    #[cfg(target_os = "linux")]
+    pub network_change_delay: Duration,
     pub client_tcp_keepalive: TcpKeepAlive,
     #[allow(dead_code)]
     pub server_tcp_keepalive: TcpKeepAlive,
     pub graceful_shutdown_timeout: Duration,
+    pub pool_idle_timeout: Option<Duration>,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub request_buffer_size: usize,
 }
 
 fn is_file(v: &str) -> Result<PathBuf, String> {
@@ -77,6 +117,36 @@ fn is_valid_socket_addr(v: &str) -> Result<SocketAddr, String> {
     Ok(s)
 }
 
+/// A proxy in PAC syntax (e.g. `PROXY proxy.example.org:3128`) or a plain `host:port`.
+fn is_valid_proxy(v: &str) -> Result<Proxy, String> {
+    match v.parse::<ProxyOrDirect>() {
+        Ok(ProxyOrDirect::Proxy(proxy)) => Ok(proxy),
+        Ok(ProxyOrDirect::Direct) => Err("DIRECT is not a proxy".to_owned()),
+        Err(cause) => v
+            .parse::<HostAndPort>()
+            .map(Proxy::Http)
+            .map_err(|_| cause.to_string()),
+    }
+}
+
+/// A minisign public key, either given directly or as path to the `.pub` file.
+fn is_public_key(v: &str) -> Result<PublicKey, String> {
+    let p = Path::new(v);
+    let key = if p.is_file() {
+        read_to_string(p).map_err(|e| format!("unable to read '{v}': {e}"))?
+    } else {
+        v.to_owned()
+    };
+    key.parse::<PublicKey>().map_err(|e| e.to_string())
+}
+
+fn is_valid_nameserver(v: &str) -> Result<SocketAddr, String> {
+    match v.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, 53)),
+        Err(_) => is_valid_socket_addr(v),
+    }
+}
+
 fn which_pac_file() -> Option<PathBuf> {
     // For Windows, accept a proxy.pac file located next to the binary.
     #[cfg(target_family = "windows")]
@@ -143,6 +213,37 @@ impl Options {
                 .action(ArgAction::SetTrue),
         );
 
+        #[cfg(target_os = "linux")]
+        let app = app.arg(
+            Arg::new("transparent_listen")
+                .long("transparent-listen")
+                .value_name("INTERFACE:PORT")
+                .help("Listening interface for connections redirected via iptables (e.g. 127.0.0.1:3129)")
+                .value_parser(is_valid_socket_addr)
+                .action(ArgAction::Append),
+        );
+
+        #[cfg(target_os = "linux")]
+        let app = app
+            .arg(
+                Arg::new("on_network_change")
+                    .long("on-network-change")
+                    .value_name("ACTION")
+                    .help("When an IP address or the default route changes, update the address returned by myIpAddress (my-ip) and also reload the PAC file (reload)")
+                    .value_parser(["none", "my-ip", "reload"])
+                    .action(ArgAction::Set)
+                    .default_value("none"),
+            )
+            .arg(
+                Arg::new("network_change_delay")
+                    .long("network-change-delay")
+                    .value_name("SECONDS")
+                    .help("React to a network change only after the network did not change for this many seconds")
+                    .value_parser(clap::value_parser!(f64))
+                    .action(ArgAction::Set)
+                    .default_value("2"),
+            );
+
         #[cfg(feature = "negotiate")]
         let app = app.arg(
             Arg::new("negotiate")
@@ -154,14 +255,30 @@ impl Options {
                 .num_args(0..=1),
         );
 
+        let ntlm_arg = Arg::new("ntlm")
+            .long("ntlm")
+            .help("Enables NTLM authentication with the credentials of the .netrc file")
+            .action(ArgAction::SetTrue);
+        #[cfg(feature = "negotiate")]
+        let ntlm_arg = ntlm_arg.conflicts_with("negotiate");
+
+        let auto_auth_arg = Arg::new("auto_auth")
+            .long("auto-auth")
+            .help("Selects the strongest authentication scheme offered by each proxy (Negotiate, NTLM, or basic)")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("ntlm");
+        #[cfg(feature = "negotiate")]
+        let auto_auth_arg = auto_auth_arg.conflicts_with("negotiate");
+
         let netrc_arg = Arg::new("netrc_file")
             .long("netrc-file")
-            .help("Path to a .netrc file to be used for basic authentication")
+            .help("Path to a .netrc file to be used for basic or NTLM authentication")
             .value_parser(is_file)
             .value_name("PATH")
             .action(clap::ArgAction::Set);
         #[cfg(feature = "negotiate")]
         let netrc_arg = netrc_arg.conflicts_with("negotiate");
+        let app = app.arg(ntlm_arg).arg(auto_auth_arg);
 
         let app = app
             .arg(
@@ -186,6 +303,50 @@ impl Options {
                     .value_parser(clap::value_parser!(PathBuf))
                     .action(clap::ArgAction::Set)
             )
+            .arg(
+                Arg::new("access_log")
+                    .long("access-log")
+                    .value_name("PATH")
+                    .help("Write the access log to this file, which is opened again on SIGHUP")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("access_log_format")
+                    .long("access-log-format")
+                    .value_name("FORMAT")
+                    .help("Format of the access log file: text, Apache Common Log Format (common), Apache Combined Log Format (combined), or JSON Lines (json)")
+                    .value_parser(["text", "common", "combined", "json"])
+                    .action(ArgAction::Set)
+                    .default_value("text"),
+            )
+            .arg(
+                Arg::new("access_log_max_size")
+                    .long("access-log-max-size")
+                    .value_name("BYTES")
+                    .help("Rotate the access log file when it reached this size, zero disables the size based rotation")
+                    .value_parser(clap::value_parser!(u64))
+                    .action(ArgAction::Set)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::new("access_log_max_age")
+                    .long("access-log-max-age")
+                    .value_name("SECONDS")
+                    .help("Rotate the access log file after this many seconds, zero disables the time based rotation")
+                    .value_parser(clap::value_parser!(u64))
+                    .action(ArgAction::Set)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::new("access_log_keep")
+                    .long("access-log-keep")
+                    .value_name("NUM")
+                    .help("Number of rotated access log files to keep")
+                    .value_parser(clap::value_parser!(usize))
+                    .action(ArgAction::Set)
+                    .default_value("7"),
+            )
             .arg(
                  Arg::new("activate_socket")
                      .long("activate-socket")
@@ -202,13 +363,21 @@ impl Options {
                     .value_parser(is_valid_socket_addr)
                     .action(ArgAction::Append)
             )
+            .arg(
+                Arg::new("socks_listen")
+                    .long("socks-listen")
+                    .value_name("INTERFACE:PORT")
+                    .help("Listening interface for SOCKS4a and SOCKS5 clients (e.g. 127.0.0.1:1080)")
+                    .value_parser(is_valid_socket_addr)
+                    .action(ArgAction::Append)
+            )
             .arg(
                 Arg::new("pac_file")
                     .long("pac-file")
                     .short('p')
                     .value_name("PATH_OR_URL")
                     .help(
-                        "PAC file to be used to decide which upstream proxy to forward the request (local file path, http://, or https:// URI are accepted)",
+                        "PAC file to be used to decide which upstream proxy to forward the request (local file path, http://, or https:// URI are accepted, `auto` discovers the URI via DHCP or DNS)",
                     )
                     .value_parser(is_file_or_http_uri)
                     .action(clap::ArgAction::Set),
@@ -251,6 +420,134 @@ impl Options {
                     .help("Race multiple connections at the same time")
                     .action(ArgAction::SetTrue),
             )
+            .arg(
+                Arg::new("pac_workers")
+                    .long("pac-workers")
+                    .help("Number of threads which evaluate the PAC script in parallel")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u8).range(1..=64))
+                    .action(ArgAction::Set)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::new("pac_cache_ttl")
+                    .long("pac-cache-ttl")
+                    .help("Cache the results of the PAC script per host for this many seconds (0 disables the cache)")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(u64))
+                    .action(ArgAction::Set)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::new("pac_cache_size")
+                    .long("pac-cache-size")
+                    .help("Maximum number of cached PAC script results")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(usize))
+                    .action(ArgAction::Set)
+                    .default_value("1024"),
+            )
+            .arg(
+                Arg::new("pac_timeout")
+                    .long("pac-timeout")
+                    .help("Maximum time in seconds of one PAC script evaluation, DIRECT is used when exceeded")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(f64))
+                    .action(ArgAction::Set)
+                    .default_value("10"),
+            )
+            .arg(
+                Arg::new("pac_loop_limit")
+                    .long("pac-loop-limit")
+                    .help("Maximum number of loop iterations within one PAC script function call")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(u64))
+                    .action(ArgAction::Set)
+                    .default_value("1000000"),
+            )
+            .arg(
+                Arg::new("pac_recursion_limit")
+                    .long("pac-recursion-limit")
+                    .help("Maximum depth of nested PAC script function calls")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(usize))
+                    .action(ArgAction::Set)
+                    .default_value("512"),
+            )
+            .arg(
+                Arg::new("pac_refresh_interval")
+                    .long("pac-refresh-interval")
+                    .help("Check a downloaded PAC file for modifications every this many seconds (0 disables the check)")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(u64))
+                    .action(ArgAction::Set)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::new("pac_backup_file")
+                    .long("pac-backup-file")
+                    .help("File where the last downloaded PAC file is kept, used when the PAC file cannot be downloaded (default: proxydetox/proxy.pac in the cache directory)")
+                    .value_name("PATH")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("pac_proxy")
+                    .long("pac-proxy")
+                    .help("Proxy used to download the PAC file, e.g. `PROXY proxy.example.org:3128` (default: connect directly)")
+                    .value_name("PROXY")
+                    .value_parser(is_valid_proxy)
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("pac_sha256")
+                    .long("pac-sha256")
+                    .help("Only accept PAC files with this SHA-256 digest (can be given multiple times)")
+                    .value_name("DIGEST")
+                    .value_parser(|v: &str| v.parse::<Sha256>().map_err(|e| e.to_string()))
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("pac_public_key")
+                    .long("pac-public-key")
+                    .help("Only accept PAC files with a minisign signature (the PAC file URL or path with a .minisig suffix) of this public key")
+                    .value_name("KEY_OR_PATH")
+                    .value_parser(is_public_key)
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("dns_server")
+                    .long("dns-server")
+                    .value_name("IP[:PORT]")
+                    .help("Name server used by the PAC functions and for direct connections (default: from /etc/resolv.conf)")
+                    .value_parser(is_valid_nameserver)
+                    .action(ArgAction::Append)
+            )
+            .arg(
+                Arg::new("dns_search")
+                    .long("dns-search")
+                    .value_name("DOMAIN")
+                    .help("Search domain appended to short host names (default: from /etc/resolv.conf)")
+                    .action(ArgAction::Append)
+            )
+            .arg(
+                Arg::new("dns_timeout")
+                    .long("dns-timeout")
+                    .help("Time in seconds to wait for the answer of one DNS query")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(f64))
+                    .action(ArgAction::Set)
+                    .default_value("2"),
+            )
+            .arg(
+                Arg::new("dns_negative_ttl")
+                    .long("dns-negative-ttl")
+                    .help("Cache names without addresses for at most this many seconds")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(u64))
+                    .action(ArgAction::Set)
+                    .default_value("30"),
+            )
             .arg(
                 Arg::new("parallel_connect")
                     .long("parallel-connect")
@@ -307,6 +604,31 @@ impl Options {
                     .value_parser(clap::value_parser!(u32))
                     .action(ArgAction::Set)
             )
+            .arg(
+                Arg::new("pool_idle_timeout")
+                    .long("pool-idle-timeout")
+                    .help("Time after which idle upstream connections are closed")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(f64))
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("pool_max_idle_per_host")
+                    .long("pool-max-idle-per-host")
+                    .help("Maximum number of idle upstream connections per proxy and destination (0 disables connection pooling)")
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(usize))
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("request_buffer_size")
+                    .long("request-buffer-size")
+                    .help("Maximum size of a request body kept in memory to resend the request after a 407 response or a closed pooled connection")
+                    .value_name("BYTES")
+                    .value_parser(clap::value_parser!(usize))
+                    .action(ArgAction::Set)
+                    .default_value("1048576"),
+            )
             .arg(
                 Arg::new("graceful_shutdown_timeout")
                     .long("graceful-shutdown-timeout")
@@ -344,13 +666,18 @@ impl From<ArgMatches> for Options {
                 netrc_path
             });
 
+        let authorization = if m.get_flag("ntlm") {
+            Authorization::Ntlm(netrc_file)
+        } else if m.get_flag("auto_auth") {
+            Authorization::Auto(netrc_file)
+        } else {
+            Authorization::Basic(netrc_file)
+        };
         #[cfg(feature = "negotiate")]
         let authorization = match m.get_many::<String>("negotiate") {
             Some(negotiate) => Authorization::Negotiate(negotiate.cloned().collect()),
-            _ => Authorization::Basic(netrc_file),
+            _ => authorization,
         };
-        #[cfg(not(feature = "negotiate"))]
-        let authorization = Authorization::Basic(netrc_file);
 
         let listen = match m.get_many::<SocketAddr>("listen") {
             Some(listen) => listen.cloned().collect(),
@@ -405,14 +732,118 @@ impl From<ArgMatches> for Options {
                 .get_one::<usize>("parallel_connect")
                 .copied()
                 .expect("default value for parallel_connect"),
+            pac_workers: m
+                .get_one::<u8>("pac_workers")
+                .map(|n| *n as usize)
+                .expect("default value for pac_workers"),
+            pac_cache_ttl: m
+                .get_one::<u64>("pac_cache_ttl")
+                .map(|s| Duration::from_secs(*s))
+                .expect("default value for pac_cache_ttl"),
+            pac_cache_size: m
+                .get_one::<usize>("pac_cache_size")
+                .copied()
+                .expect("default value for pac_cache_size"),
+            pac_loop_limit: m
+                .get_one::<u64>("pac_loop_limit")
+                .copied()
+                .expect("default value for pac_loop_limit"),
+            pac_recursion_limit: m
+                .get_one::<usize>("pac_recursion_limit")
+                .copied()
+                .expect("default value for pac_recursion_limit"),
+            pac_timeout: m
+                .get_one::<f64>("pac_timeout")
+                .map(|s| Duration::from_millis((*s * 1000.0) as u64))
+                .expect("default value for pac_timeout"),
+            pac_refresh_interval: m
+                .get_one::<u64>("pac_refresh_interval")
+                .map(|s| Duration::from_secs(*s))
+                .expect("default value for pac_refresh_interval"),
+            pac_backup_file: m
+                .get_one::<PathBuf>("pac_backup_file")
+                .cloned()
+                .or_else(|| dirs::cache_dir().map(|d| d.join("proxydetox/proxy.pac"))),
+            pac_proxy: m.get_one::<Proxy>("pac_proxy").cloned(),
+            access_log: m
+                .get_one::<PathBuf>("access_log")
+                .map(|path| accesslog::Config {
+                    path: path.clone(),
+                    format: m
+                        .get_one::<String>("access_log_format")
+                        .expect("default value for access_log_format")
+                        .parse()
+                        .expect("valid access_log_format"),
+                    max_size: m
+                        .get_one::<u64>("access_log_max_size")
+                        .copied()
+                        .filter(|size| *size > 0),
+                    max_age: m
+                        .get_one::<u64>("access_log_max_age")
+                        .map(|s| Duration::from_secs(*s))
+                        .filter(|age| !age.is_zero()),
+                    keep: m
+                        .get_one::<usize>("access_log_keep")
+                        .copied()
+                        .expect("default value for access_log_keep"),
+                }),
+            pac_sha256: m
+                .get_many::<Sha256>("pac_sha256")
+                .map(|l| l.cloned().collect())
+                .unwrap_or_default(),
+            pac_public_key: m.get_one::<PublicKey>("pac_public_key").cloned(),
+            dns_servers: m
+                .get_many::<SocketAddr>("dns_server")
+                .map(|l| l.cloned().collect())
+                .unwrap_or_default(),
+            dns_search: m
+                .get_many::<String>("dns_search")
+                .map(|l| l.cloned().collect())
+                .unwrap_or_default(),
+            dns_timeout: m
+                .get_one::<f64>("dns_timeout")
+                .map(|s| Duration::from_millis((*s * 1000.0) as u64))
+                .expect("default value for dns_timeout"),
+            dns_negative_ttl: m
+                .get_one::<u64>("dns_negative_ttl")
+                .map(|s| Duration::from_secs(*s))
+                .expect("default value for dns_negative_ttl"),
             activate_socket: m.get_one::<String>("activate_socket").cloned(),
             listen,
+            socks_listen: m
+                .get_many::<SocketAddr>("socks_listen")
+                .map(|l| l.cloned().collect())
+                .unwrap_or_default(),
+            #[cfg(target_os = "linux")]
+            transparent_listen: m
+                .get_many::<SocketAddr>("transparent_listen")
+                .map(|l| l.cloned().collect())
+                .unwrap_or_default(),
+            #[cfg(target_os = "linux")]
+            on_network_change: match m.get_one::<String>("on_network_change").map(String::as_str) {
+                Some("my-ip") => NetworkChange::MyIpAddress,
+                Some("reload") => NetworkChange::Reload,
+                _ => NetworkChange::Ignore,
+            },
+            #[cfg(target_os = "linux")]
+            network_change_delay: m
+                .get_one::<f64>("network_change_delay")
+                .map(|s| Duration::from_millis((*s * 1000.0) as u64))
+                .expect("default value for network_change_delay"),
             client_tcp_keepalive,
             server_tcp_keepalive,
             graceful_shutdown_timeout: m
                 .get_one::<u64>("graceful_shutdown_timeout")
                 .map(|s| Duration::from_secs(*s))
                 .expect("default value for graceful_shutdown_timeout"),
+            pool_idle_timeout: m
+                .get_one::<f64>("pool_idle_timeout")
+                .map(|s| Duration::from_millis((*s * 1000.0) as u64)),
+            pool_max_idle_per_host: m.get_one::<usize>("pool_max_idle_per_host").copied(),
+            request_buffer_size: m
+                .get_one::<usize>("request_buffer_size")
+                .copied()
+                .expect("default value for request_buffer_size"),
         }
     }
 }
@@ -551,6 +982,12 @@ mod tests {
         assert_eq!(args.pac_file, proxy_pac.parse().ok());
     }
 
+    #[test]
+    fn test_pac_file_auto() {
+        let args = Options::parse_args(&["proxydetox".into(), "--pac-file".into(), "auto".into()]);
+        assert_eq!(args.pac_file, Some(PathOrUri::Auto));
+    }
+
     #[test]
     fn test_listen_none() {
         let args = Options::parse_args(&["proxydetox".into()]);
@@ -610,6 +1047,267 @@ mod tests {
         assert!(matches!(args.authorization, Authorization::Basic(_)));
     }
 
+    #[test]
+    fn test_ntlm() {
+        let args = Options::parse_args(&["proxydetox".into(), "--ntlm".into()]);
+        assert!(matches!(args.authorization, Authorization::Ntlm(_)));
+    }
+
+    #[test]
+    fn test_auto_auth() {
+        let args = Options::parse_args(&["proxydetox".into(), "--auto-auth".into()]);
+        assert!(matches!(args.authorization, Authorization::Auto(_)));
+    }
+
+    #[test]
+    fn test_pool() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.pool_idle_timeout, None);
+        assert_eq!(args.pool_max_idle_per_host, None);
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--pool-idle-timeout".into(),
+            "2.5".into(),
+            "--pool-max-idle-per-host".into(),
+            "0".into(),
+        ]);
+        assert_eq!(args.pool_idle_timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(args.pool_max_idle_per_host, Some(0));
+    }
+
+    #[test]
+    fn test_pac_workers() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.pac_workers, 1);
+
+        let args = Options::parse_args(&["proxydetox".into(), "--pac-workers".into(), "4".into()]);
+        assert_eq!(args.pac_workers, 4);
+    }
+
+    #[test]
+    fn test_pac_cache() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.pac_cache_ttl, Duration::ZERO);
+        assert_eq!(args.pac_cache_size, 1024);
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--pac-cache-ttl".into(),
+            "300".into(),
+            "--pac-cache-size".into(),
+            "16".into(),
+        ]);
+        assert_eq!(args.pac_cache_ttl, Duration::from_secs(300));
+        assert_eq!(args.pac_cache_size, 16);
+    }
+
+    #[test]
+    fn test_pac_limits() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.pac_timeout, Duration::from_secs(10));
+        assert_eq!(args.pac_loop_limit, 1_000_000);
+        assert_eq!(args.pac_recursion_limit, 512);
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--pac-timeout".into(),
+            "0.5".into(),
+            "--pac-loop-limit".into(),
+            "1000".into(),
+            "--pac-recursion-limit".into(),
+            "64".into(),
+        ]);
+        assert_eq!(args.pac_timeout, Duration::from_millis(500));
+        assert_eq!(args.pac_loop_limit, 1000);
+        assert_eq!(args.pac_recursion_limit, 64);
+    }
+
+    #[test]
+    fn test_pac_refresh() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.pac_refresh_interval, Duration::ZERO);
+        assert_eq!(
+            args.pac_backup_file,
+            dirs::cache_dir().map(|d| d.join("proxydetox/proxy.pac"))
+        );
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--pac-refresh-interval".into(),
+            "600".into(),
+            "--pac-backup-file".into(),
+            "/tmp/backup.pac".into(),
+        ]);
+        assert_eq!(args.pac_refresh_interval, Duration::from_secs(600));
+        assert_eq!(args.pac_backup_file, Some(PathBuf::from("/tmp/backup.pac")));
+    }
+
+    #[test]
+    fn test_access_log() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.access_log, None);
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--access-log".into(),
+            "/var/log/proxydetox/access.log".into(),
+            "--access-log-format".into(),
+            "combined".into(),
+            "--access-log-max-size".into(),
+            "1048576".into(),
+            "--access-log-keep".into(),
+            "3".into(),
+        ]);
+        assert_eq!(
+            args.access_log,
+            Some(accesslog::Config {
+                path: PathBuf::from("/var/log/proxydetox/access.log"),
+                format: accesslog::Format::Combined,
+                max_size: Some(1048576),
+                max_age: None,
+                keep: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_pac_proxy() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.pac_proxy, None);
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--pac-proxy".into(),
+            "HTTPS proxy.example.org:3129".into(),
+        ]);
+        assert_eq!(
+            args.pac_proxy,
+            Some(Proxy::Https(HostAndPort::new("proxy.example.org", 3129)))
+        );
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--pac-proxy".into(),
+            "proxy.example.org:3128".into(),
+        ]);
+        assert_eq!(
+            args.pac_proxy,
+            Some(Proxy::Http(HostAndPort::new("proxy.example.org", 3128)))
+        );
+    }
+
+    #[test]
+    fn test_pac_integrity() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert!(args.pac_sha256.is_empty());
+        assert_eq!(args.pac_public_key, None);
+
+        let digest = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--pac-sha256".into(),
+            digest.into(),
+            "--pac-public-key".into(),
+            "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3".into(),
+        ]);
+        assert_eq!(args.pac_sha256, vec![digest.parse().unwrap()]);
+        assert!(args.pac_public_key.is_some());
+    }
+
+    #[test]
+    fn test_dns() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert!(args.dns_servers.is_empty());
+        assert!(args.dns_search.is_empty());
+        assert_eq!(args.dns_timeout, Duration::from_secs(2));
+        assert_eq!(args.dns_negative_ttl, Duration::from_secs(30));
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--dns-server".into(),
+            "192.0.2.53".into(),
+            "--dns-server".into(),
+            "[2001:db8::53]:5353".into(),
+            "--dns-search".into(),
+            "example.org".into(),
+            "--dns-timeout".into(),
+            "0.5".into(),
+            "--dns-negative-ttl".into(),
+            "5".into(),
+        ]);
+        assert_eq!(
+            args.dns_servers,
+            vec![
+                "192.0.2.53:53".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::53]:5353".parse().unwrap()
+            ]
+        );
+        assert_eq!(args.dns_search, vec!["example.org"]);
+        assert_eq!(args.dns_timeout, Duration::from_millis(500));
+        assert_eq!(args.dns_negative_ttl, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_request_buffer_size() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.request_buffer_size, 1024 * 1024);
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--request-buffer-size".into(),
+            "0".into(),
+        ]);
+        assert_eq!(args.request_buffer_size, 0);
+    }
+
+    #[test]
+    fn test_socks_listen() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert!(args.socks_listen.is_empty());
+
+        let addr: SocketAddr = "127.0.0.1:1080".parse().unwrap();
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--socks-listen".into(),
+            "127.0.0.1:1080".into(),
+        ]);
+        assert_eq!(args.socks_listen, vec![addr]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_transparent_listen() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert!(args.transparent_listen.is_empty());
+
+        let addr: SocketAddr = "127.0.0.1:3129".parse().unwrap();
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--transparent-listen".into(),
+            "127.0.0.1:3129".into(),
+        ]);
+        assert_eq!(args.transparent_listen, vec![addr]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_on_network_change() {
+        let args = Options::parse_args(&["proxydetox".into()]);
+        assert_eq!(args.on_network_change, NetworkChange::Ignore);
+        assert_eq!(args.network_change_delay, Duration::from_secs(2));
+
+        let args = Options::parse_args(&[
+            "proxydetox".into(),
+            "--on-network-change".into(),
+            "reload".into(),
+            "--network-change-delay".into(),
+            "0.5".into(),
+        ]);
+        assert_eq!(args.on_network_change, NetworkChange::Reload);
+        assert_eq!(args.network_change_delay, Duration::from_millis(500));
+    }
+
     #[test]
     fn test_tcp_keep_alive() {
         let args = &[
@@ -1,10 +1,43 @@
-use std::{fmt::Write, net::SocketAddr};
+mod writer;
+
+use std::{fmt::Write, net::SocketAddr, str::FromStr};
 
 use chrono::{DateTime, Duration, Local, SecondsFormat};
 
 use http::StatusCode;
 use paclib::ProxyOrDirect;
 
+pub use writer::{Config, Handle, Writer};
+
+/// Format of the access log file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Same as the `/access.log` event stream of the management console.
+    #[default]
+    Text,
+    /// Common Log Format of the Apache HTTP server.
+    Common,
+    /// Combined Log Format of the Apache HTTP server, the Common Log Format with referer and
+    /// user agent.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "common" => Ok(Self::Common),
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown access log format: {s}")),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 enum Response {
     Success {
@@ -22,6 +55,7 @@ pub struct Entry {
     uri: http::Uri,
     version: http::Version,
     user_agent: Option<String>,
+    referer: Option<String>,
     proxy: Option<ProxyOrDirect>,
     response: Response,
     duration: Duration,
@@ -34,6 +68,7 @@ pub struct EntryBegin {
     uri: http::Uri,
     version: http::Version,
     user_agent: Option<String>,
+    referer: Option<String>,
 }
 
 impl EntryBegin {
@@ -50,6 +85,7 @@ impl EntryBegin {
             uri: self.uri,
             version: self.version,
             user_agent: self.user_agent,
+            referer: self.referer,
             proxy: Some(proxy),
             response: Response::Success { status_code, bytes },
             duration: Local::now() - self.timestamp,
@@ -64,6 +100,7 @@ impl EntryBegin {
             uri: self.uri,
             version: self.version,
             user_agent: self.user_agent,
+            referer: self.referer,
             proxy,
             response: Response::Error(error.to_string()),
             duration: Local::now() - self.timestamp,
@@ -78,6 +115,7 @@ impl Entry {
         uri: http::Uri,
         version: http::Version,
         user_agent: Option<String>,
+        referer: Option<String>,
     ) -> EntryBegin {
         EntryBegin {
             timestamp: Local::now(),
@@ -86,8 +124,80 @@ impl Entry {
             uri,
             version,
             user_agent,
+            referer,
+        }
+    }
+}
+
+impl Entry {
+    /// Format the entry as one line, without the line break.
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_string(),
+            Format::Common => self.common(),
+            Format::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                self.common(),
+                self.referer
+                    .as_deref()
+                    .map(quote)
+                    .unwrap_or_else(|| "-".into()),
+                self.user_agent
+                    .as_deref()
+                    .map(quote)
+                    .unwrap_or_else(|| "-".into())
+            ),
+            Format::Json => self.json(),
         }
     }
+
+    /// `host ident user [time] "request" status bytes`, unknown values are replaced by `-`.
+    fn common(&self) -> String {
+        let (status, bytes) = match self.response {
+            Response::Success { status_code, bytes } => (
+                status_code.as_u16().to_string(),
+                bytes.map(|b| b.to_string()).unwrap_or_else(|| "-".into()),
+            ),
+            Response::Error(_) => ("-".into(), "-".into()),
+        };
+        format!(
+            "{} - - [{}] \"{} {} {:?}\" {} {}",
+            self.peer_addr.ip(),
+            self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.uri,
+            self.version,
+            status,
+            bytes
+        )
+    }
+
+    fn json(&self) -> String {
+        let (status, bytes, error) = match self.response {
+            Response::Success { status_code, bytes } => (Some(status_code.as_u16()), bytes, None),
+            Response::Error(ref cause) => (None, None, Some(cause.as_str())),
+        };
+        serde_json::json!({
+            "timestamp": self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, false),
+            "peer_addr": self.peer_addr.to_string(),
+            "method": self.method.as_str(),
+            "uri": self.uri.to_string(),
+            "version": format!("{:?}", self.version),
+            "proxy": self.proxy.as_ref().map(|p| p.to_string()),
+            "status": status,
+            "bytes": bytes,
+            "error": error,
+            "duration": self.duration.num_microseconds().map(|us| us as f64 * 1e-6),
+            "user_agent": self.user_agent,
+            "referer": self.referer,
+        })
+        .to_string()
+    }
+}
+
+/// Quote a value of the Combined Log Format, without the surrounding quotes.
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
 }
 
 impl std::fmt::Display for Entry {
@@ -137,7 +247,7 @@ impl std::fmt::Display for Entry {
 mod tests {
     use paclib::{Proxy, ProxyOrDirect};
 
-    use super::Entry;
+    use super::{Entry, Format};
 
     #[test]
     fn test_success_entry() {
@@ -147,6 +257,7 @@ mod tests {
             "http://localhost:8080".parse().unwrap(),
             http::Version::HTTP_11,
             Some("curl/7.79.1".to_string()),
+            None,
         );
         let entry = entry.success(
             ProxyOrDirect::Proxy(Proxy::Http("127.0.0.1:8080".parse().unwrap())),
@@ -174,6 +285,7 @@ mod tests {
             "http://localhost:8080".parse().unwrap(),
             http::Version::HTTP_11,
             Some("curl/7.79.1".to_string()),
+            None,
         );
         let entry = entry.success(ProxyOrDirect::Direct, http::StatusCode::OK, None);
         let entry = entry.to_string();
@@ -189,6 +301,7 @@ mod tests {
             "http://localhost:8080".parse().unwrap(),
             http::Version::HTTP_11,
             None,
+            None,
         );
         let entry = entry.success(ProxyOrDirect::Direct, http::StatusCode::OK, Some(1024));
         let entry = entry.to_string();
@@ -204,6 +317,7 @@ mod tests {
             "http://localhost:8080".parse().unwrap(),
             http::Version::HTTP_11,
             Some("curl/7.79.1".to_string()),
+            None,
         );
         let entry = entry.error(Some(ProxyOrDirect::Direct), &std::io::Error::other("ERROR"));
         let entry = entry.to_string();
@@ -216,4 +330,39 @@ mod tests {
         assert!(entry.contains("ERROR"));
         assert!(entry.contains("\"curl/7.79.1\""));
     }
+
+    #[test]
+    fn test_formats() {
+        let entry = Entry::begin(
+            "127.0.0.1:34524".parse().unwrap(),
+            http::Method::GET,
+            "http://localhost:8080/index.html".parse().unwrap(),
+            http::Version::HTTP_11,
+            Some("curl \"7\"".to_string()),
+            Some("http://localhost:8080/".to_string()),
+        );
+        let entry = entry.success(ProxyOrDirect::Direct, http::StatusCode::OK, Some(4096));
+
+        let common = entry.format(Format::Common);
+        assert!(common.starts_with("127.0.0.1 - - ["));
+        assert!(common.ends_with("] \"GET http://localhost:8080/index.html HTTP/1.1\" 200 4096"));
+
+        let combined = entry.format(Format::Combined);
+        assert_eq!(
+            combined,
+            format!("{common} \"http://localhost:8080/\" \"curl \\\"7\\\"\"")
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&entry.format(Format::Json)).unwrap();
+        assert_eq!(json["peer_addr"], "127.0.0.1:34524");
+        assert_eq!(json["proxy"], "DIRECT");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["bytes"], 4096);
+        assert_eq!(json["referer"], "http://localhost:8080/");
+        assert!(json["error"].is_null());
+
+        assert_eq!(entry.format(Format::Text), entry.to_string());
+        assert_eq!("combined".parse::<Format>(), Ok(Format::Combined));
+        assert!("apache".parse::<Format>().is_err());
+    }
 }
@@ -0,0 +1,253 @@
+//! Access log file with size and time based rotation.
+
+use super::{Entry, Format};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, oneshot};
+
+/// Number of entries which may wait for the writer thread before requests are slowed down.
+const QUEUE_SIZE: usize = 1024;
+
+/// Where and how the access log is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub path: PathBuf,
+    pub format: Format,
+    /// Rotate the file when it reached this size in bytes.
+    pub max_size: Option<u64>,
+    /// Rotate the file when it is older than this.
+    pub max_age: Option<Duration>,
+    /// Number of rotated files to keep, `<path>.1` is the most recent one.
+    pub keep: usize,
+}
+
+/// Appends the entries to the access log file.
+#[derive(Debug)]
+pub struct Writer {
+    config: Config,
+    file: File,
+    size: u64,
+    opened: SystemTime,
+}
+
+impl Writer {
+    pub fn open(config: Config) -> io::Result<Self> {
+        let (file, size) = open(&config.path)?;
+        Ok(Self {
+            config,
+            file,
+            size,
+            opened: SystemTime::now(),
+        })
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        if self.needs_rotation() {
+            self.rotate()?;
+        }
+        let mut line = entry.format(self.config.format);
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Open the file again, e.g. after it was moved away by `logrotate`.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        (self.file, self.size) = open(&self.config.path)?;
+        self.opened = SystemTime::now();
+        Ok(())
+    }
+
+    /// Move the writer to its own thread, such that writing and rotating the file does not block
+    /// the runtime. The thread ends when all handles are dropped.
+    pub fn spawn(mut self) -> io::Result<Handle> {
+        let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name("accesslog".into())
+            .spawn(move || {
+                while let Some(message) = rx.blocking_recv() {
+                    match message {
+                        Message::Entry(entry) => {
+                            if let Err(cause) = self.write(&entry) {
+                                tracing::error!(%cause, "failed to write access log");
+                            }
+                        }
+                        Message::Reopen(done) => {
+                            done.send(self.reopen()).ok();
+                        }
+                    }
+                }
+            })?;
+        Ok(Handle { tx })
+    }
+
+    fn needs_rotation(&self) -> bool {
+        let too_large = self.config.max_size.is_some_and(|max| self.size >= max);
+        let too_old = self
+            .config
+            .max_age
+            .is_some_and(|max| self.opened.elapsed().is_ok_and(|age| age >= max));
+        self.size > 0 && (too_large || too_old)
+    }
+
+    /// Rename `<path>.N` to `<path>.N+1` and `<path>` to `<path>.1`, the oldest files beyond
+    /// `keep` are removed.
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = &self.config.path;
+        if self.config.keep == 0 {
+            std::fs::remove_file(path)?;
+        } else {
+            remove_if_exists(&rotated(path, self.config.keep))?;
+            for n in (1..self.config.keep).rev() {
+                let from = rotated(path, n);
+                if from.exists() {
+                    std::fs::rename(&from, rotated(path, n + 1))?;
+                }
+            }
+            std::fs::rename(path, rotated(path, 1))?;
+        }
+        tracing::debug!(path = %path.display(), "rotated access log");
+        self.reopen()
+    }
+}
+
+#[derive(Debug)]
+enum Message {
+    Entry(Box<Entry>),
+    Reopen(oneshot::Sender<io::Result<()>>),
+}
+
+/// Sends the entries to a [`Writer`] running on its own thread, see [`Writer::spawn`].
+#[derive(Debug, Clone)]
+pub struct Handle {
+    tx: mpsc::Sender<Message>,
+}
+
+impl Handle {
+    /// Queue `entry`, waits while the queue is full such that no entry is lost.
+    pub async fn write(&self, entry: Entry) {
+        self.tx.send(Message::Entry(Box::new(entry))).await.ok();
+    }
+
+    /// Open the file again after all queued entries are written.
+    pub async fn reopen(&self) -> io::Result<()> {
+        let (done, result) = oneshot::channel();
+        self.tx
+            .send(Message::Reopen(done))
+            .await
+            .map_err(|_| io::Error::other("access log writer stopped"))?;
+        result
+            .await
+            .map_err(|_| io::Error::other("access log writer stopped"))?
+    }
+}
+
+fn open(path: &Path) -> io::Result<(File, u64)> {
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paclib::ProxyOrDirect;
+
+    fn entry() -> Entry {
+        Entry::begin(
+            "127.0.0.1:34524".parse().unwrap(),
+            http::Method::GET,
+            "http://localhost:8080/".parse().unwrap(),
+            http::Version::HTTP_11,
+            None,
+            None,
+        )
+        .success(ProxyOrDirect::Direct, http::StatusCode::OK, None)
+    }
+
+    fn lines(path: &Path) -> usize {
+        std::fs::read_to_string(path)
+            .map(|s| s.lines().count())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = std::env::temp_dir().join(format!("proxydetox-accesslog-{}", std::process::id()));
+        let path = dir.join("access.log");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut writer = Writer::open(Config {
+            path: path.clone(),
+            format: Format::Common,
+            max_size: Some(1),
+            max_age: None,
+            keep: 2,
+        })
+        .unwrap();
+        for _ in 0..4 {
+            writer.write(&entry()).unwrap();
+        }
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&rotated(&path, 1)), 1);
+        assert_eq!(lines(&rotated(&path, 2)), 1);
+        assert!(!rotated(&path, 3).exists());
+
+        // the file was moved away, e.g. by logrotate
+        std::fs::rename(&path, dir.join("moved.log")).unwrap();
+        writer.reopen().unwrap();
+        assert!(path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_on_thread() {
+        let dir = std::env::temp_dir().join(format!(
+            "proxydetox-accesslog-thread-{}",
+            std::process::id()
+        ));
+        let path = dir.join("access.log");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let handle = Writer::open(Config {
+            path: path.clone(),
+            format: Format::Common,
+            max_size: None,
+            max_age: None,
+            keep: 0,
+        })
+        .unwrap()
+        .spawn()
+        .unwrap();
+        for _ in 0..2 * QUEUE_SIZE {
+            handle.write(entry()).await;
+        }
+        // reopen waits for the queued entries
+        handle.reopen().await.unwrap();
+        assert_eq!(lines(&path), 2 * QUEUE_SIZE);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
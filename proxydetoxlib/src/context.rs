@@ -1,18 +1,30 @@
 pub mod builder;
+mod pac;
+
+pub use pac::PacStatus;
 
 use crate::accesslog;
+use crate::integrity;
+use crate::metrics::Metrics;
 use detox_auth::AuthenticatorFactory;
 use detox_futures::FutureExt;
-use detox_hyper::conn::Connection;
-use detox_net::{HostAndPort, PathOrUri, TcpKeepAlive};
+use detox_hyper::BootstrapProxy;
+use detox_hyper::body::BufferedBody;
+use detox_hyper::conn::{Connection, ConnectionBuilder};
+use detox_hyper::h2;
+use detox_hyper::pool::{self, Pool, Pooled};
+use detox_net::dns::Resolver;
+use detox_net::{HostAndPort, Metered, PathOrUri, TcpKeepAlive, copy_bidirectional};
 use http::Uri;
-use paclib::ProxyOrDirect;
-use std::fs::read_to_string;
+use paclib::{Proxy, ProxyOrDirect};
 use std::future::IntoFuture;
-use std::net::IpAddr;
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::broadcast::Sender;
 use tracing::field::debug;
 use tracing_attributes::instrument;
@@ -63,6 +75,37 @@ pub struct Context {
     pub(super) connect_timeout: Duration,
     pub(super) client_tcp_keepalive: TcpKeepAlive,
     pub(super) accesslog_tx: Sender<accesslog::Entry>,
+    pub(super) pool: Pool<BufferedBody>,
+    pub(super) request_buffer_size: usize,
+    pub(super) h2_sessions: Option<h2::Sessions>,
+    pub(super) resolver: Resolver,
+    pub(super) pac_state: Mutex<pac::State>,
+    pub(super) pac_backup_file: Option<PathBuf>,
+    pub(super) pac_proxy: Option<BootstrapProxy>,
+    pub(super) pac_verifier: integrity::Verifier,
+    /// PAC file given at start, the direct mode is used without one.
+    pub(super) pac_file: Option<PathOrUri>,
+    pub(super) direct_mode: AtomicBool,
+    pub(super) my_ip_address: Mutex<Option<IpAddr>>,
+    pub(super) metrics: Metrics,
+    pub(super) access_log: Option<accesslog::Handle>,
+}
+
+/// Connection to the upstream server or proxy returned by [`Context::connect`].
+pub(super) enum Upstream {
+    /// Byte stream for `CONNECT` requests.
+    Stream(Box<Connection>),
+    /// HTTP/1.1 connection for all other requests, which might be reused from the pool.
+    Http(Box<Pooled<BufferedBody>>),
+}
+
+impl Upstream {
+    pub(super) fn proxy(&self) -> &ProxyOrDirect {
+        match self {
+            Self::Stream(conn) => conn.proxy(),
+            Self::Http(conn) => conn.proxy(),
+        }
+    }
 }
 
 impl Context {
@@ -71,48 +114,102 @@ impl Context {
     }
 
     pub(super) async fn find_proxy(&self, uri: Uri) -> paclib::Proxies {
-        let mut proxies = self
-            .eval
-            .find_proxy(uri.clone())
-            .await
-            .unwrap_or_else(|cause| {
-                tracing::error!(%cause, %uri, "failed to find_proxy");
-                paclib::Proxies::direct()
-            });
+        if self.is_direct_mode() {
+            return paclib::Proxies::direct();
+        }
+        let start = Instant::now();
+        let proxies = self.eval.find_proxy(uri.clone()).await;
+        self.metrics.pac_eval_duration(start.elapsed());
+        let mut proxies = proxies.unwrap_or_else(|cause| {
+            tracing::error!(%cause, %uri, "failed to find_proxy");
+            paclib::Proxies::direct()
+        });
         if self.direct_fallback && !proxies.iter().any(|p| *p == ProxyOrDirect::Direct) {
             proxies.push(ProxyOrDirect::Direct);
         }
         proxies
     }
 
-    #[instrument(skip(self))]
-    pub async fn load_pac_file(&self, uri: &Option<PathOrUri>) -> std::io::Result<()> {
-        tracing::info!("update PAC script");
-        let pac = if let Some(uri) = uri {
-            let pac = match uri {
-                PathOrUri::Path(p) => read_to_string(p)?,
-                PathOrUri::Uri(u) => detox_hyper::http_file(u.clone(), self.tls_config.clone())
-                    .timeout(Duration::from_secs(15))
-                    .await
-                    .map_err(std::io::Error::other)??,
-            };
-            Some(pac)
-        } else {
-            None
-        };
-        self.eval
-            .set_pac_script(pac)
-            .await
-            .map_err(std::io::Error::other)
-    }
-
     #[instrument(skip(self))]
     pub async fn set_my_ip_address(&self, addr: IpAddr) -> std::io::Result<()> {
         tracing::info!("update my IP address");
         self.eval
             .set_my_ip_address(addr)
             .await
-            .map_err(std::io::Error::other)
+            .map_err(std::io::Error::other)?;
+        *self.my_ip_address.lock().unwrap() = Some(addr);
+        Ok(())
+    }
+
+    /// Address returned by `myIpAddress`, if it was set.
+    pub fn my_ip_address(&self) -> Option<IpAddr> {
+        *self.my_ip_address.lock().unwrap()
+    }
+
+    /// In direct mode all requests are send `DIRECT`, the PAC script is kept but not evaluated.
+    pub fn set_direct_mode(&self, enabled: bool) {
+        tracing::info!(enabled, "direct mode");
+        self.direct_mode.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_direct_mode(&self) -> bool {
+        self.direct_mode.load(Ordering::Relaxed)
+    }
+
+    /// PAC file given at start.
+    pub fn pac_file(&self) -> &Option<PathOrUri> {
+        &self.pac_file
+    }
+
+    /// Open the access log file again, e.g. after it was rotated by `logrotate`.
+    pub async fn reopen_access_log(&self) -> std::io::Result<()> {
+        match self.access_log {
+            Some(ref writer) => writer.reopen().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Write `entry` to the access log file and the `/access.log` event stream.
+    pub(super) async fn log_access(&self, entry: accesslog::Entry) {
+        if let Some(ref writer) = self.access_log {
+            writer.write(entry.clone()).await;
+        }
+        self.accesslog_tx.send(entry).ok();
+    }
+
+    /// Read the DNS configuration of the system again, e.g. after the network changed.
+    pub fn reload_resolver(&self) {
+        self.resolver.reload();
+    }
+
+    /// Count a failed authentication with `proxy` by the scheme negotiated with it.
+    pub(super) fn auth_failure(&self, proxy: &Proxy) {
+        self.metrics
+            .auth_failure(self.auth.negotiated_scheme(proxy.host()));
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Copy the data of a tunnel from `peer` to `destination` between the client and the
+    /// upstream connection, the tunnel is listed in the metrics while it is open.
+    pub(super) async fn copy_tunnel<A, B>(
+        &self,
+        peer: SocketAddr,
+        destination: &Uri,
+        proxy: &ProxyOrDirect,
+        client: &mut A,
+        upstream: &mut B,
+    ) -> std::io::Result<()>
+    where
+        A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+        B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    {
+        let active = self.metrics.tunnel(peer, destination, proxy);
+        let mut upstream = Metered::with_counters(upstream, active.counters());
+        copy_bidirectional(client, &mut upstream).await?;
+        Ok(())
     }
 
     /// Establish a connection to parent proxy.
@@ -120,19 +217,132 @@ impl Context {
     /// In case of `CONNECT` the connesction will be established so far that `CONNECT` request is
     /// send, but not the client request.
     /// For upstream servers which can be connected directly a TCP connection will be established.
-    #[instrument(level = "debug", skip(self, method), fields(proxy = %proxy, tunnel, duration))]
+    /// For all other methods an idle connection from the pool is used when available.
+    #[instrument(level = "debug", skip(self, method), fields(proxy = %proxy))]
     pub(super) async fn connect(
         self: Arc<Self>,
         proxy: ProxyOrDirect,
         method: http::Method,
         uri: http::Uri,
-    ) -> Result<Connection, Error> {
+    ) -> Result<Upstream, Error> {
+        let dst = HostAndPort::try_from_uri(&uri)?;
+        if method != hyper::Method::CONNECT
+            && let Some(conn) = self.pool.checkout(&pool::Key::new(proxy.clone(), dst))
+        {
+            tracing::debug!("reuse pooled connection");
+            return Ok(Upstream::Http(Box::new(conn)));
+        }
+
+        self.reconnect(proxy, method, uri).await
+    }
+
+    /// Establish a `CONNECT` tunnel to `uri` via the first reachable proxy of the PAC script.
+    pub(super) async fn tunnel(self: Arc<Self>, uri: Uri) -> Result<Box<Connection>, Error> {
+        for proxy in self.find_proxy(uri.clone()).await {
+            match self
+                .clone()
+                .connect(proxy, http::Method::CONNECT, uri.clone())
+                .await
+            {
+                Ok(Upstream::Stream(conn)) => return Ok(conn),
+                Ok(Upstream::Http(_)) => {}
+                Err(cause) => tracing::warn!(%cause, "unable to connect"),
+            }
+        }
+        Err(Error::UnableToEstablishConnection(uri))
+    }
+
+    /// Establish a new connection like [`Context::connect`], but without using the pool.
+    #[instrument(level = "debug", skip(self, method), fields(proxy = %proxy, tunnel, duration))]
+    pub(super) async fn reconnect(
+        self: Arc<Self>,
+        proxy: ProxyOrDirect,
+        method: http::Method,
+        uri: http::Uri,
+    ) -> Result<Upstream, Error> {
         let dst = HostAndPort::try_from_uri(&uri)?;
         let tunnel = method == hyper::Method::CONNECT || self.proxytunnel;
         tracing::Span::current().record("tunnel", tunnel);
 
+        let key = pool::Key::new(proxy.clone(), dst.clone());
+        let conn = self.connection(&proxy, dst, tunnel);
+
+        self.metrics.connect_attempt(&proxy);
+        let start = Instant::now();
+        let conn = match conn
+            .into_future()
+            .timeout(self.connect_timeout * if tunnel { 2 } else { 1 })
+            .await
+        {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => {
+                self.metrics.connect_failure(&proxy);
+                if e.kind() == std::io::ErrorKind::PermissionDenied
+                    && let ProxyOrDirect::Proxy(proxy) = &proxy
+                {
+                    self.auth_failure(proxy);
+                }
+                return Err(Error::Connect(e, proxy, uri));
+            }
+            Err(_) => {
+                self.metrics.connect_timeout(&proxy);
+                return Err(Error::ConnectTimeout(proxy, uri));
+            }
+        };
+        self.metrics.connect_duration(&proxy, start.elapsed());
+        tracing::Span::current().record("duration", debug(&start.elapsed()));
+        tracing::debug!("connect");
+
+        if method == hyper::Method::CONNECT {
+            Ok(Upstream::Stream(Box::new(conn)))
+        } else {
+            let conn = conn.handshake().await?;
+            Ok(Upstream::Http(Box::new(self.pool.pooled(key, conn))))
+        }
+    }
+
+    /// Connect via `proxy` like [`Context::reconnect`] to test whether it is reachable.
+    ///
+    /// Neither the metrics are updated nor the connection is returned to the pool.
+    #[instrument(level = "debug", skip(self, method), fields(proxy = %proxy, tunnel))]
+    pub(super) async fn probe(
+        &self,
+        proxy: ProxyOrDirect,
+        method: http::Method,
+        uri: http::Uri,
+    ) -> Result<(), Error> {
+        let dst = HostAndPort::try_from_uri(&uri)?;
+        let tunnel = method == hyper::Method::CONNECT || self.proxytunnel;
+        tracing::Span::current().record("tunnel", tunnel);
+
+        let conn = match self
+            .connection(&proxy, dst, tunnel)
+            .into_future()
+            .timeout(self.connect_timeout * if tunnel { 2 } else { 1 })
+            .await
+        {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => return Err(Error::Connect(e, proxy, uri)),
+            Err(_) => return Err(Error::ConnectTimeout(proxy, uri)),
+        };
+        if method != hyper::Method::CONNECT {
+            conn.handshake::<BufferedBody>().await?;
+        }
+        Ok(())
+    }
+
+    /// The connection to `dst` via `proxy`, not yet established.
+    fn connection(
+        &self,
+        proxy: &ProxyOrDirect,
+        dst: HostAndPort,
+        tunnel: bool,
+    ) -> ConnectionBuilder {
         let conn = match proxy {
-            ProxyOrDirect::Proxy(ref proxy) => {
+            ProxyOrDirect::Proxy(proxy) if proxy.is_socks() => {
+                Connection::socks(proxy.clone(), self.auth.clone(), dst)
+            }
+            ProxyOrDirect::Proxy(proxy) => {
                 if tunnel {
                     Connection::http_tunnel(
                         proxy.clone(),
@@ -148,24 +358,12 @@ impl Context {
                     )
                 }
             }
-            ProxyOrDirect::Direct => Connection::http(dst),
+            ProxyOrDirect::Direct => Connection::http(dst).with_resolver(self.resolver.clone()),
         };
-        let conn = conn.with_tcp_keepalive(self.client_tcp_keepalive.clone());
-
-        let start = Instant::now();
-        let conn = conn
-            .into_future()
-            .timeout(self.connect_timeout * if tunnel { 2 } else { 1 })
-            .await
-            .map_err({
-                let proxy = proxy.clone();
-                let uri = uri.clone();
-                move |_| Error::ConnectTimeout(proxy, uri)
-            })?
-            .map_err(move |e| Error::Connect(e, proxy, uri))?;
-        tracing::Span::current().record("duration", debug(&start.elapsed()));
-        tracing::debug!("connect");
-
-        Ok(conn)
+        let mut conn = conn.with_tcp_keepalive(self.client_tcp_keepalive.clone());
+        if let Some(sessions) = &self.h2_sessions {
+            conn = conn.with_h2_sessions(sessions.clone());
+        }
+        conn
     }
 }
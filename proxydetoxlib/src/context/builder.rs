@@ -1,7 +1,15 @@
 use super::Context;
+use crate::accesslog;
+use crate::integrity::{PublicKey, Sha256, Verifier};
 use detox_auth::AuthenticatorFactory;
+use detox_hyper::BootstrapProxy;
+use detox_hyper::h2;
+use detox_hyper::pool::{self, Pool};
+use detox_net::dns::Resolver;
 use detox_net::{PathOrUri, TcpKeepAlive};
-use paclib::Evaluator;
+use paclib::{Evaluator, Proxy};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast;
@@ -10,6 +18,21 @@ use tokio::sync::broadcast;
 pub struct Builder {
     pac_file: Option<PathOrUri>,
     pac_script: Option<String>,
+    pac_workers: Option<usize>,
+    pac_cache_ttl: Option<Duration>,
+    pac_cache_size: Option<usize>,
+    pac_loop_limit: Option<u64>,
+    pac_recursion_limit: Option<usize>,
+    pac_timeout: Option<Duration>,
+    pac_refresh_interval: Option<Duration>,
+    pac_backup_file: Option<PathBuf>,
+    pac_proxy: Option<Proxy>,
+    pac_sha256: Vec<Sha256>,
+    pac_public_key: Option<PublicKey>,
+    dns_servers: Vec<SocketAddr>,
+    dns_search: Vec<String>,
+    dns_timeout: Option<Duration>,
+    dns_negative_ttl: Option<Duration>,
     auth: Option<AuthenticatorFactory>,
     proxytunnel: bool,
     direct_fallback: bool,
@@ -18,6 +41,10 @@ pub struct Builder {
     race_connect: bool,
     parallel_connect: usize,
     client_tcp_keepalive: TcpKeepAlive,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    request_buffer_size: Option<usize>,
+    access_log: Option<accesslog::Handle>,
 }
 
 impl Builder {
@@ -34,6 +61,105 @@ impl Builder {
         self.pac_script = Some(script);
         self
     }
+
+    /// Number of threads which evaluate the PAC script in parallel.
+    pub fn pac_workers(mut self, num: usize) -> Self {
+        self.pac_workers = Some(num);
+        self
+    }
+
+    /// Time for which the results of the PAC script are cached.
+    /// Zero (the default) disables the cache.
+    pub fn pac_cache_ttl(mut self, duration: Duration) -> Self {
+        self.pac_cache_ttl = Some(duration);
+        self
+    }
+
+    /// Maximum number of cached PAC script results.
+    pub fn pac_cache_size(mut self, num: usize) -> Self {
+        self.pac_cache_size = Some(num);
+        self
+    }
+
+    /// Maximum number of loop iterations within one function call of the PAC script.
+    pub fn pac_loop_limit(mut self, num: u64) -> Self {
+        self.pac_loop_limit = Some(num);
+        self
+    }
+
+    /// Maximum depth of nested function calls of the PAC script.
+    pub fn pac_recursion_limit(mut self, num: usize) -> Self {
+        self.pac_recursion_limit = Some(num);
+        self
+    }
+
+    /// Maximum time of one PAC script evaluation, after which DIRECT is used.
+    pub fn pac_timeout(mut self, duration: Duration) -> Self {
+        self.pac_timeout = Some(duration);
+        self
+    }
+
+    /// Interval in which a downloaded PAC file is checked for modifications.
+    /// Zero (the default) disables the periodic refresh.
+    pub fn pac_refresh_interval(mut self, duration: Duration) -> Self {
+        self.pac_refresh_interval = Some(duration);
+        self
+    }
+
+    /// File where the last successfully downloaded PAC script is kept, which is used when the
+    /// PAC file cannot be downloaded.
+    pub fn pac_backup_file(mut self, path: Option<PathBuf>) -> Self {
+        self.pac_backup_file = path;
+        self
+    }
+
+    /// Proxy used to download the PAC file, with the same authentication as the other proxies.
+    /// If `None`, the PAC file is downloaded directly.
+    pub fn pac_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.pac_proxy = proxy;
+        self
+    }
+
+    /// Only accept PAC scripts with one of these SHA-256 digests.
+    /// If empty, the digest is not checked.
+    pub fn pac_sha256(mut self, digests: Vec<Sha256>) -> Self {
+        self.pac_sha256 = digests;
+        self
+    }
+
+    /// Only accept PAC scripts with a detached minisign signature (`proxy.pac.minisig`) of this key.
+    /// If `None`, no signature is required.
+    pub fn pac_public_key(mut self, key: Option<PublicKey>) -> Self {
+        self.pac_public_key = key;
+        self
+    }
+
+    /// Name servers used by the PAC script and for direct connections.
+    /// If empty, the name servers of `/etc/resolv.conf` are used.
+    pub fn dns_servers(mut self, nameservers: Vec<SocketAddr>) -> Self {
+        self.dns_servers = nameservers;
+        self
+    }
+
+    /// Search domains appended to short host names.
+    /// If empty, the search domains of `/etc/resolv.conf` are used.
+    pub fn dns_search(mut self, domains: Vec<String>) -> Self {
+        self.dns_search = domains;
+        self
+    }
+
+    /// Time to wait for the answer of one DNS query.
+    pub fn dns_timeout(mut self, duration: Duration) -> Self {
+        self.dns_timeout = Some(duration);
+        self
+    }
+
+    /// Maximum time for which failed DNS lookups are cached.
+    pub fn dns_negative_ttl(mut self, duration: Duration) -> Self {
+        self.dns_negative_ttl = Some(duration);
+        self
+    }
+
     /// Authenticator factory (Basic or Negotiate)
     /// If `None`, use no authentication toward the proxy.
     pub fn authenticator_factory(mut self, factory: Option<AuthenticatorFactory>) -> Self {
@@ -82,25 +208,77 @@ impl Builder {
         self
     }
 
+    /// Time after which idle upstream connections are closed, defaults to
+    /// [`pool::DEFAULT_IDLE_TIMEOUT`].
+    pub fn pool_idle_timeout(mut self, duration: Option<Duration>) -> Self {
+        self.pool_idle_timeout = duration;
+        self
+    }
+
+    /// Maximum number of idle upstream connections kept per proxy and destination, defaults
+    /// to [`pool::DEFAULT_MAX_IDLE_PER_KEY`]. Zero disables connection pooling.
+    pub fn pool_max_idle_per_host(mut self, num: Option<usize>) -> Self {
+        self.pool_max_idle_per_host = num;
+        self
+    }
+
+    /// Maximum size of a request body which is kept in memory, such that the request can be
+    /// send again after a `407` response or when a pooled connection turned out to be closed.
+    pub fn request_buffer_size(mut self, size: usize) -> Self {
+        self.request_buffer_size = Some(size);
+        self
+    }
+
+    /// Write each access log entry to a file, see [`accesslog::Writer::spawn`].
+    pub fn access_log(mut self, writer: Option<accesslog::Handle>) -> Self {
+        self.access_log = writer;
+        self
+    }
+
     pub fn build(self) -> Arc<Context> {
         let auth = self.auth.unwrap_or(AuthenticatorFactory::None);
-        let eval = if let Some(pac) = self.pac_script {
-            Evaluator::with_pac_script(&pac).unwrap_or_default()
-        } else {
-            Evaluator::new()
-        };
-        let tls_config = self.tls_config.unwrap_or_else(default_tls_config);
-        let (accesslog_tx, mut accesslog_rx) = broadcast::channel(16);
-        tokio::spawn(async move {
-            loop {
-                let entry = accesslog_rx.recv().await;
-                if let Err(cause) = entry
-                    && cause == broadcast::error::RecvError::Closed
-                {
-                    break;
+        // NTLM authenticates the connection, which does not work with multiplexed HTTP/2 streams
+        let h2_sessions = (!matches!(
+            auth,
+            AuthenticatorFactory::Ntlm(_) | AuthenticatorFactory::Auto(_, _)
+        ))
+        .then(h2::Sessions::new);
+        let mut resolver = Resolver::builder().nameservers(self.dns_servers);
+        if !self.dns_search.is_empty() {
+            resolver = resolver.search(self.dns_search);
+        }
+        if let Some(timeout) = self.dns_timeout {
+            resolver = resolver.timeout(timeout);
+        }
+        if let Some(ttl) = self.dns_negative_ttl {
+            resolver = resolver.negative_ttl(ttl);
+        }
+        let resolver = resolver.build();
+        let eval = Evaluator::builder()
+            .pac_script(self.pac_script)
+            .workers(self.pac_workers.unwrap_or(1))
+            .cache_ttl(self.pac_cache_ttl.unwrap_or_default())
+            .cache_size(self.pac_cache_size.unwrap_or(1024))
+            .limits({
+                let mut limits = paclib::Limits::default();
+                if let Some(num) = self.pac_loop_limit {
+                    limits.loop_iterations = num;
                 }
-            }
+                if let Some(num) = self.pac_recursion_limit {
+                    limits.recursion = num;
+                }
+                limits
+            })
+            .timeout(self.pac_timeout.unwrap_or(Duration::from_secs(10)))
+            .resolver(resolver.clone())
+            .build();
+        let tls_config = self.tls_config.unwrap_or_else(default_tls_config);
+        let pac_proxy = self.pac_proxy.map(|proxy| BootstrapProxy {
+            proxy,
+            auth: auth.clone(),
         });
+        // only for the `/access.log` event stream, the file is written via `access_log`
+        let (accesslog_tx, _) = broadcast::channel(256);
         let context = Context {
             eval,
             auth,
@@ -112,9 +290,38 @@ impl Builder {
             connect_timeout: self.connect_timeout.unwrap_or(Duration::new(30, 0)),
             client_tcp_keepalive: self.client_tcp_keepalive,
             accesslog_tx,
+            pool: Pool::new(
+                self.pool_idle_timeout.unwrap_or(pool::DEFAULT_IDLE_TIMEOUT),
+                self.pool_max_idle_per_host
+                    .unwrap_or(pool::DEFAULT_MAX_IDLE_PER_KEY),
+            ),
+            request_buffer_size: self.request_buffer_size.unwrap_or(1024 * 1024),
+            h2_sessions,
+            resolver,
+            pac_state: Default::default(),
+            pac_backup_file: self.pac_backup_file,
+            pac_proxy,
+            pac_verifier: Verifier {
+                digests: self.pac_sha256,
+                public_key: self.pac_public_key,
+            },
+            pac_file: self.pac_file.clone(),
+            direct_mode: Default::default(),
+            my_ip_address: Default::default(),
+            metrics: Default::default(),
+            access_log: self.access_log,
         };
         let context = Arc::new(context);
 
+        if let Some(interval) = self.pac_refresh_interval
+            && !interval.is_zero()
+        {
+            tokio::spawn(super::pac::refresh_periodically(
+                Arc::downgrade(&context),
+                interval,
+            ));
+        }
+
         if self.pac_file.is_some() {
             tokio::spawn({
                 let context = context.clone();
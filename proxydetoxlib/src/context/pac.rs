@@ -0,0 +1,337 @@
+//! Loading and periodic refresh of the PAC script.
+
+use super::Context;
+use crate::wpad;
+use detox_futures::FutureExt;
+use detox_hyper::{HttpFile, Validators};
+use detox_net::PathOrUri;
+use http::Uri;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::time::{Duration, SystemTime};
+use tracing_attributes::instrument;
+
+/// Maximum time to download a PAC file.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(15);
+/// First line of the backup file, followed by the URL the PAC script was downloaded from.
+const BACKUP_HEADER: &str = "// proxydetox backup of ";
+
+/// Where the current PAC script came from.
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    /// Source passed to the last [`Context::load_pac_file`] call, `None` without a PAC file.
+    source: Option<PathOrUri>,
+    /// URL the current PAC script was downloaded from.
+    uri: Option<Uri>,
+    validators: Validators,
+    /// Why the last PAC script was refused, shown on the management console.
+    refused: Option<String>,
+    /// When the current PAC script was loaded.
+    loaded_at: Option<SystemTime>,
+    /// Why the last load or refresh failed.
+    error: Option<String>,
+}
+
+/// Where the current PAC script came from and whether loading it succeeded.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PacStatus {
+    /// Source passed to the last [`Context::load_pac_file`] call, `None` without a PAC file.
+    pub source: Option<String>,
+    /// URL the current PAC script was downloaded from.
+    pub uri: Option<String>,
+    /// When the current PAC script was loaded (RFC 3339).
+    pub loaded_at: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Why the last load or refresh failed.
+    pub error: Option<String>,
+    /// Why the last PAC script was refused by the integrity check.
+    pub refused: Option<String>,
+}
+
+impl Context {
+    #[instrument(skip(self))]
+    pub async fn load_pac_file(&self, uri: &Option<PathOrUri>) -> std::io::Result<()> {
+        tracing::info!("update PAC script");
+        self.pac_state.lock().unwrap().source = uri.clone();
+        let result = self.load(uri).await;
+        self.record(&result);
+        result
+    }
+
+    async fn load(&self, uri: &Option<PathOrUri>) -> std::io::Result<()> {
+        let pac = match uri {
+            None => None,
+            Some(PathOrUri::Path(p)) => {
+                let pac = read_to_string(p)?;
+                let signature = self
+                    .pac_verifier
+                    .needs_signature()
+                    .then(|| read_to_string(signature_path(p)).ok())
+                    .flatten();
+                self.verify_pac_script(&pac, signature.as_deref())?;
+                Some(pac)
+            }
+            Some(source) => {
+                let downloaded = match source {
+                    PathOrUri::Uri(u) => self
+                        .download_pac_file(u.clone(), &Validators::default())
+                        .await
+                        .map(|file| (u.clone(), file)),
+                    _ => self.discover_pac_file().await,
+                };
+                match downloaded {
+                    Ok((uri, HttpFile::Modified(pac, validators))) => {
+                        return self.set_downloaded_pac_script(uri, pac, validators).await;
+                    }
+                    Ok((_, HttpFile::NotModified)) => {
+                        return Err(std::io::Error::other("unexpected 304 Not Modified"));
+                    }
+                    Err(cause) => match self.read_backup(source) {
+                        Some(pac) => {
+                            tracing::warn!(%cause, "failed to download PAC file, using the last known good one");
+                            Some(pac)
+                        }
+                        None => return Err(cause),
+                    },
+                }
+            }
+        };
+        self.eval
+            .set_pac_script(pac)
+            .await
+            .map_err(std::io::Error::other)?;
+        let mut state = self.pac_state.lock().unwrap();
+        state.refused = None;
+        state.loaded_at = Some(SystemTime::now());
+        Ok(())
+    }
+
+    /// Remember why loading the PAC file failed, or forget it on success.
+    fn record(&self, result: &std::io::Result<()>) {
+        self.pac_state.lock().unwrap().error = result.as_ref().err().map(|e| e.to_string());
+    }
+
+    /// Where the current PAC script came from and whether loading it succeeded.
+    pub fn pac_status(&self) -> PacStatus {
+        let state = self.pac_state.lock().unwrap();
+        PacStatus {
+            source: state.source.as_ref().map(|s| s.to_string()),
+            uri: state.uri.as_ref().map(|u| u.to_string()),
+            loaded_at: state
+                .loaded_at
+                .map(|t| chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339()),
+            etag: state.validators.etag.clone(),
+            last_modified: state.validators.last_modified.clone(),
+            error: state.error.clone(),
+            refused: state.refused.clone(),
+        }
+    }
+
+    /// Why the last PAC script was refused by the integrity check, if it was.
+    pub fn pac_refused(&self) -> Option<String> {
+        self.pac_state.lock().unwrap().refused.clone()
+    }
+
+    /// Load the PAC file again from the source of the last [`Context::load_pac_file`] call, e.g.
+    /// after the network changed. Nothing is done without a PAC file.
+    pub async fn reload_pac_file(&self) -> std::io::Result<()> {
+        let source = self.pac_state.lock().unwrap().source.clone();
+        if source.is_none() {
+            return Ok(());
+        }
+        self.load_pac_file(&source).await
+    }
+
+    /// Download the PAC file again, but only evaluate it when it was modified.
+    ///
+    /// On failure the current PAC script is kept.
+    #[instrument(skip(self))]
+    pub async fn refresh_pac_file(&self) -> std::io::Result<()> {
+        let result = self.refresh().await;
+        self.record(&result);
+        result
+    }
+
+    async fn refresh(&self) -> std::io::Result<()> {
+        let (source, uri, validators) = {
+            let state = self.pac_state.lock().unwrap();
+            (
+                state.source.clone(),
+                state.uri.clone(),
+                state.validators.clone(),
+            )
+        };
+        let uri = match (source, uri) {
+            // no PAC file or a local file
+            (None, _) | (Some(PathOrUri::Path(_)), _) => return Ok(()),
+            (Some(PathOrUri::Uri(source)), uri) => uri.unwrap_or(source),
+            (Some(PathOrUri::Auto), Some(uri)) => uri,
+            // nothing was discovered so far
+            (Some(PathOrUri::Auto), None) => {
+                return self.load_pac_file(&Some(PathOrUri::Auto)).await;
+            }
+        };
+        match self.download_pac_file(uri.clone(), &validators).await? {
+            HttpFile::Modified(pac, validators) => {
+                tracing::info!(%uri, "PAC file was modified");
+                self.set_downloaded_pac_script(uri, pac, validators).await
+            }
+            HttpFile::NotModified => {
+                tracing::debug!(%uri, "PAC file not modified");
+                Ok(())
+            }
+        }
+    }
+
+    async fn download_pac_file(
+        &self,
+        uri: Uri,
+        validators: &Validators,
+    ) -> std::io::Result<HttpFile> {
+        detox_hyper::http_file_if_modified(
+            uri,
+            self.tls_config.clone(),
+            self.pac_proxy.as_ref(),
+            validators,
+        )
+        .timeout(DOWNLOAD_TIMEOUT)
+        .await
+        .map_err(std::io::Error::other)?
+    }
+
+    /// Download the first PAC file found via WPAD.
+    async fn discover_pac_file(&self) -> std::io::Result<(Uri, HttpFile)> {
+        for uri in wpad::candidates(&self.resolver).await {
+            match self
+                .download_pac_file(uri.clone(), &Validators::default())
+                .await
+            {
+                Ok(file) => {
+                    tracing::info!(%uri, "discovered PAC file");
+                    return Ok((uri, file));
+                }
+                Err(cause) => tracing::debug!(%uri, %cause, "WPAD candidate failed"),
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "no PAC file found via WPAD",
+        ))
+    }
+
+    async fn set_downloaded_pac_script(
+        &self,
+        uri: Uri,
+        pac: String,
+        validators: Validators,
+    ) -> std::io::Result<()> {
+        let signature = if self.pac_verifier.needs_signature() {
+            self.download_signature(&uri).await
+        } else {
+            None
+        };
+        self.verify_pac_script(&pac, signature.as_deref())?;
+        self.eval
+            .set_pac_script(Some(pac.clone()))
+            .await
+            .map_err(std::io::Error::other)?;
+        if let Some(ref path) = self.pac_backup_file
+            && let Err(cause) = write_backup(path, &uri, &pac, signature.as_deref())
+        {
+            tracing::warn!(%cause, path = %path.display(), "failed to write PAC backup file");
+        }
+        let mut state = self.pac_state.lock().unwrap();
+        state.uri = Some(uri);
+        state.validators = validators;
+        state.refused = None;
+        state.loaded_at = Some(SystemTime::now());
+        Ok(())
+    }
+
+    /// Download the detached signature `<uri>.minisig` of the PAC file.
+    async fn download_signature(&self, uri: &Uri) -> Option<String> {
+        let sig_uri = Uri::builder()
+            .scheme(uri.scheme()?.clone())
+            .authority(uri.authority()?.clone())
+            .path_and_query(format!("{}.minisig", uri.path()))
+            .build()
+            .ok()?;
+        match self
+            .download_pac_file(sig_uri.clone(), &Validators::default())
+            .await
+        {
+            Ok(HttpFile::Modified(signature, _)) => Some(signature),
+            Ok(HttpFile::NotModified) => None,
+            Err(cause) => {
+                tracing::warn!(%cause, uri = %sig_uri, "failed to download PAC signature");
+                None
+            }
+        }
+    }
+
+    /// Refuse PAC scripts which do not match the pinned digests or the signature.
+    fn verify_pac_script(&self, pac: &str, signature: Option<&str>) -> std::io::Result<()> {
+        if let Err(cause) = self.pac_verifier.verify(pac, signature) {
+            tracing::error!(%cause, "PAC script failed the integrity check, keeping the current one");
+            self.pac_state.lock().unwrap().refused = Some(cause.to_string());
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, cause));
+        }
+        Ok(())
+    }
+
+    /// The backup of the last PAC script downloaded from `source`.
+    fn read_backup(&self, source: &PathOrUri) -> Option<String> {
+        let path = self.pac_backup_file.as_ref()?;
+        let content = read_to_string(path).ok()?;
+        let (header, pac) = content.split_once('\n')?;
+        let uri = header.strip_prefix(BACKUP_HEADER)?.parse::<Uri>().ok()?;
+        if let PathOrUri::Uri(source) = source
+            && *source != uri
+        {
+            return None;
+        }
+        let signature = self
+            .pac_verifier
+            .needs_signature()
+            .then(|| read_to_string(signature_path(path)).ok())
+            .flatten();
+        self.verify_pac_script(pac, signature.as_deref()).ok()?;
+        Some(pac.to_owned())
+    }
+}
+
+/// Download the PAC file every `interval`, until the context is dropped.
+pub(super) async fn refresh_periodically(context: Weak<Context>, interval: Duration) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        let Some(context) = context.upgrade() else {
+            break;
+        };
+        if let Err(cause) = context.refresh_pac_file().await {
+            tracing::warn!(%cause, "failed to refresh PAC file, keeping the current one");
+        }
+    }
+}
+
+fn write_backup(path: &Path, uri: &Uri, pac: &str, signature: Option<&str>) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if let Some(signature) = signature {
+        std::fs::write(signature_path(path), signature)?;
+    }
+    // replace the file atomically, such that a crash does not leave a truncated file behind
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, format!("{BACKUP_HEADER}{uri}\n{pac}"))?;
+    std::fs::rename(tmp, path)
+}
+
+/// Path of the detached signature of the file `path`.
+fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".minisig");
+    PathBuf::from(name)
+}
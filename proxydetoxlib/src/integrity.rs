@@ -0,0 +1,290 @@
+//! Integrity checks of the PAC script, either by pinned SHA-256 digests or by a detached
+//! [minisign](https://jedisct1.github.io/minisign/) signature.
+
+use base64::Engine;
+use blake2::{Blake2b512, Digest};
+use ring::signature::{ED25519, UnparsedPublicKey};
+use std::fmt;
+use std::str::FromStr;
+
+const SIGNATURE_ALG: &[u8; 2] = b"Ed";
+/// Signature of the BLAKE2b-512 digest of the file, the default since minisign 0.10.
+const SIGNATURE_ALG_PREHASHED: &[u8; 2] = b"ED";
+const TRUSTED_COMMENT: &str = "trusted comment: ";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("SHA-256 digest {0} does not match any of the pinned digests")]
+    DigestMismatch(Sha256),
+    #[error("signature is missing")]
+    SignatureMissing,
+    #[error("invalid signature file: {0}")]
+    InvalidSignatureFile(&'static str),
+    #[error("signature was created with key {0:016X}, expected key {1:016X}")]
+    KeyIdMismatch(u64, u64),
+    #[error("signature verification failed")]
+    InvalidSignature,
+    #[error("signature verification of the trusted comment failed")]
+    InvalidTrustedComment,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("expected 64 hex digits")]
+    InvalidDigest,
+    #[error("invalid base64 encoding: {0}")]
+    Base64(
+        #[source]
+        #[from]
+        base64::DecodeError,
+    ),
+    #[error("not a minisign Ed25519 public key")]
+    InvalidPublicKey,
+}
+
+/// SHA-256 digest of a PAC script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sha256([u8; 32]);
+
+impl Sha256 {
+    pub fn digest(data: &[u8]) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, data);
+        Self(digest.as_ref().try_into().expect("32 bytes"))
+    }
+}
+
+impl fmt::Display for Sha256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl FromStr for Sha256 {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(ParseError::InvalidDigest);
+        }
+        let mut digest = [0u8; 32];
+        for (i, b) in digest.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16)
+                .map_err(|_| ParseError::InvalidDigest)?;
+        }
+        Ok(Self(digest))
+    }
+}
+
+/// Minisign public key, the base64 encoded key or the content of a `.pub` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    key_id: u64,
+    key: [u8; 32],
+}
+
+impl FromStr for PublicKey {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s
+            .lines()
+            .map(str::trim)
+            .rfind(|l| !l.is_empty() && !l.starts_with("untrusted comment:"))
+            .ok_or(ParseError::InvalidPublicKey)?;
+        let data = base64::engine::general_purpose::STANDARD.decode(encoded)?;
+        if data.len() != 42 || &data[..2] != SIGNATURE_ALG {
+            return Err(ParseError::InvalidPublicKey);
+        }
+        Ok(Self {
+            key_id: u64::from_le_bytes(data[2..10].try_into().expect("8 bytes")),
+            key: data[10..].try_into().expect("32 bytes"),
+        })
+    }
+}
+
+impl PublicKey {
+    /// Verify the detached minisign `signature` of `data`.
+    pub fn verify(&self, data: &[u8], signature: &str) -> Result<(), Error> {
+        let mut lines = signature.lines().map(str::trim_end);
+        let (Some(_untrusted), Some(encoded), Some(trusted), Some(global)) =
+            (lines.next(), lines.next(), lines.next(), lines.next())
+        else {
+            return Err(Error::InvalidSignatureFile("expected four lines"));
+        };
+        let decode = |s: &str| base64::engine::general_purpose::STANDARD.decode(s.trim());
+        let sig = decode(encoded).map_err(|_| Error::InvalidSignatureFile("invalid base64"))?;
+        if sig.len() != 74 {
+            return Err(Error::InvalidSignatureFile("invalid signature length"));
+        }
+        let key_id = u64::from_le_bytes(sig[2..10].try_into().expect("8 bytes"));
+        if key_id != self.key_id {
+            return Err(Error::KeyIdMismatch(key_id, self.key_id));
+        }
+        let prehashed;
+        let message = match &sig[..2] {
+            alg if alg == SIGNATURE_ALG => data,
+            alg if alg == SIGNATURE_ALG_PREHASHED => {
+                prehashed = Blake2b512::digest(data);
+                &prehashed[..]
+            }
+            _ => return Err(Error::InvalidSignatureFile("unknown signature algorithm")),
+        };
+        let key = UnparsedPublicKey::new(&ED25519, &self.key);
+        key.verify(message, &sig[10..])
+            .map_err(|_| Error::InvalidSignature)?;
+
+        // the trusted comment is signed together with the signature
+        let comment = trusted
+            .strip_prefix(TRUSTED_COMMENT)
+            .ok_or(Error::InvalidSignatureFile("trusted comment is missing"))?;
+        let global = decode(global).map_err(|_| Error::InvalidSignatureFile("invalid base64"))?;
+        let mut signed = sig[10..].to_vec();
+        signed.extend_from_slice(comment.as_bytes());
+        key.verify(&signed, &global)
+            .map_err(|_| Error::InvalidTrustedComment)
+    }
+}
+
+/// Checks applied to each PAC script before it is evaluated.
+#[derive(Debug, Clone, Default)]
+pub struct Verifier {
+    /// Accepted SHA-256 digests, any one must match.
+    pub digests: Vec<Sha256>,
+    /// Key of the detached signature.
+    pub public_key: Option<PublicKey>,
+}
+
+impl Verifier {
+    /// Returns `true` when a signature is needed.
+    pub fn needs_signature(&self) -> bool {
+        self.public_key.is_some()
+    }
+
+    pub fn verify(&self, pac: &str, signature: Option<&str>) -> Result<(), Error> {
+        if !self.digests.is_empty() {
+            let digest = Sha256::digest(pac.as_bytes());
+            if !self.digests.contains(&digest) {
+                return Err(Error::DigestMismatch(digest));
+            }
+        }
+        if let Some(ref key) = self.public_key {
+            key.verify(pac.as_bytes(), signature.ok_or(Error::SignatureMissing)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    const PAC: &str = "function FindProxyForURL(url, host) { return \"DIRECT\"; }";
+
+    fn key_pair() -> (Ed25519KeyPair, PublicKey) {
+        let pair = Ed25519KeyPair::from_seed_unchecked(&[7u8; 32]).unwrap();
+        let mut data = SIGNATURE_ALG.to_vec();
+        data.extend_from_slice(&0x0123456789abcdefu64.to_le_bytes());
+        data.extend_from_slice(pair.public_key().as_ref());
+        let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+        let key = format!("untrusted comment: minisign public key\n{encoded}\n")
+            .parse()
+            .unwrap();
+        (pair, key)
+    }
+
+    fn sign(pair: &Ed25519KeyPair, alg: &[u8; 2], data: &[u8], comment: &str) -> String {
+        let message = if alg == SIGNATURE_ALG_PREHASHED {
+            Blake2b512::digest(data).to_vec()
+        } else {
+            data.to_vec()
+        };
+        let signature = pair.sign(&message);
+        let mut sig = alg.to_vec();
+        sig.extend_from_slice(&0x0123456789abcdefu64.to_le_bytes());
+        sig.extend_from_slice(signature.as_ref());
+        let mut signed = signature.as_ref().to_vec();
+        signed.extend_from_slice(comment.as_bytes());
+        let global = pair.sign(&signed);
+        let b64 = base64::engine::general_purpose::STANDARD;
+        format!(
+            "untrusted comment: signature\n{}\ntrusted comment: {comment}\n{}\n",
+            b64.encode(sig),
+            b64.encode(global)
+        )
+    }
+
+    #[test]
+    fn sha256_pin() {
+        let digest = Sha256::digest(PAC.as_bytes());
+        assert_eq!(digest.to_string().parse::<Sha256>().unwrap(), digest);
+        assert!("abc".parse::<Sha256>().is_err());
+
+        let verifier = Verifier {
+            digests: vec![digest],
+            public_key: None,
+        };
+        assert!(verifier.verify(PAC, None).is_ok());
+        assert!(matches!(
+            verifier.verify("function FindProxyForURL() {}", None),
+            Err(Error::DigestMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn minisign_signature() {
+        let (pair, key) = key_pair();
+        for alg in [SIGNATURE_ALG, SIGNATURE_ALG_PREHASHED] {
+            let signature = sign(&pair, alg, PAC.as_bytes(), "file:proxy.pac");
+            assert!(key.verify(PAC.as_bytes(), &signature).is_ok());
+            assert!(matches!(
+                key.verify(b"tampered", &signature),
+                Err(Error::InvalidSignature)
+            ));
+            let comment = signature.replace("file:proxy.pac", "file:other.pac");
+            assert!(matches!(
+                key.verify(PAC.as_bytes(), &comment),
+                Err(Error::InvalidTrustedComment)
+            ));
+        }
+
+        let verifier = Verifier {
+            digests: Vec::new(),
+            public_key: Some(key),
+        };
+        assert!(matches!(
+            verifier.verify(PAC, None),
+            Err(Error::SignatureMissing)
+        ));
+    }
+
+    /// Files created by the `minisign` tool, the signatures of `test.txt` in the legacy and the
+    /// prehashed format.
+    #[test]
+    fn minisign_files() {
+        let data = include_bytes!("integrity/testdata/test.txt");
+        let key = include_str!("integrity/testdata/minisign.pub")
+            .parse::<PublicKey>()
+            .unwrap();
+        for signature in [
+            include_str!("integrity/testdata/test.txt.legacy.minisig"),
+            include_str!("integrity/testdata/test.txt.minisig"),
+        ] {
+            assert!(key.verify(data, signature).is_ok());
+            assert!(matches!(
+                key.verify(b"Test", signature),
+                Err(Error::InvalidSignature)
+            ));
+        }
+    }
+
+    #[test]
+    fn minisign_wrong_key() {
+        let (_, key) = key_pair();
+        let other = Ed25519KeyPair::from_seed_unchecked(&[8u8; 32]).unwrap();
+        let signature = sign(&other, SIGNATURE_ALG, PAC.as_bytes(), "");
+        assert!(key.verify(PAC.as_bytes(), &signature).is_err());
+        assert!("RWQ=".parse::<PublicKey>().is_err());
+    }
+}
@@ -1,8 +1,15 @@
 pub mod accesslog;
 pub mod context;
+pub mod integrity;
+pub mod metrics;
+#[cfg(target_os = "linux")]
+pub mod netwatch;
 pub mod server;
 pub mod session;
 pub mod socket;
+pub mod socks;
+pub mod transparent;
+pub mod wpad;
 
 pub use crate::context::Context;
 pub use crate::session::Session;
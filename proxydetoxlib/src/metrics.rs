@@ -0,0 +1,587 @@
+//! Statistics of the proxy, exposed in the Prometheus text format at `/metrics`.
+
+use detox_net::metered::Counters;
+use paclib::ProxyOrDirect;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Window of the recent error rate.
+pub const RECENT_WINDOW: Duration = Duration::from_secs(300);
+
+/// Upper bounds of the buckets of the latency histograms in seconds.
+const BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Cumulative count of each bucket of [`BUCKETS`].
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        self.buckets.resize(BUCKETS.len(), 0);
+        for (count, le) in self.buckets.iter_mut().zip(BUCKETS) {
+            if value <= *le {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Metric with one value per combination of label values.
+#[derive(Debug)]
+struct Family<M> {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, M>>,
+}
+
+impl<M: Default> Family<M> {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            values: Default::default(),
+        }
+    }
+
+    fn with(&self, values: Vec<String>, f: impl FnOnce(&mut M)) {
+        debug_assert_eq!(values.len(), self.labels.len());
+        f(self.values.lock().unwrap().entry(values).or_default());
+    }
+
+    fn header(&self, out: &mut String, kind: &str) {
+        writeln!(out, "# HELP {} {}", self.name, self.help).unwrap();
+        writeln!(out, "# TYPE {} {}", self.name, kind).unwrap();
+    }
+}
+
+impl Family<u64> {
+    fn inc_by(&self, values: Vec<String>, n: u64) {
+        self.with(values, |v| *v += n);
+    }
+
+    fn get(&self, values: &[String]) -> u64 {
+        self.values
+            .lock()
+            .unwrap()
+            .get(values)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn encode(&self, out: &mut String) {
+        self.header(out, "counter");
+        for (values, value) in self.values.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "{}{} {}",
+                self.name,
+                labels(self.labels, values, None),
+                value
+            )
+            .unwrap();
+        }
+    }
+}
+
+impl Family<Histogram> {
+    fn observe(&self, values: Vec<String>, value: Duration) {
+        self.with(values, |h| h.observe(value.as_secs_f64()));
+    }
+
+    fn encode(&self, out: &mut String) {
+        self.header(out, "histogram");
+        for (values, h) in self.values.lock().unwrap().iter() {
+            for (le, count) in BUCKETS.iter().zip(&h.buckets) {
+                let le = le.to_string();
+                let labels = labels(self.labels, values, Some(&le));
+                writeln!(out, "{}_bucket{} {}", self.name, labels, count).unwrap();
+            }
+            let labels_inf = labels(self.labels, values, Some("+Inf"));
+            let labels = labels(self.labels, values, None);
+            writeln!(out, "{}_bucket{} {}", self.name, labels_inf, h.count).unwrap();
+            writeln!(out, "{}_sum{} {}", self.name, labels, h.sum).unwrap();
+            writeln!(out, "{}_count{} {}", self.name, labels, h.count).unwrap();
+        }
+    }
+}
+
+/// Format the label set, e.g. `{method="GET",status="200"}`.
+fn labels(names: &[&str], values: &[String], le: Option<&str>) -> String {
+    let mut pairs = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// Remove the requests older than [`RECENT_WINDOW`].
+fn expire(recent: &mut VecDeque<(Instant, bool)>, now: Instant) {
+    while recent
+        .front()
+        .is_some_and(|(t, _)| now.duration_since(*t) > RECENT_WINDOW)
+    {
+        recent.pop_front();
+    }
+}
+
+/// Label of the standard methods, `OTHER` for extension methods such that clients cannot create
+/// an unbounded number of series.
+fn method_label(method: &http::Method) -> &'static str {
+    match *method {
+        http::Method::GET => "GET",
+        http::Method::HEAD => "HEAD",
+        http::Method::POST => "POST",
+        http::Method::PUT => "PUT",
+        http::Method::DELETE => "DELETE",
+        http::Method::CONNECT => "CONNECT",
+        http::Method::OPTIONS => "OPTIONS",
+        http::Method::TRACE => "TRACE",
+        http::Method::PATCH => "PATCH",
+        _ => "OTHER",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Counters and histograms of one [`crate::Context`].
+#[derive(Debug)]
+pub struct Metrics {
+    requests: Family<u64>,
+    connect_attempts: Family<u64>,
+    connect_failures: Family<u64>,
+    connect_timeouts: Family<u64>,
+    connect_duration: Family<Histogram>,
+    pac_eval_duration: Family<Histogram>,
+    auth_failures: Family<u64>,
+    /// Open tunnels by id.
+    tunnels: Mutex<BTreeMap<u64, Tunnel>>,
+    next_tunnel: AtomicU64,
+    tunnel_bytes: Family<u64>,
+    /// Outcome of the last connection attempt of each proxy.
+    last_connect: Mutex<BTreeMap<String, bool>>,
+    /// Time and failure of the requests within [`RECENT_WINDOW`].
+    recent: Mutex<VecDeque<(Instant, bool)>>,
+}
+
+/// Connection statistics of an upstream proxy, or `DIRECT`.
+#[derive(Debug, Serialize)]
+pub struct ProxyHealth {
+    pub proxy: String,
+    /// The last connection attempt succeeded.
+    pub healthy: bool,
+    pub attempts: u64,
+    pub failures: u64,
+    pub timeouts: u64,
+    /// Average time to connect in seconds.
+    pub connect_seconds: Option<f64>,
+}
+
+/// An open tunnel, see [`Metrics::tunnel`].
+#[derive(Debug)]
+struct Tunnel {
+    peer: SocketAddr,
+    destination: String,
+    proxy: String,
+    since: Instant,
+    /// Counters of the upstream connection.
+    counters: Counters,
+}
+
+/// Peer, destination, and transferred bytes of an open tunnel.
+#[derive(Debug, Serialize)]
+pub struct TunnelStatus {
+    pub peer: SocketAddr,
+    pub destination: String,
+    pub proxy: String,
+    /// Time since the tunnel was opened in seconds.
+    pub seconds: f64,
+    /// Bytes sent to upstream so far.
+    pub bytes_sent: u64,
+    /// Bytes received from upstream so far.
+    pub bytes_received: u64,
+}
+
+/// Requests and failed requests (status code 5xx) within [`RECENT_WINDOW`].
+#[derive(Debug, Serialize)]
+pub struct ErrorRate {
+    pub window_seconds: u64,
+    pub requests: usize,
+    pub errors: usize,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            requests: Family::new(
+                "proxydetox_requests_total",
+                "Proxied HTTP requests by method and status code.",
+                &["method", "status"],
+            ),
+            connect_attempts: Family::new(
+                "proxydetox_connect_attempts_total",
+                "New connections to an upstream proxy or server.",
+                &["proxy"],
+            ),
+            connect_failures: Family::new(
+                "proxydetox_connect_failures_total",
+                "Failed connection attempts, without timeouts.",
+                &["proxy"],
+            ),
+            connect_timeouts: Family::new(
+                "proxydetox_connect_timeouts_total",
+                "Connection attempts which timed out.",
+                &["proxy"],
+            ),
+            connect_duration: Family::new(
+                "proxydetox_connect_duration_seconds",
+                "Time to establish a connection to an upstream proxy or server.",
+                &["proxy"],
+            ),
+            pac_eval_duration: Family::new(
+                "proxydetox_pac_eval_duration_seconds",
+                "Time to evaluate FindProxyForURL of the PAC script.",
+                &[],
+            ),
+            auth_failures: Family::new(
+                "proxydetox_auth_failures_total",
+                "Failed authentications at an upstream proxy by scheme.",
+                &["scheme"],
+            ),
+            tunnels: Default::default(),
+            next_tunnel: Default::default(),
+            tunnel_bytes: Family::new(
+                "proxydetox_tunnel_bytes_total",
+                "Bytes transferred through tunnels, sent to or received from upstream.",
+                &["direction"],
+            ),
+            last_connect: Default::default(),
+            recent: Default::default(),
+        }
+    }
+}
+
+/// Lists a tunnel as open as long as it is alive, its bytes are added to the totals on drop.
+pub(crate) struct ActiveTunnel<'a> {
+    metrics: &'a Metrics,
+    id: u64,
+    counters: Counters,
+}
+
+impl ActiveTunnel<'_> {
+    /// Counters to be updated with the bytes of the upstream connection.
+    pub(crate) fn counters(&self) -> Counters {
+        self.counters.clone()
+    }
+}
+
+impl Drop for ActiveTunnel<'_> {
+    fn drop(&mut self) {
+        self.metrics.tunnels.lock().unwrap().remove(&self.id);
+        self.metrics
+            .tunnel_bytes(self.counters.bytes_written(), self.counters.bytes_read());
+    }
+}
+
+impl Metrics {
+    pub(crate) fn request(&self, method: &http::Method, status: http::StatusCode) {
+        self.requests.inc_by(
+            vec![method_label(method).to_owned(), status.as_u16().to_string()],
+            1,
+        );
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        expire(&mut recent, now);
+        recent.push_back((now, status.is_server_error()));
+    }
+
+    pub(crate) fn connect_attempt(&self, proxy: &ProxyOrDirect) {
+        self.connect_attempts.inc_by(vec![proxy.to_string()], 1);
+    }
+
+    pub(crate) fn connect_failure(&self, proxy: &ProxyOrDirect) {
+        self.connect_failures.inc_by(vec![proxy.to_string()], 1);
+        self.last_connect(proxy, false);
+    }
+
+    pub(crate) fn connect_timeout(&self, proxy: &ProxyOrDirect) {
+        self.connect_timeouts.inc_by(vec![proxy.to_string()], 1);
+        self.last_connect(proxy, false);
+    }
+
+    pub(crate) fn connect_duration(&self, proxy: &ProxyOrDirect, duration: Duration) {
+        self.connect_duration
+            .observe(vec![proxy.to_string()], duration);
+        self.last_connect(proxy, true);
+    }
+
+    fn last_connect(&self, proxy: &ProxyOrDirect, ok: bool) {
+        self.last_connect
+            .lock()
+            .unwrap()
+            .insert(proxy.to_string(), ok);
+    }
+
+    pub(crate) fn pac_eval_duration(&self, duration: Duration) {
+        self.pac_eval_duration.observe(Vec::new(), duration);
+    }
+
+    pub(crate) fn auth_failure(&self, scheme: &str) {
+        self.auth_failures.inc_by(vec![scheme.to_owned()], 1);
+    }
+
+    /// Register an open tunnel from `peer` to `destination` via `proxy`.
+    pub(crate) fn tunnel(
+        &self,
+        peer: SocketAddr,
+        destination: &http::Uri,
+        proxy: &ProxyOrDirect,
+    ) -> ActiveTunnel<'_> {
+        let id = self.next_tunnel.fetch_add(1, Ordering::Relaxed);
+        let counters = Counters::default();
+        let destination = destination
+            .authority()
+            .map(|a| a.to_string())
+            .unwrap_or_else(|| destination.to_string());
+        self.tunnels.lock().unwrap().insert(
+            id,
+            Tunnel {
+                peer,
+                destination,
+                proxy: proxy.to_string(),
+                since: Instant::now(),
+                counters: counters.clone(),
+            },
+        );
+        ActiveTunnel {
+            metrics: self,
+            id,
+            counters,
+        }
+    }
+
+    fn tunnel_bytes(&self, sent: u64, received: u64) {
+        self.tunnel_bytes.inc_by(vec!["sent".to_owned()], sent);
+        self.tunnel_bytes
+            .inc_by(vec!["received".to_owned()], received);
+    }
+
+    /// Connection statistics of all proxies connected so far.
+    pub fn proxies(&self) -> Vec<ProxyHealth> {
+        let last_connect = self.last_connect.lock().unwrap().clone();
+        last_connect
+            .into_iter()
+            .map(|(proxy, healthy)| {
+                let labels = [proxy.clone()];
+                let connect_seconds = self
+                    .connect_duration
+                    .values
+                    .lock()
+                    .unwrap()
+                    .get(&labels[..])
+                    .filter(|h| h.count > 0)
+                    .map(|h| h.sum / h.count as f64);
+                ProxyHealth {
+                    proxy,
+                    healthy,
+                    attempts: self.connect_attempts.get(&labels),
+                    failures: self.connect_failures.get(&labels),
+                    timeouts: self.connect_timeouts.get(&labels),
+                    connect_seconds,
+                }
+            })
+            .collect()
+    }
+
+    /// Number of open tunnels.
+    pub fn tunnels_active(&self) -> usize {
+        self.tunnels.lock().unwrap().len()
+    }
+
+    /// The open tunnels, the oldest first.
+    pub fn tunnels(&self) -> Vec<TunnelStatus> {
+        self.tunnels
+            .lock()
+            .unwrap()
+            .values()
+            .map(|tunnel| TunnelStatus {
+                peer: tunnel.peer,
+                destination: tunnel.destination.clone(),
+                proxy: tunnel.proxy.clone(),
+                seconds: tunnel.since.elapsed().as_secs_f64(),
+                bytes_sent: tunnel.counters.bytes_written(),
+                bytes_received: tunnel.counters.bytes_read(),
+            })
+            .collect()
+    }
+
+    /// Bytes sent to and received from upstream through all tunnels, including the open ones.
+    pub fn tunnel_bytes_total(&self) -> (u64, u64) {
+        self.tunnels().iter().fold(
+            (
+                self.tunnel_bytes.get(&["sent".to_owned()]),
+                self.tunnel_bytes.get(&["received".to_owned()]),
+            ),
+            |(sent, received), tunnel| (sent + tunnel.bytes_sent, received + tunnel.bytes_received),
+        )
+    }
+
+    pub fn error_rate(&self) -> ErrorRate {
+        let mut recent = self.recent.lock().unwrap();
+        expire(&mut recent, Instant::now());
+        ErrorRate {
+            window_seconds: RECENT_WINDOW.as_secs(),
+            requests: recent.len(),
+            errors: recent.iter().filter(|(_, error)| *error).count(),
+        }
+    }
+
+    /// Encode all metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        self.requests.encode(&mut out);
+        self.connect_attempts.encode(&mut out);
+        self.connect_failures.encode(&mut out);
+        self.connect_timeouts.encode(&mut out);
+        self.connect_duration.encode(&mut out);
+        self.pac_eval_duration.encode(&mut out);
+        self.auth_failures.encode(&mut out);
+        writeln!(
+            out,
+            "# HELP proxydetox_tunnels_active Open CONNECT, SOCKS, and transparent tunnels."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE proxydetox_tunnels_active gauge").unwrap();
+        writeln!(out, "proxydetox_tunnels_active {}", self.tunnels_active()).unwrap();
+        self.tunnel_bytes.encode(&mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn encode_counters() {
+        let metrics = Metrics::default();
+        metrics.request(&http::Method::GET, http::StatusCode::OK);
+        metrics.request(&http::Method::GET, http::StatusCode::OK);
+        for method in ["FOO", "BAR"] {
+            let method = http::Method::from_bytes(method.as_bytes()).unwrap();
+            metrics.request(&method, http::StatusCode::NOT_IMPLEMENTED);
+        }
+        metrics.auth_failure("negotiate");
+        {
+            let tunnel = metrics.tunnel(
+                SocketAddr::from(([127, 0, 0, 1], 4711)),
+                &http::Uri::from_static("example.org:443"),
+                &ProxyOrDirect::Direct,
+            );
+            let (stream, mut server) = tokio::io::duplex(64);
+            let mut upstream = detox_net::Metered::with_counters(stream, tunnel.counters());
+            upstream.write_all(&[0; 10]).await.unwrap();
+            server.write_all(&[0; 20]).await.unwrap();
+            drop(server);
+            upstream.read_to_end(&mut Vec::new()).await.unwrap();
+            assert!(metrics.encode().contains("proxydetox_tunnels_active 1\n"));
+            let tunnels = metrics.tunnels();
+            assert_eq!(tunnels.len(), 1);
+            assert_eq!(tunnels[0].destination, "example.org:443");
+            assert_eq!(tunnels[0].proxy, "DIRECT");
+            assert_eq!(tunnels[0].bytes_sent, 10);
+            assert_eq!(tunnels[0].bytes_received, 20);
+        }
+        assert!(metrics.tunnels().is_empty());
+        let out = metrics.encode();
+        assert!(out.contains("# TYPE proxydetox_requests_total counter\n"));
+        assert!(out.contains("proxydetox_requests_total{method=\"GET\",status=\"200\"} 2\n"));
+        assert!(out.contains("proxydetox_requests_total{method=\"OTHER\",status=\"501\"} 2\n"));
+        assert!(!out.contains("FOO"));
+        assert!(out.contains("proxydetox_auth_failures_total{scheme=\"negotiate\"} 1\n"));
+        assert!(out.contains("proxydetox_tunnels_active 0\n"));
+        assert!(out.contains("proxydetox_tunnel_bytes_total{direction=\"sent\"} 10\n"));
+        assert!(out.contains("proxydetox_tunnel_bytes_total{direction=\"received\"} 20\n"));
+    }
+
+    #[test]
+    fn encode_histogram() {
+        let metrics = Metrics::default();
+        metrics.connect_duration(&ProxyOrDirect::Direct, Duration::from_millis(20));
+        metrics.connect_duration(&ProxyOrDirect::Direct, Duration::from_secs(20));
+        let out = metrics.encode();
+        assert!(out.contains("# TYPE proxydetox_connect_duration_seconds histogram\n"));
+        assert!(out.contains(
+            "proxydetox_connect_duration_seconds_bucket{proxy=\"DIRECT\",le=\"0.01\"} 0\n"
+        ));
+        assert!(out.contains(
+            "proxydetox_connect_duration_seconds_bucket{proxy=\"DIRECT\",le=\"0.025\"} 1\n"
+        ));
+        assert!(out.contains(
+            "proxydetox_connect_duration_seconds_bucket{proxy=\"DIRECT\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(out.contains("proxydetox_connect_duration_seconds_count{proxy=\"DIRECT\"} 2\n"));
+    }
+
+    #[test]
+    fn proxy_health_and_error_rate() {
+        let metrics = Metrics::default();
+        metrics.connect_attempt(&ProxyOrDirect::Direct);
+        metrics.connect_duration(&ProxyOrDirect::Direct, Duration::from_millis(20));
+        metrics.connect_attempt(&ProxyOrDirect::Direct);
+        metrics.connect_timeout(&ProxyOrDirect::Direct);
+        let proxies = metrics.proxies();
+        assert_eq!(proxies.len(), 1);
+        assert_eq!(proxies[0].proxy, "DIRECT");
+        assert!(!proxies[0].healthy);
+        assert_eq!(proxies[0].attempts, 2);
+        assert_eq!(proxies[0].timeouts, 1);
+        assert_eq!(proxies[0].connect_seconds, Some(0.02));
+
+        metrics.request(&http::Method::GET, http::StatusCode::OK);
+        metrics.request(&http::Method::GET, http::StatusCode::BAD_GATEWAY);
+        let rate = metrics.error_rate();
+        assert_eq!(rate.requests, 2);
+        assert_eq!(rate.errors, 1);
+    }
+
+    #[test]
+    fn escape_label_values() {
+        assert_eq!(
+            labels(&["a"], &["x\"y\\z\n".to_owned()], None),
+            "{a=\"x\\\"y\\\\z\\n\"}"
+        );
+        assert_eq!(labels(&[], &[], None), "");
+    }
+}
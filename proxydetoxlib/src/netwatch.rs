@@ -0,0 +1,156 @@
+//! Detection of network changes via rtnetlink, e.g. when switching the Wi-Fi or connecting a VPN.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+
+/// Size of the header of each netlink message (`struct nlmsghdr`).
+const NLMSG_HDRLEN: usize = 16;
+
+/// Watches for changes of the IP addresses and the default routes.
+pub struct Watcher {
+    fd: AsyncFd<OwnedFd>,
+    delay: Duration,
+}
+
+impl Watcher {
+    /// Subscribe to the address and route changes. Changes which follow each other within
+    /// `delay` are reported only once, after the network settled.
+    pub fn new(delay: Duration) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_IPV4_IFADDR
+            | libc::RTMGRP_IPV6_IFADDR
+            | libc::RTMGRP_IPV4_ROUTE
+            | libc::RTMGRP_IPV6_ROUTE) as u32;
+        let rc = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const _ as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the `OwnedFd` is moved into the `AsyncFd` and stays open as long as it
+        let fd = unsafe { AsyncFd::register(fd)? };
+        Ok(Self { fd, delay })
+    }
+
+    /// Wait until an address or a default route changed and no further change followed.
+    pub async fn changed(&mut self) -> io::Result<()> {
+        while !self.recv().await? {}
+        loop {
+            match tokio::time::timeout(self.delay, self.recv()).await {
+                Ok(changed) => {
+                    changed?;
+                }
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+
+    /// Receive the next batch of messages, `true` when it contains a relevant change.
+    async fn recv(&self) -> io::Result<bool> {
+        let mut buf = [0u8; 8192];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let len = guard.try_io(|fd| {
+                let len = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if len < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(len as usize)
+                }
+            });
+            match len {
+                Ok(Ok(len)) => return Ok(is_relevant(&buf[..len])),
+                // the kernel dropped messages, something changed for sure
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => return Ok(true),
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Returns `true` when the netlink messages in `buf` add or remove an address or a default
+/// route of the main routing table.
+fn is_relevant(mut buf: &[u8]) -> bool {
+    let mut relevant = false;
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes(buf[0..4].try_into().expect("4 bytes")) as usize;
+        let kind = u16::from_ne_bytes(buf[4..6].try_into().expect("2 bytes"));
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            break;
+        }
+        let payload = &buf[NLMSG_HDRLEN..len];
+        relevant |= match kind {
+            libc::RTM_NEWADDR | libc::RTM_DELADDR => true,
+            // struct rtmsg: family, dst_len, src_len, tos, table, ...
+            libc::RTM_NEWROUTE | libc::RTM_DELROUTE => {
+                payload.len() >= 5 && payload[1] == 0 && payload[4] == libc::RT_TABLE_MAIN
+            }
+            _ => false,
+        };
+        // messages are aligned to four bytes
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    relevant
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let mut msg = ((NLMSG_HDRLEN + payload.len()) as u32)
+            .to_ne_bytes()
+            .to_vec();
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(&[0u8; 10]);
+        msg.extend_from_slice(payload);
+        msg.resize((msg.len() + 3) & !3, 0);
+        msg
+    }
+
+    #[test]
+    fn relevant_messages() {
+        assert!(is_relevant(&message(libc::RTM_NEWADDR, &[0u8; 8])));
+        assert!(is_relevant(&message(libc::RTM_DELADDR, &[0u8; 8])));
+
+        let default_route = [2, 0, 0, 0, libc::RT_TABLE_MAIN, 0, 0, 0, 0, 0, 0, 0];
+        let host_route = [2, 32, 0, 0, libc::RT_TABLE_MAIN, 0, 0, 0, 0, 0, 0, 0];
+        let local_route = [2, 0, 0, 0, libc::RT_TABLE_LOCAL, 0, 0, 0, 0, 0, 0, 0];
+        assert!(is_relevant(&message(libc::RTM_NEWROUTE, &default_route)));
+        assert!(!is_relevant(&message(libc::RTM_NEWROUTE, &host_route)));
+        assert!(!is_relevant(&message(libc::RTM_DELROUTE, &local_route)));
+
+        let mut batch = message(libc::RTM_NEWROUTE, &host_route);
+        batch.extend(message(libc::RTM_DELROUTE, &default_route));
+        assert!(is_relevant(&batch));
+        assert!(!is_relevant(&message(libc::RTM_NEWLINK, &[0u8; 16])));
+        assert!(!is_relevant(&[1, 2, 3]));
+    }
+}
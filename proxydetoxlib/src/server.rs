@@ -2,13 +2,13 @@ use std::{net::SocketAddr, sync::Arc};
 
 use detox_futures::FutureExt;
 use futures_util::StreamExt;
-use hyper::server::conn::http1;
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
 use tokio::{net::TcpStream, select};
 use tokio_util::sync::CancellationToken;
 use tracing_attributes::instrument;
 
-use crate::{Context, Session};
+use crate::{Context, Session, socks, transparent};
 
 #[derive(Debug, thiserror::Error)]
 pub enum WaitError {
@@ -16,9 +16,39 @@ pub enum WaitError {
     TimeoutExpired,
 }
 
+/// Protocol spoken by the clients of a listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// HTTP proxy requests, SOCKS clients are detected automatically.
+    Http,
+    /// SOCKS4, SOCKS4a, and SOCKS5 clients.
+    Socks,
+    /// Connections redirected via `iptables`, see [`crate::transparent`].
+    Transparent,
+}
+
+/// Connection accepted by a listener.
+#[derive(Debug)]
+pub struct Accepted {
+    stream: TcpStream,
+    protocol: Protocol,
+}
+
+impl Accepted {
+    pub fn new(stream: TcpStream, protocol: Protocol) -> Self {
+        Self { stream, protocol }
+    }
+}
+
+impl From<TcpStream> for Accepted {
+    fn from(stream: TcpStream) -> Self {
+        Self::new(stream, Protocol::Http)
+    }
+}
+
 pub struct Server<A> {
     acceptor: A,
-    http_server: http1::Builder,
+    http_server: auto::Builder<TokioExecutor>,
     context: Arc<Context>,
     shutdown_request: CancellationToken,
     shutdown_complete_tx: tokio::sync::mpsc::Sender<()>,
@@ -35,7 +65,10 @@ pub struct JoinHandle {
 
 struct Handler {
     addr: SocketAddr,
-    conn: hyper::server::conn::http1::Connection<TokioIo<TcpStream>, Session>,
+    stream: TcpStream,
+    protocol: Protocol,
+    http_server: auto::Builder<TokioExecutor>,
+    context: Arc<Context>,
     shutdown_request: CancellationToken,
     shutdown_complete_tx: tokio::sync::mpsc::Sender<()>,
 }
@@ -44,42 +77,78 @@ impl Handler {
     #[instrument(skip(self), fields(peer = debug(self.addr)))]
     async fn run(self) {
         let Handler {
-            addr: _,
-            conn,
+            addr,
+            stream,
+            protocol,
+            http_server,
+            context,
             shutdown_request,
             shutdown_complete_tx,
         } = self;
-        let conn = conn.with_upgrades();
         tracing::debug!("peer connected");
-        let mut conn = std::pin::pin!(conn);
-        loop {
-            select! {
-                c = conn.as_mut() => {
-                    if let Err(cause) = c {
-                        tracing::error!(%cause, "server connection error");
+        let protocol = match protocol {
+            Protocol::Http => select! {
+                protocol = detect_protocol(&stream) => protocol,
+                _ = shutdown_request.cancelled() => return,
+            },
+            protocol => protocol,
+        };
+        let result = match protocol {
+            Protocol::Http => {
+                let conn = http_server
+                    .serve_connection_with_upgrades(
+                        TokioIo::new(stream),
+                        Session::new(context, addr),
+                    )
+                    .into_owned();
+                let mut conn = std::pin::pin!(conn);
+                loop {
+                    select! {
+                        c = conn.as_mut() => break c,
+                        _ = shutdown_request.cancelled(), if !shutdown_request.is_cancelled() => {
+                            tracing::debug!("handler received shutdown requested");
+                            conn.as_mut().graceful_shutdown();
+                        }
                     }
-                    tracing::debug!("peer disconnected");
-                    break;
-                },
-                _ = shutdown_request.cancelled(), if !shutdown_request.is_cancelled() => {
-                    tracing::debug!("handler received shutdown requested");
-                    conn.as_mut().graceful_shutdown();
                 }
             }
+            // tunnels are not interrupted on shutdown, they can run until the graceful
+            // shutdown timeout expires
+            Protocol::Socks => socks::serve(context, stream).await.map_err(Into::into),
+            Protocol::Transparent => transparent::serve(context, stream)
+                .await
+                .map_err(Into::into),
+        };
+        if let Err(cause) = result {
+            tracing::error!(%cause, ?protocol, "server connection error");
         }
+        tracing::debug!("peer disconnected");
         drop(shutdown_complete_tx);
     }
 }
 
-impl<A> Server<A>
+/// SOCKS clients are detected by the first byte on HTTP listeners.
+async fn detect_protocol(stream: &TcpStream) -> Protocol {
+    let mut buf = [0u8; 1];
+    match stream.peek(&mut buf).await {
+        Ok(1) if socks::is_socks(buf[0]) => Protocol::Socks,
+        _ => Protocol::Http,
+    }
+}
+
+impl<A, S> Server<A>
 where
-    A: futures_util::Stream<Item = std::io::Result<tokio::net::TcpStream>> + Send + Unpin + 'static,
+    A: futures_util::Stream<Item = std::io::Result<S>> + Send + Unpin + 'static,
+    S: Into<Accepted>,
 {
     pub fn new(acceptor: A, context: Arc<Context>) -> (Server<A>, Control) {
+        // HTTP/1.1 or HTTP/2 (prior knowledge, h2c) is detected from the connection preface,
+        // extended CONNECT (RFC 8441) is not enabled since its protocols are not proxied
         let http_server = {
-            let mut b = http1::Builder::new();
-            b.preserve_header_case(true);
-            b.title_case_headers(true);
+            let mut b = auto::Builder::new(TokioExecutor::new());
+            b.http1()
+                .preserve_header_case(true)
+                .title_case_headers(true);
             b
         };
         let shutdown_request = CancellationToken::new();
@@ -147,11 +216,14 @@ where
                         },
                         None => unreachable!(),
                     };
+                    let Accepted { stream, protocol } = stream.into();
                     let addr = stream.peer_addr().expect("peer_addr");
-                    let conn = http_server.serve_connection(TokioIo::new(stream), Session::new(context.clone(), addr));
                     let handler = Handler {
                         addr,
-                        conn,
+                        stream,
+                        protocol,
+                        http_server: http_server.clone(),
+                        context: context.clone(),
                         shutdown_request: shutdown_request.clone(),
                         shutdown_complete_tx: shutdown_complete_tx.clone(),
                     };
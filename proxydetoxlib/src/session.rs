@@ -1,12 +1,15 @@
-use crate::context::Context;
+mod api;
+
+use crate::context::{Context, Upstream};
 use crate::{accesslog, body};
 use bytes::Bytes;
-use detox_net::{HostAndPort, copy_bidirectional};
+use detox_hyper::body::{BufferedBody, replay_request};
+use detox_net::HostAndPort;
 use futures_util::{FutureExt, StreamExt, stream};
 use http::Uri;
 use http::header::{
     CACHE_CONTROL, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST, PROXY_AUTHENTICATE,
-    PROXY_AUTHORIZATION, TE, TRAILER, TRANSFER_ENCODING, UPGRADE, USER_AGENT,
+    PROXY_AUTHORIZATION, REFERER, TE, TRAILER, TRANSFER_ENCODING, UPGRADE, USER_AGENT,
 };
 use http::{HeaderMap, HeaderName};
 use http::{HeaderValue, Response};
@@ -87,6 +90,10 @@ pub enum Error {
     Connect(#[source] tokio::io::Error, Box<Uri>),
     #[error("upstream proxy ({0}) requires authentication")]
     ProxyAuthenticationRequired(HostAndPort),
+    #[error(
+        "upstream proxy ({1}) requires authentication, but the request body is larger than {0} bytes and cannot be send again"
+    )]
+    RequestBodyTooLarge(usize, HostAndPort),
     #[error("received invalid status code: {0}")]
     InvalidStatusCode(http::StatusCode),
     #[error("http error: {0}")]
@@ -125,6 +132,8 @@ impl Inner {
     ) -> std::result::Result<http::Response<BoxBody<Bytes, hyper::Error>>, Infallible> {
         // TODO: management console must also be choosen, when authority is pointing to us
         // (or abort the connection), since otherwise we create an endless loop.
+        let proxied = req.uri().authority().is_some();
+        let method = req.method().clone();
         let res = if req.uri().authority().is_some() {
             self.proxy_request(req).await
         } else if req.method() != hyper::Method::CONNECT {
@@ -135,10 +144,14 @@ impl Inner {
                 format!("Invalid request: <tt>{} {}</<tt>", req.method(), req.uri()),
             ))
         };
-        match res {
-            Ok(res) => Ok::<_, Infallible>(res),
-            Err(cause) => Ok::<_, Infallible>(make_error_response(&cause)),
+        let res = match res {
+            Ok(res) => res,
+            Err(cause) => make_error_response(&cause),
+        };
+        if proxied {
+            self.context.metrics.request(&method, res.status());
         }
+        Ok(res)
     }
 
     async fn proxy_request(
@@ -154,8 +167,19 @@ impl Inner {
                 .get(USER_AGENT)
                 .and_then(|v| v.to_str().ok())
                 .map(|s| s.to_owned()),
+            req.headers()
+                .get(REFERER)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_owned()),
         );
         remove_hop_by_hop_headers(req.headers_mut());
+        if !req.headers().contains_key(HOST)
+            && let Some(authority) = req.uri().authority()
+        {
+            // HTTP/2 requests carry the host only in the `:authority` pseudo header
+            let host = HeaderValue::from_str(authority.as_str()).map_err(http::Error::from)?;
+            req.headers_mut().insert(HOST, host);
+        }
         let uri = if req.uri().scheme().is_some() {
             req.uri().clone()
         } else {
@@ -179,6 +203,7 @@ impl Inner {
         let conn = proxies.clone().into_iter().map({
             let cx = self.context.clone();
             let method = req.method();
+            let uri = uri.clone();
             move |p| {
                 let cx = cx.clone();
                 let race = cx.race_connect;
@@ -217,26 +242,66 @@ impl Inner {
             .map(|c| c.proxy().to_owned())
             .unwrap_or(ProxyOrDirect::Direct);
 
-        let resp = if let Some(mut conn) = conn {
-            let resp = if req.method() == hyper::Method::CONNECT {
-                tokio::task::spawn(async move {
-                    match hyper::upgrade::on(req).await {
-                        Ok(upgraded) => {
-                            let mut upgraded = TokioIo::new(upgraded);
-                            if let Err(cause) = copy_bidirectional(&mut upgraded, &mut conn).await {
-                                tracing::error!(%cause, "copy bidrectional");
+        let mut replayable = true;
+        let resp = if let Some(conn) = conn {
+            let resp = match conn {
+                Upstream::Stream(mut conn) => {
+                    let context = self.context.clone();
+                    let peer = self.addr;
+                    let proxy = proxy.clone();
+                    tokio::task::spawn(async move {
+                        match hyper::upgrade::on(req).await {
+                            Ok(upgraded) => {
+                                let mut upgraded = TokioIo::new(upgraded);
+                                if let Err(cause) = context
+                                    .copy_tunnel(peer, &uri, &proxy, &mut upgraded, &mut conn)
+                                    .await
+                                {
+                                    tracing::error!(%cause, "copy bidrectional");
+                                }
                             }
+                            Err(cause) => tracing::error!(%cause, "upgrade error"),
                         }
-                        Err(cause) => tracing::error!(%cause, "upgrade error"),
+                    });
+
+                    Ok(Response::new(body::empty()))
+                }
+                Upstream::Http(conn) => {
+                    let (parts, body) = req.into_parts();
+                    // only keep the body for a replay if there can be one, otherwise stream it
+                    let body = if conn.is_reused() || conn.has_auth() {
+                        let body =
+                            BufferedBody::buffer(body, self.context.request_buffer_size).await?;
+                        replayable = body.is_replayable();
+                        body
+                    } else {
+                        BufferedBody::streaming(body)
+                    };
+                    let req = http::Request::from_parts(parts, body);
+                    let retry = conn.is_reused().then(|| replay_request(&req)).flatten();
+                    let method = req.method().clone();
+                    let resp = match (conn.send_request(req).await, retry) {
+                        (Err(detox_hyper::conn::Error::Hyper(cause)), Some(retry))
+                            if is_stale_connection(&cause) =>
+                        {
+                            tracing::debug!(%cause, "pooled connection closed, retry with a new connection");
+                            self.send_again(proxy.clone(), method, uri, retry).await
+                        }
+                        (resp, _) => Ok(resp?),
+                    };
+                    if let Err(Error::Connection(
+                        detox_hyper::conn::Error::AuthenticationFailed(..)
+                        | detox_hyper::conn::Error::AuthenticationTimeout,
+                    )) = &resp
+                        && let ProxyOrDirect::Proxy(proxy) = &proxy
+                    {
+                        self.context.auth_failure(proxy);
                     }
-                });
-
-                Ok(Response::new(body::empty()))
-            } else {
-                let conn = conn.handshake().await?;
-                let mut resp = conn.send_request(req).await?;
-                remove_hop_by_hop_headers(resp.headers_mut());
-                Ok(resp.map(|b| b.boxed()))
+                    resp.map(|mut resp| {
+                        remove_hop_by_hop_headers(resp.headers_mut());
+                        resp.map(|b| b.boxed())
+                    })
+                }
             };
 
             match resp {
@@ -247,9 +312,17 @@ impl Inner {
                             http::StatusCode::PROXY_AUTHENTICATION_REQUIRED,
                         ) => {
                             tracing::error!(%proxy, "407 proxy authentication required");
-                            Err(Error::ProxyAuthenticationRequired(
-                                proxy.endpoint().to_owned(),
-                            ))
+                            self.context.auth_failure(proxy);
+                            if replayable {
+                                Err(Error::ProxyAuthenticationRequired(
+                                    proxy.endpoint().to_owned(),
+                                ))
+                            } else {
+                                Err(Error::RequestBodyTooLarge(
+                                    self.context.request_buffer_size,
+                                    proxy.endpoint().to_owned(),
+                                ))
+                            }
                         }
                         (
                             ProxyOrDirect::Direct,
@@ -284,21 +357,49 @@ impl Inner {
                 Err(cause) => access.error(Some(proxy.clone()), cause),
             }
         };
-        self.context.accesslog_tx.send(entry).ok();
+        self.context.log_access(entry).await;
 
         resp
     }
 
+    /// Send a request again via a new connection, after the pooled connection was closed.
+    async fn send_again(
+        &self,
+        proxy: ProxyOrDirect,
+        method: http::Method,
+        uri: Uri,
+        req: http::Request<BufferedBody>,
+    ) -> Result<http::Response<hyper::body::Incoming>> {
+        let conn = self
+            .context
+            .clone()
+            .reconnect(proxy, method, uri.clone())
+            .await;
+        match conn {
+            Ok(Upstream::Http(conn)) => Ok(conn.send_request(req).await?),
+            Ok(Upstream::Stream(_)) => Err(Error::UnableToEstablishConnection(Box::new(uri))),
+            Err(cause) => {
+                tracing::warn!(%cause, "unable to connect");
+                Err(Error::UnableToEstablishConnection(Box::new(uri)))
+            }
+        }
+    }
+
     async fn management_console(
         &self,
         req: http::Request<hyper::body::Incoming>,
     ) -> Result<http::Response<Body>> {
         const GET: http::Method = http::Method::GET;
         match (req.method(), req.uri().path()) {
+            (_, path) if path.starts_with("/api/") => {
+                api::handle(&self.context, self.addr, req).await
+            }
             (&GET, "/") => self.index_html(),
             (&GET, "/access.log") => self.accesslog_stream(),
             (&GET, "/access.html") => self.accesslog_html(),
+            (&GET, "/find-proxy.html") => self.find_proxy_html(),
             (&GET, "/proxy.pac") => proxy_pac(req.headers().get(HOST)),
+            (&GET, "/metrics") => self.metrics(),
             (&GET, _) => Ok(make_error_html(
                 http::StatusCode::NOT_FOUND,
                 "ressource not found",
@@ -311,14 +412,20 @@ impl Inner {
     }
 
     fn index_html(&self) -> Result<Response<Body>> {
-        let body = format!(
-            "<!DOCTYPE html><html><body><h1>Proxydetox<h1><p>{}/{}</p></body></html>",
-            env!("CARGO_PKG_NAME"),
-            *crate::VERSION_STR,
-        );
         let resp = Response::builder()
             .header(CONTENT_TYPE, HeaderValue::from_static("text/html"))
-            .body(body::full(body))?;
+            .body(body::full(include_str!("index.html")))?;
+        Ok(resp)
+    }
+
+    fn metrics(&self) -> Result<Response<Body>> {
+        let resp = Response::builder()
+            .header(CACHE_CONTROL, HeaderValue::from_static("no-store"))
+            .header(
+                CONTENT_TYPE,
+                HeaderValue::from_static(crate::metrics::CONTENT_TYPE),
+            )
+            .body(body::full(self.context.metrics.encode()))?;
         Ok(resp)
     }
 
@@ -329,6 +436,13 @@ impl Inner {
         Ok(resp)
     }
 
+    fn find_proxy_html(&self) -> Result<Response<Body>> {
+        let resp = Response::builder()
+            .header(CONTENT_TYPE, HeaderValue::from_static("text/html"))
+            .body(body::full(include_str!("find-proxy.html")))?;
+        Ok(resp)
+    }
+
     fn accesslog_stream(&self) -> Result<Response<Body>> {
         // the client accepts an SSE event stream
         let stream = self.context.accesslog_tx.subscribe();
@@ -423,6 +537,12 @@ fn proxy_pac(host: Option<&HeaderValue>) -> std::result::Result<Response<Body>,
     Ok(resp)
 }
 
+/// Returns `true` if the error indicates that the connection was closed by the peer before the
+/// response was received.
+fn is_stale_connection(cause: &hyper::Error) -> bool {
+    cause.is_canceled() || cause.is_closed() || cause.is_incomplete_message()
+}
+
 fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
     // Remove hop-by-hop headers which must not be forwarded.
     if let Some(connection) = headers.remove(CONNECTION)
@@ -0,0 +1,459 @@
+//! JSON API of the management console to control a running instance, e.g. from a tray icon.
+//!
+//! Only clients on the loopback interface are served. To protect against requests of web pages
+//! opened in a local browser, the `Host` header must name the loopback interface and requests
+//! which change the state must have the content type `application/json`.
+
+use super::{Body, Result};
+use crate::body;
+use crate::context::{Context, PacStatus};
+use crate::metrics::{ErrorRate, ProxyHealth, TunnelStatus};
+use bytes::Bytes;
+use futures_util::{future, stream};
+use http::header::{CACHE_CONTROL, CONTENT_TYPE, HOST};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Limited, StreamBody};
+use hyper::body::Frame;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Maximum size of a request body.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Interval of the events of `/api/v1/status/events`.
+const STATUS_INTERVAL: Duration = Duration::from_secs(2);
+
+const PATHS: &[&str] = &[
+    "/api/v1/config",
+    "/api/v1/direct-mode",
+    "/api/v1/find-proxy",
+    "/api/v1/my-ip-address",
+    "/api/v1/pac",
+    "/api/v1/pac/reload",
+    "/api/v1/status",
+    "/api/v1/status/events",
+];
+
+#[derive(Debug, Serialize)]
+struct ErrorMessage {
+    error: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DirectMode {
+    enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MyIpAddress {
+    address: Option<IpAddr>,
+}
+
+/// The effective configuration.
+#[derive(Debug, Serialize)]
+struct Config {
+    version: &'static str,
+    pac_file: Option<String>,
+    pac_proxy: Option<String>,
+    pac_backup_file: Option<String>,
+    pac_sha256: Vec<String>,
+    pac_signature_required: bool,
+    authentication: String,
+    proxytunnel: bool,
+    direct_fallback: bool,
+    race_connect: bool,
+    parallel_connect: usize,
+    connect_timeout: f64,
+    request_buffer_size: usize,
+    dns_search: Vec<String>,
+    direct_mode: bool,
+    my_ip_address: Option<IpAddr>,
+}
+
+impl Config {
+    fn new(context: &Context) -> Self {
+        Self {
+            version: *crate::VERSION_STR,
+            pac_file: context.pac_file.as_ref().map(|p| p.to_string()),
+            pac_proxy: context.pac_proxy.as_ref().map(|p| p.proxy.to_string()),
+            pac_backup_file: context
+                .pac_backup_file
+                .as_ref()
+                .map(|p| p.display().to_string()),
+            pac_sha256: context
+                .pac_verifier
+                .digests
+                .iter()
+                .map(|d| d.to_string())
+                .collect(),
+            pac_signature_required: context.pac_verifier.needs_signature(),
+            authentication: context.auth.to_string(),
+            proxytunnel: context.proxytunnel,
+            direct_fallback: context.direct_fallback,
+            race_connect: context.race_connect,
+            parallel_connect: context.parallel_connect,
+            connect_timeout: context.connect_timeout.as_secs_f64(),
+            request_buffer_size: context.request_buffer_size,
+            dns_search: context.resolver.search(),
+            direct_mode: context.is_direct_mode(),
+            my_ip_address: context.my_ip_address(),
+        }
+    }
+}
+
+/// Result of `FindProxyForURL` for one URL.
+#[derive(Debug, Serialize)]
+struct FindProxy {
+    url: String,
+    direct_mode: bool,
+    proxies: Vec<Candidate>,
+}
+
+/// A proxy returned by the PAC script, or added by `--direct-fallback`.
+#[derive(Debug, Serialize)]
+struct Candidate {
+    proxy: String,
+    /// Outcome of the test connection, when requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connect: Option<ConnectResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct ConnectResult {
+    ok: bool,
+    seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Everything shown on the dashboard of the management console.
+#[derive(Debug, Serialize)]
+struct Status {
+    version: &'static str,
+    pac: PacStatus,
+    direct_mode: bool,
+    my_ip_address: Option<IpAddr>,
+    authentication: String,
+    proxies: Vec<ProxyHealth>,
+    tunnels_active: usize,
+    tunnels: Vec<TunnelStatus>,
+    tunnel_bytes_sent: u64,
+    tunnel_bytes_received: u64,
+    error_rate: ErrorRate,
+}
+
+impl Status {
+    fn new(context: &Context) -> Self {
+        let metrics = context.metrics();
+        let (tunnel_bytes_sent, tunnel_bytes_received) = metrics.tunnel_bytes_total();
+        Self {
+            version: *crate::VERSION_STR,
+            pac: context.pac_status(),
+            direct_mode: context.is_direct_mode(),
+            my_ip_address: context.my_ip_address(),
+            authentication: context.auth.to_string(),
+            proxies: metrics.proxies(),
+            tunnels_active: metrics.tunnels_active(),
+            tunnels: metrics.tunnels(),
+            tunnel_bytes_sent,
+            tunnel_bytes_received,
+            error_rate: metrics.error_rate(),
+        }
+    }
+}
+
+pub(super) async fn handle(
+    context: &Arc<Context>,
+    addr: SocketAddr,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<Body>> {
+    if !addr.ip().to_canonical().is_loopback() || !is_loopback_host(req.headers()) {
+        return error(
+            StatusCode::FORBIDDEN,
+            "only available via the loopback interface",
+        );
+    }
+    if req.method() != Method::GET && !is_json(req.headers()) {
+        return error(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected content type application/json",
+        );
+    }
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/api/v1/config") => json(StatusCode::OK, &Config::new(context)),
+        (&Method::GET, "/api/v1/pac") => json(StatusCode::OK, &context.pac_status()),
+        (&Method::GET, "/api/v1/status") => json(StatusCode::OK, &Status::new(context)),
+        (&Method::GET, "/api/v1/status/events") => status_events(context.clone()),
+        (&Method::GET, "/api/v1/find-proxy") => {
+            let query = req.uri().query().unwrap_or_default();
+            let Some(url) = query_param(query, "url") else {
+                return error(StatusCode::BAD_REQUEST, "parameter url is missing");
+            };
+            let uri = match url.parse::<http::Uri>() {
+                Ok(uri) if uri.scheme().is_some() && uri.host().is_some() => uri,
+                _ => return error(StatusCode::BAD_REQUEST, format!("invalid URL: {url}")),
+            };
+            let connect = query_param(query, "connect").is_some_and(|v| v == "true" || v == "1");
+            json(StatusCode::OK, &find_proxy(context, uri, connect).await)
+        }
+        (&Method::POST, "/api/v1/pac/reload") => match context.reload_pac_file().await {
+            Ok(()) => json(StatusCode::OK, &context.pac_status()),
+            Err(cause) => error(StatusCode::BAD_GATEWAY, cause.to_string()),
+        },
+        (&Method::GET, "/api/v1/direct-mode") => json(
+            StatusCode::OK,
+            &DirectMode {
+                enabled: context.is_direct_mode(),
+            },
+        ),
+        (&Method::PUT, "/api/v1/direct-mode") => {
+            let mode = match read_json::<DirectMode>(req).await {
+                Ok(mode) => mode,
+                Err(resp) => return Ok(resp),
+            };
+            context.set_direct_mode(mode.enabled);
+            json(StatusCode::OK, &mode)
+        }
+        (&Method::GET, "/api/v1/my-ip-address") => json(
+            StatusCode::OK,
+            &MyIpAddress {
+                address: context.my_ip_address(),
+            },
+        ),
+        (&Method::PUT, "/api/v1/my-ip-address") => {
+            let address = match read_json::<MyIpAddress>(req).await {
+                Ok(MyIpAddress {
+                    address: Some(address),
+                }) => address,
+                Ok(_) => return error(StatusCode::BAD_REQUEST, "address is missing"),
+                Err(resp) => return Ok(resp),
+            };
+            match context.set_my_ip_address(address).await {
+                Ok(()) => json(
+                    StatusCode::OK,
+                    &MyIpAddress {
+                        address: Some(address),
+                    },
+                ),
+                Err(cause) => error(StatusCode::INTERNAL_SERVER_ERROR, cause.to_string()),
+            }
+        }
+        (_, path) if PATHS.contains(&path) => {
+            error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+        }
+        (_, _) => error(StatusCode::NOT_FOUND, "resource not found"),
+    }
+}
+
+/// Evaluate the PAC script for `uri` and connect via each candidate when `connect` is set.
+///
+/// The test connection is a `CONNECT` tunnel for `https` URLs and a connection to the proxy
+/// otherwise, like for an actual request. No request is sent, the metrics are not updated and
+/// the connection is closed afterwards.
+async fn find_proxy(context: &Arc<Context>, uri: http::Uri, connect: bool) -> FindProxy {
+    let proxies = context.find_proxy(uri.clone()).await;
+    let method = if uri.scheme() == Some(&http::uri::Scheme::HTTPS) {
+        Method::CONNECT
+    } else {
+        Method::GET
+    };
+    let candidates = proxies.into_iter().map(|proxy| {
+        let context = context.clone();
+        let method = method.clone();
+        let uri = uri.clone();
+        async move {
+            let connect = if connect {
+                let start = Instant::now();
+                let result = context.probe(proxy.clone(), method, uri).await;
+                Some(ConnectResult {
+                    ok: result.is_ok(),
+                    seconds: start.elapsed().as_secs_f64(),
+                    error: result.err().map(|cause| cause.to_string()),
+                })
+            } else {
+                None
+            };
+            Candidate {
+                proxy: proxy.to_string(),
+                connect,
+            }
+        }
+    });
+    FindProxy {
+        url: uri.to_string(),
+        direct_mode: context.is_direct_mode(),
+        proxies: future::join_all(candidates).await,
+    }
+}
+
+/// Value of the parameter `name` in the URL `query`, decoded like a form.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+fn percent_decode(value: &str) -> String {
+    let input = value.as_bytes();
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let hex = input
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit));
+        match (input[i], hex) {
+            (b'+', _) => bytes.push(b' '),
+            (b'%', Some(hex)) => {
+                let hex = std::str::from_utf8(hex).expect("ASCII");
+                bytes.push(u8::from_str_radix(hex, 16).expect("hex digits"));
+                i += 2;
+            }
+            (b, _) => bytes.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// SSE event stream with the [`Status`] every [`STATUS_INTERVAL`].
+fn status_events(context: Arc<Context>) -> Result<Response<Body>> {
+    let interval = tokio::time::interval(STATUS_INTERVAL);
+    let stream = stream::unfold((context, interval), |(context, mut interval)| async move {
+        interval.tick().await;
+        let status = serde_json::to_string(&Status::new(&context)).expect("serializable status");
+        let frame = Frame::data(Bytes::from(format!("event:status\ndata:{status}\n\n")));
+        Some((Ok::<_, hyper::Error>(frame), (context, interval)))
+    });
+    let resp = Response::builder()
+        .header(CACHE_CONTROL, HeaderValue::from_static("no-store"))
+        .header(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"))
+        .body(BoxBody::new(StreamBody::new(stream)))?;
+    Ok(resp)
+}
+
+/// Returns `true` when the `Host` header names the loopback interface, which rules out DNS
+/// rebinding attacks.
+fn is_loopback_host(headers: &HeaderMap) -> bool {
+    let Some(host) = headers.get(HOST).and_then(|h| h.to_str().ok()) else {
+        return true;
+    };
+    let host = match host.rsplit_once(':') {
+        Some((h, port)) if port.bytes().all(|b| b.is_ascii_digit()) && !h.ends_with(':') => h,
+        _ => host,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.to_canonical().is_loopback())
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("application/json"))
+}
+
+async fn read_json<T: DeserializeOwned>(
+    req: Request<hyper::body::Incoming>,
+) -> std::result::Result<T, Response<Body>> {
+    let data = Limited::new(req.into_body(), MAX_BODY_SIZE)
+        .collect()
+        .await
+        .map_err(|cause| {
+            make_json(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &ErrorMessage {
+                    error: cause.to_string(),
+                },
+            )
+        })?
+        .to_bytes();
+    serde_json::from_slice(&data).map_err(|cause| {
+        make_json(
+            StatusCode::BAD_REQUEST,
+            &ErrorMessage {
+                error: cause.to_string(),
+            },
+        )
+    })
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Result<Response<Body>> {
+    json(
+        status,
+        &ErrorMessage {
+            error: message.into(),
+        },
+    )
+}
+
+fn json<T: Serialize>(status: StatusCode, value: &T) -> Result<Response<Body>> {
+    Ok(make_json(status, value))
+}
+
+fn make_json<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    let data = serde_json::to_vec(value).expect("serializable value");
+    let mut resp = Response::new(body::full(Bytes::from(data)));
+    *resp.status_mut() = status;
+    resp.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    resp.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn loopback_host() {
+        assert!(is_loopback_host(&HeaderMap::new()));
+        assert!(is_loopback_host(&host("127.0.0.1:3128")));
+        assert!(is_loopback_host(&host("localhost:3128")));
+        assert!(is_loopback_host(&host("[::1]:3128")));
+        assert!(is_loopback_host(&host("[::ffff:127.0.0.1]")));
+        assert!(!is_loopback_host(&host("evil.example.org:3128")));
+        assert!(!is_loopback_host(&host("192.0.2.1")));
+    }
+
+    #[test]
+    fn query_parameters() {
+        let query = "url=https%3A%2F%2Fexample.org%2Fa%20b%3Fc%3Dd&connect=true&empty";
+        assert_eq!(
+            query_param(query, "url").as_deref(),
+            Some("https://example.org/a b?c=d")
+        );
+        assert_eq!(query_param(query, "connect").as_deref(), Some("true"));
+        assert_eq!(query_param(query, "empty").as_deref(), Some(""));
+        assert_eq!(query_param(query, "other"), None);
+        assert_eq!(percent_decode("a+b%2"), "a b%2");
+    }
+
+    #[test]
+    fn json_content_type() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_json(&headers));
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/json; charset=utf-8"),
+        );
+        assert!(is_json(&headers));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        assert!(!is_json(&headers));
+    }
+}
@@ -0,0 +1,113 @@
+//! SOCKS4a and SOCKS5 clients, each `CONNECT` request is tunneled via the proxy returned by the
+//! PAC script like a HTTP `CONNECT` request.
+
+use crate::accesslog;
+use crate::context::Context;
+use detox_net::{HostAndPort, socks};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+    #[error("SOCKS error: {0}")]
+    Socks(
+        #[from]
+        #[source]
+        socks::Error,
+    ),
+    #[error("{0}")]
+    Connect(
+        #[from]
+        #[source]
+        crate::context::Error,
+    ),
+}
+
+/// Returns `true` if `byte`, the first byte send by a client, starts a SOCKS handshake.
+///
+/// HTTP requests (including the HTTP/2 connection preface) start with an upper case letter.
+pub fn is_socks(byte: u8) -> bool {
+    byte == 4 || byte == 5
+}
+
+/// Serve a SOCKS client.
+pub async fn serve(context: Arc<Context>, mut stream: TcpStream) -> Result<(), Error> {
+    let peer_addr = stream.peer_addr()?;
+    let request = socks::accept(&mut stream).await?;
+    let uri = tunnel_uri(&request.dst)?;
+    tracing::debug!(version = ?request.version, %uri, "SOCKS connection");
+
+    let access = accesslog::Entry::begin(
+        peer_addr,
+        http::Method::CONNECT,
+        http::Uri::try_from(request.dst).map_err(std::io::Error::other)?,
+        http::Version::HTTP_11,
+        None,
+        None,
+    );
+
+    let mut upstream = match context.clone().tunnel(uri.clone()).await {
+        Ok(upstream) => upstream,
+        Err(error) => {
+            context.log_access(access.error(None, &error)).await;
+            socks::reply(&mut stream, request.version, false).await?;
+            return Err(error.into());
+        }
+    };
+    context
+        .log_access(access.success(upstream.proxy().clone(), http::StatusCode::OK, None))
+        .await;
+
+    socks::reply(&mut stream, request.version, true).await?;
+    let proxy = upstream.proxy().clone();
+    context
+        .copy_tunnel(peer_addr, &uri, &proxy, &mut stream, &mut upstream)
+        .await?;
+    Ok(())
+}
+
+/// URI passed to the PAC script, the scheme is guessed from the port like for `CONNECT` requests.
+fn tunnel_uri(dst: &HostAndPort) -> std::io::Result<http::Uri> {
+    let scheme = match dst.port() {
+        443 => http::uri::Scheme::HTTPS,
+        _ => http::uri::Scheme::HTTP,
+    };
+    let host = if dst.host().contains(':') {
+        format!("[{}]", dst.host())
+    } else {
+        dst.host().to_owned()
+    };
+    http::Uri::builder()
+        .scheme(scheme)
+        .authority(format!("{host}:{}", dst.port()))
+        .path_and_query("/")
+        .build()
+        .map_err(std::io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tunnel_uri_test() {
+        let uri = tunnel_uri(&HostAndPort::new("example.org", 443)).unwrap();
+        assert_eq!(uri, "https://example.org:443/");
+        let uri = tunnel_uri(&HostAndPort::new("::1", 22)).unwrap();
+        assert_eq!(uri, "http://[::1]:22/");
+    }
+
+    #[test]
+    fn is_socks_test() {
+        assert!(is_socks(4));
+        assert!(is_socks(5));
+        assert!(!is_socks(b'C'));
+        assert!(!is_socks(b'P'));
+    }
+}
@@ -0,0 +1,317 @@
+//! Transparent proxy mode for connections redirected via `iptables` DNAT.
+//!
+//! The original destination is recovered with `SO_ORIGINAL_DST` (Linux only). The host name is
+//! taken from the `Host` header of HTTP requests or the server name indication (SNI) of TLS
+//! handshakes. The connection is then tunneled via the proxy returned by the PAC script.
+
+use crate::accesslog;
+use crate::context::Context;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Maximum number of bytes read to find the host name.
+const MAX_SNIFF_SIZE: usize = 16 * 1024;
+/// Time to wait for the first bytes of the client.
+const SNIFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+    #[error("connection to {0} was not redirected")]
+    NotRedirected(SocketAddr),
+    #[error("{0}")]
+    Connect(
+        #[from]
+        #[source]
+        crate::context::Error,
+    ),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Sniff {
+    /// More data is needed.
+    Incomplete,
+    /// TLS handshake, with the server name if present.
+    Tls(Option<String>),
+    /// Plain HTTP request, with the `Host` header value if present.
+    Http(Option<String>),
+}
+
+/// Serve a connection which was redirected to the transparent listener.
+pub async fn serve(context: Arc<Context>, mut stream: TcpStream) -> Result<(), Error> {
+    let peer_addr = stream.peer_addr()?;
+    let dst = original_dst(&stream)?;
+    if dst == stream.local_addr()? {
+        // connected directly, would end up in an endless loop
+        return Err(Error::NotRedirected(dst));
+    }
+
+    let mut head = Vec::new();
+    let sniff = tokio::time::timeout(SNIFF_TIMEOUT, read_head(&mut stream, &mut head))
+        .await
+        .unwrap_or(Ok(Sniff::Incomplete))?;
+    let (scheme, host) = match sniff {
+        Sniff::Tls(host) => (http::uri::Scheme::HTTPS, host),
+        Sniff::Http(host) => (http::uri::Scheme::HTTP, host),
+        Sniff::Incomplete => (http::uri::Scheme::HTTP, None),
+    };
+    // the port of the `Host` header is ignored, only the original destination port counts
+    let host = host
+        .map(|h| strip_port(&h).to_owned())
+        .unwrap_or_else(|| match dst.ip() {
+            std::net::IpAddr::V6(ip) => format!("[{ip}]"),
+            ip => ip.to_string(),
+        });
+    let authority = format!("{host}:{}", dst.port());
+    let uri = http::Uri::builder()
+        .scheme(scheme)
+        .authority(authority.as_str())
+        .path_and_query("/")
+        .build()
+        .map_err(std::io::Error::other)?;
+    tracing::debug!(%dst, %uri, "transparent connection");
+
+    let access = accesslog::Entry::begin(
+        peer_addr,
+        http::Method::CONNECT,
+        http::Uri::try_from(authority).map_err(std::io::Error::other)?,
+        http::Version::HTTP_11,
+        None,
+        None,
+    );
+
+    let mut upstream = match context.clone().tunnel(uri.clone()).await {
+        Ok(upstream) => upstream,
+        Err(error) => {
+            context.log_access(access.error(None, &error)).await;
+            return Err(error.into());
+        }
+    };
+    context
+        .log_access(access.success(upstream.proxy().clone(), http::StatusCode::OK, None))
+        .await;
+
+    upstream.write_all(&head).await?;
+    let proxy = upstream.proxy().clone();
+    context
+        .copy_tunnel(peer_addr, &uri, &proxy, &mut stream, &mut upstream)
+        .await?;
+    Ok(())
+}
+
+/// Read from `stream` into `buf` until the host name can be determined.
+async fn read_head(stream: &mut TcpStream, buf: &mut Vec<u8>) -> std::io::Result<Sniff> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(Sniff::Incomplete);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        match sniff(buf) {
+            Sniff::Incomplete if buf.len() < MAX_SNIFF_SIZE => continue,
+            sniff => return Ok(sniff),
+        }
+    }
+}
+
+fn sniff(data: &[u8]) -> Sniff {
+    match data.first() {
+        None => Sniff::Incomplete,
+        // TLS record of content type handshake
+        Some(0x16) => server_name(data),
+        Some(_) => http_host(data),
+    }
+}
+
+/// Server name indication of a TLS ClientHello, see RFC 8446 section 4.1.2 and RFC 6066
+/// section 3.
+fn server_name(data: &[u8]) -> Sniff {
+    let Some(record) = data.get(5..) else {
+        return Sniff::Incomplete;
+    };
+    let record_len = u16::from_be_bytes([data[3], data[4]]) as usize;
+    if record.len() < record_len {
+        return Sniff::Incomplete;
+    }
+    Sniff::Tls(parse_client_hello(&record[..record_len]))
+}
+
+fn parse_client_hello(hs: &[u8]) -> Option<String> {
+    // handshake type client_hello (1), 24 bit length
+    if *hs.first()? != 1 {
+        return None;
+    }
+    // client_version (2) and random (32)
+    let mut rest = hs.get(4 + 2 + 32..)?;
+    let session_id_len = *rest.first()? as usize;
+    rest = rest.get(1 + session_id_len..)?;
+    let cipher_suites_len = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize;
+    rest = rest.get(2 + cipher_suites_len..)?;
+    let compression_len = *rest.first()? as usize;
+    rest = rest.get(1 + compression_len..)?;
+    let extensions_len = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize;
+    let mut extensions = rest.get(2..2 + extensions_len)?;
+    while extensions.len() >= 4 {
+        let ext_type = u16::from_be_bytes([extensions[0], extensions[1]]);
+        let ext_len = u16::from_be_bytes([extensions[2], extensions[3]]) as usize;
+        let ext = extensions.get(4..4 + ext_len)?;
+        if ext_type == 0 {
+            // server_name_list: 16 bit length, name_type host_name (0), 16 bit length, name
+            if *ext.get(2)? != 0 {
+                return None;
+            }
+            let name_len = u16::from_be_bytes([*ext.get(3)?, *ext.get(4)?]) as usize;
+            let name = ext.get(5..5 + name_len)?;
+            return std::str::from_utf8(name).ok().map(|s| s.to_owned());
+        }
+        extensions = &extensions[4 + ext_len..];
+    }
+    None
+}
+
+/// Value of the `Host` header of a HTTP/1.x request.
+fn http_host(data: &[u8]) -> Sniff {
+    let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Sniff::Incomplete;
+    };
+    let host = std::str::from_utf8(&data[..end]).ok().and_then(|head| {
+        head.split("\r\n").skip(1).find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("host")
+                .then(|| value.trim().to_owned())
+        })
+    });
+    Sniff::Http(host.filter(|h| !h.is_empty()))
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((h, port)) if !h.ends_with(':') && port.bytes().all(|b| b.is_ascii_digit()) => h,
+        _ => host,
+    }
+}
+
+/// Destination of a connection before it was redirected by `iptables`.
+#[cfg(target_os = "linux")]
+fn original_dst(stream: &TcpStream) -> std::io::Result<SocketAddr> {
+    use std::os::fd::AsRawFd;
+
+    // from <linux/netfilter_ipv4.h> and <linux/netfilter_ipv6/ip6_tables.h>
+    const SO_ORIGINAL_DST: libc::c_int = 80;
+    const IP6T_SO_ORIGINAL_DST: libc::c_int = 80;
+
+    let fd = stream.as_raw_fd();
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let (level, name) = if stream.local_addr()?.is_ipv4() {
+        (libc::SOL_IP, SO_ORIGINAL_DST)
+    } else {
+        (libc::SOL_IPV6, IP6T_SO_ORIGINAL_DST)
+    };
+    let rc = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            name,
+            &mut addr as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe { *(&addr as *const _ as *const libc::sockaddr_in) };
+            Ok(SocketAddr::from((
+                u32::from_be(addr.sin_addr.s_addr).to_be_bytes(),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { *(&addr as *const _ as *const libc::sockaddr_in6) };
+            Ok(SocketAddr::from((
+                addr.sin6_addr.s6_addr,
+                u16::from_be(addr.sin6_port),
+            )))
+        }
+        family => Err(std::io::Error::other(format!(
+            "unexpected address family {family}"
+        ))),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn original_dst(_stream: &TcpStream) -> std::io::Result<SocketAddr> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "transparent proxy mode is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// TLS record with a ClientHello which only has the server name extension.
+    fn client_hello(name: &str) -> Vec<u8> {
+        let mut sni = vec![0, 0];
+        let list_len = (3 + name.len()) as u16;
+        let ext_len = 2 + list_len;
+        sni.extend_from_slice(&ext_len.to_be_bytes());
+        sni.extend_from_slice(&list_len.to_be_bytes());
+        sni.push(0);
+        sni.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        sni.extend_from_slice(name.as_bytes());
+
+        let mut hello = vec![3, 3];
+        hello.extend_from_slice(&[0u8; 32]);
+        hello.push(0); // session id
+        hello.extend_from_slice(&[0, 2, 0x13, 0x01]); // cipher suites
+        hello.extend_from_slice(&[1, 0]); // compression methods
+        hello.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&sni);
+
+        let mut hs = vec![1, 0];
+        hs.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        hs.extend_from_slice(&hello);
+
+        let mut record = vec![0x16, 3, 1];
+        record.extend_from_slice(&(hs.len() as u16).to_be_bytes());
+        record.extend_from_slice(&hs);
+        record
+    }
+
+    #[test]
+    fn sniff_tls() {
+        let data = client_hello("example.org");
+        assert_eq!(sniff(&data), Sniff::Tls(Some("example.org".into())));
+        assert_eq!(sniff(&data[..data.len() - 1]), Sniff::Incomplete);
+        assert_eq!(sniff(&data[..3]), Sniff::Incomplete);
+    }
+
+    #[test]
+    fn sniff_http() {
+        let data = b"GET / HTTP/1.1\r\nUser-Agent: test\r\nhost: example.org:8080\r\n\r\n";
+        assert_eq!(sniff(data), Sniff::Http(Some("example.org:8080".into())));
+        assert_eq!(sniff(&data[..20]), Sniff::Incomplete);
+        assert_eq!(sniff(b"GET / HTTP/1.0\r\n\r\n"), Sniff::Http(None));
+    }
+
+    #[test]
+    fn strip_port_test() {
+        assert_eq!(strip_port("example.org:8080"), "example.org");
+        assert_eq!(strip_port("example.org"), "example.org");
+        assert_eq!(strip_port("[::1]:80"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+    }
+}
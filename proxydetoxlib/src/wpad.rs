@@ -0,0 +1,302 @@
+//! Web Proxy Auto-Discovery (WPAD) of the PAC file.
+//!
+//! The URL is taken from DHCP option 252, either from the lease files of the local DHCP client
+//! or by asking the DHCP server with a `DHCPINFORM` message. Then `http://wpad.<domain>/wpad.dat`
+//! is tried for each search domain. Parent domains are not tried: without the public suffix list
+//! `wpad.co.uk` could be asked for, a name anyone can register to take over all traffic.
+
+use detox_net::dns::Resolver;
+use http::Uri;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::net::UdpSocket;
+
+/// DHCP option carrying the URL of the PAC file.
+const WPAD_OPTION: u8 = 252;
+/// Time to wait for the answer of the DHCP server.
+const DHCP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Directories where DHCP clients keep their leases.
+const LEASE_DIRS: &[&str] = &[
+    "/var/lib/dhcp",
+    "/var/lib/dhclient",
+    "/var/db",
+    "/var/lib/NetworkManager",
+    "/run/systemd/netif/leases",
+];
+
+/// Candidate URLs of the PAC file, the most specific first.
+pub async fn candidates(resolver: &Resolver) -> Vec<Uri> {
+    let mut uris = Vec::new();
+    for uri in lease_files()
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .filter_map(|content| parse_lease(&content))
+    {
+        if !uris.contains(&uri) {
+            uris.push(uri);
+        }
+    }
+    if uris.is_empty() {
+        match dhcp_inform().await {
+            Ok(Some(uri)) => uris.push(uri),
+            Ok(None) => {}
+            Err(cause) => tracing::debug!(%cause, "DHCPINFORM failed"),
+        }
+    }
+    for domain in resolver.search() {
+        // the absolute name, the search domains must not be appended again
+        if let Some(host) = wpad_host(&domain)
+            && resolver.lookup(&format!("{host}.")).await.is_ok()
+            && let Ok(uri) = format!("http://{host}/wpad.dat").parse()
+        {
+            uris.push(uri);
+        }
+    }
+    tracing::debug!(?uris, "WPAD candidates");
+    uris
+}
+
+/// `wpad` host name of the search domain `domain`, none for top level domains.
+fn wpad_host(domain: &str) -> Option<String> {
+    let domain = domain.trim_matches('.');
+    domain.contains('.').then(|| format!("wpad.{domain}"))
+}
+
+/// Lease files of the DHCP clients, the most recently modified first.
+fn lease_files() -> Vec<PathBuf> {
+    let mut files = LEASE_DIRS
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| is_lease_file(path))
+        .filter_map(|path| {
+            let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, path))
+        })
+        .collect::<Vec<(SystemTime, PathBuf)>>();
+    files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+fn is_lease_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    // systemd-networkd names the files by interface index
+    path.starts_with("/run/systemd/netif/leases")
+        || name.ends_with(".lease")
+        || name.ends_with(".leases")
+        || name.starts_with("dhclient.leases")
+}
+
+/// WPAD URL of the newest lease of a `dhclient` or `systemd-networkd` lease file.
+fn parse_lease(content: &str) -> Option<Uri> {
+    let mut url = None;
+    for line in content.lines() {
+        let line = line.trim();
+        let value = if let Some(option) = line.strip_prefix("option ") {
+            // dhclient: `option wpad "http://..";` or `option unknown-252 68:74:..;`
+            let Some((name, value)) = option.trim_end_matches(';').split_once(' ') else {
+                continue;
+            };
+            match name {
+                "wpad" | "wpad-url" | "option-252" | "unknown-252" => value.trim(),
+                _ => continue,
+            }
+        } else if let Some(value) = line.strip_prefix("OPTION_252=") {
+            // systemd-networkd: private options as hex string
+            value
+        } else {
+            continue;
+        };
+        // later leases of dhclient files are newer
+        url = option_value(value).or(url);
+    }
+    url
+}
+
+/// String of a quoted or hex encoded option value.
+fn option_value(value: &str) -> Option<Uri> {
+    let value = if let Some(quoted) = value.strip_prefix('"') {
+        quoted.strip_suffix('"')?.to_owned()
+    } else {
+        let hex = value.replace(':', "");
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        String::from_utf8(bytes).ok()?
+    };
+    parse_url(value.as_bytes())
+}
+
+fn parse_url(value: &[u8]) -> Option<Uri> {
+    // some servers include the terminating NUL
+    let value = std::str::from_utf8(value)
+        .ok()?
+        .trim_end_matches('\0')
+        .trim();
+    value
+        .parse::<Uri>()
+        .ok()
+        .filter(|uri| uri.scheme().is_some() && uri.authority().is_some())
+}
+
+/// Ask the DHCP server for option 252, see RFC 2131 section 3.4.
+///
+/// Binding the DHCP client port requires elevated privileges, without them this fails.
+async fn dhcp_inform() -> std::io::Result<Option<Uri>> {
+    let local = local_ipv4().await?;
+    let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 68))).await?;
+    socket.set_broadcast(true)?;
+    let mut xid = [0u8; 4];
+    getrandom::fill(&mut xid).map_err(|e| std::io::Error::other(e.to_string()))?;
+    socket
+        .send_to(
+            &inform_message(xid, local),
+            SocketAddr::from((Ipv4Addr::BROADCAST, 67)),
+        )
+        .await?;
+
+    let mut buf = vec![0u8; 1500];
+    let answer = async {
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            if let Some(uri) = parse_ack(xid, &buf[..len]) {
+                return Ok::<_, std::io::Error>(uri);
+            }
+        }
+    };
+    match tokio::time::timeout(DHCP_TIMEOUT, answer).await {
+        Ok(uri) => uri,
+        Err(_) => Ok(None),
+    }
+}
+
+/// Address of the interface of the default route.
+async fn local_ipv4() -> std::io::Result<Ipv4Addr> {
+    let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?;
+    // no packet is send, this only selects the route
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).await?;
+    match socket.local_addr()? {
+        SocketAddr::V4(addr) => Ok(*addr.ip()),
+        SocketAddr::V6(_) => Err(std::io::Error::other("no IPv4 address")),
+    }
+}
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+fn inform_message(xid: [u8; 4], ciaddr: Ipv4Addr) -> Vec<u8> {
+    let mut msg = vec![0u8; 236];
+    msg[0] = 1; // BOOTREQUEST
+    msg[1] = 1; // Ethernet
+    msg[2] = 6; // hardware address length
+    msg[4..8].copy_from_slice(&xid);
+    msg[12..16].copy_from_slice(&ciaddr.octets());
+    msg.extend_from_slice(&MAGIC_COOKIE);
+    // DHCP message type DHCPINFORM, parameter request list, end
+    msg.extend_from_slice(&[53, 1, 8, 55, 1, WPAD_OPTION, 255]);
+    // minimal BOOTP message size
+    msg.resize(300, 0);
+    msg
+}
+
+/// WPAD URL of a `DHCPACK` answer to the message `xid`, `None` for other messages.
+fn parse_ack(xid: [u8; 4], msg: &[u8]) -> Option<Option<Uri>> {
+    if msg.len() < 240 || msg[0] != 2 || msg[4..8] != xid || msg[236..240] != MAGIC_COOKIE {
+        return None;
+    }
+    let mut options = &msg[240..];
+    let mut is_ack = false;
+    let mut uri = None;
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            0 => {
+                options = rest;
+                continue;
+            }
+            255 => break,
+            _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let value = rest.get(..len as usize)?;
+        match code {
+            53 => is_ack = value == [5],
+            WPAD_OPTION => uri = parse_url(value),
+            _ => {}
+        }
+        options = &rest[len as usize..];
+    }
+    is_ack.then_some(uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wpad_host_test() {
+        assert_eq!(
+            wpad_host("a.b.example.org.").as_deref(),
+            Some("wpad.a.b.example.org")
+        );
+        // never above the search domain, e.g. a public suffix
+        assert_eq!(
+            wpad_host("corp.example.co.uk").as_deref(),
+            Some("wpad.corp.example.co.uk")
+        );
+        assert_eq!(wpad_host("com"), None);
+        assert_eq!(wpad_host("local"), None);
+    }
+
+    #[test]
+    fn parse_dhclient_lease() {
+        let content = r#"
+lease {
+  interface "eth0";
+  option wpad "http://old.example.org/proxy.pac";
+}
+lease {
+  interface "eth0";
+  option domain-name "example.org";
+  option unknown-252 68:74:74:70:3a:2f:2f:77:70:61:64:2f:77:70:61:64:2e:64:61:74:00;
+}
+"#;
+        assert_eq!(parse_lease(content).unwrap(), "http://wpad/wpad.dat");
+        assert_eq!(parse_lease("lease {\n}\n"), None);
+    }
+
+    #[test]
+    fn parse_networkd_lease() {
+        let content = "ADDRESS=192.0.2.10\nOPTION_252=687474703a2f2f777061642f777061642e646174\n";
+        assert_eq!(parse_lease(content).unwrap(), "http://wpad/wpad.dat");
+    }
+
+    #[test]
+    fn dhcp_inform_and_ack() {
+        let xid = [1, 2, 3, 4];
+        let msg = inform_message(xid, Ipv4Addr::new(192, 0, 2, 10));
+        assert_eq!(msg.len(), 300);
+        assert_eq!(&msg[12..16], &[192, 0, 2, 10]);
+
+        let url = b"http://wpad.example.org/wpad.dat";
+        let mut ack = msg[..240].to_vec();
+        ack[0] = 2;
+        ack.extend_from_slice(&[53, 1, 5, 0, WPAD_OPTION, url.len() as u8]);
+        ack.extend_from_slice(url);
+        ack.push(255);
+        assert_eq!(
+            parse_ack(xid, &ack).unwrap().unwrap(),
+            "http://wpad.example.org/wpad.dat"
+        );
+        assert!(parse_ack([0; 4], &ack).is_none());
+
+        // DHCPNAK
+        ack[242] = 6;
+        assert!(parse_ack(xid, &ack).is_none());
+    }
+}
@@ -5,6 +5,7 @@ use hyper_util::rt::TokioIo;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
@@ -12,6 +13,7 @@ pub struct Server {
     spawn_handle: JoinHandle<()>,
     shutdown_token: CancellationToken,
     local_addr: SocketAddr,
+    connections: Arc<AtomicUsize>,
 }
 
 impl Server {
@@ -31,14 +33,17 @@ impl Server {
         let local_addr = listener.local_addr().expect("local_addr");
 
         let shutdown_token = CancellationToken::new();
+        let connections = Arc::new(AtomicUsize::new(0));
 
         let spawn_handle = tokio::spawn({
             let shutdown_token = shutdown_token.clone();
+            let connections = connections.clone();
             async move {
                 loop {
                     tokio::select! {
                         stream = listener.accept() => {
                             let (stream, _addr) = stream.expect("accept");
+                            connections.fetch_add(1, Ordering::SeqCst);
                             let stream = TokioIo::new(stream);
                             let service = service.clone();
                             tokio::task::spawn({
@@ -67,9 +72,15 @@ impl Server {
             local_addr,
             spawn_handle,
             shutdown_token,
+            connections,
         }
     }
 
+    /// Number of accepted connections.
+    pub(crate) fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
     pub(crate) fn host_and_port(&self) -> String {
         self.local_addr.to_string()
     }
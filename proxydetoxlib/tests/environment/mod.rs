@@ -106,6 +106,11 @@ impl Environment {
         response
     }
 
+    /// Stop accepting new connections, but keep serving the established ones.
+    pub(crate) fn request_shutdown(&self) {
+        self.shutdown_token.cancel();
+    }
+
     pub(crate) async fn shutdown(self) {
         self.shutdown_token.cancel();
         self.server_handle.await.ok();
@@ -116,7 +121,9 @@ impl Environment {
 pub(crate) struct Builder {
     pac_script: Option<String>,
     netrc_content: Option<String>,
+    authenticator: Option<fn(netrc::Store) -> AuthenticatorFactory>,
     proxytunnel: bool,
+    request_buffer_size: Option<usize>,
 }
 
 impl Builder {
@@ -130,6 +137,18 @@ impl Builder {
         self
     }
 
+    /// Authenticator to use with the `netrc_content`, basic by default.
+    pub(crate) fn authenticator(mut self, make: fn(netrc::Store) -> AuthenticatorFactory) -> Self {
+        self.authenticator = Some(make);
+        self
+    }
+
+    /// Maximum size of request bodies which can be send again.
+    pub(crate) fn request_buffer_size(mut self, size: usize) -> Self {
+        self.request_buffer_size = Some(size);
+        self
+    }
+
     pub(crate) async fn build(self) -> Environment {
         INIT.call_once(|| {
             rustls::crypto::CryptoProvider::install_default(
@@ -141,16 +160,19 @@ impl Builder {
         let auth = self
             .netrc_content
             .map(|n| netrc::Store::new(Cursor::new(n)).unwrap())
-            .map(AuthenticatorFactory::basic);
+            .map(self.authenticator.unwrap_or(AuthenticatorFactory::basic));
 
-        let context = proxydetoxlib::Context::builder()
+        let mut context = proxydetoxlib::Context::builder()
             .pac_script(
                 self.pac_script
                     .unwrap_or_else(|| proxydetoxlib::DEFAULT_PAC_SCRIPT.to_string()),
             )
             .authenticator_factory(auth)
-            .proxytunnel(self.proxytunnel)
-            .build();
+            .proxytunnel(self.proxytunnel);
+        if let Some(size) = self.request_buffer_size {
+            context = context.request_buffer_size(size);
+        }
+        let context = context.build();
 
         let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
             .await
@@ -177,7 +199,7 @@ impl Builder {
                 };
                 j.unwrap()
                     .unwrap()
-                    .wait_with_timeout(Duration::from_secs(0))
+                    .wait_with_timeout(Duration::from_secs(5))
                     .await
                     .unwrap();
             }
@@ -0,0 +1,236 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use detox_auth::AuthenticatorFactory;
+use detox_hyper::{conn::Connection, h2};
+use detox_net::HostAndPort;
+use http::{Method, Request, Response, StatusCode, Version};
+use http_body_util::Empty;
+use hyper::service::service_fn;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use paclib::Proxy;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+use tokio_rustls::TlsAcceptor;
+
+static INIT: std::sync::Once = std::sync::Once::new();
+
+const CA_PEM: &[u8] = include_bytes!("environment/certs/ca.pem");
+const PROXY_PEM: &[u8] = include_bytes!("environment/certs/proxy.pem");
+const PROXY_KEY: &[u8] = include_bytes!("environment/certs/proxy.key");
+
+/// HTTPS proxy which echoes the data of each tunnel back to the client.
+struct TlsProxy {
+    accept_handle: JoinHandle<()>,
+    local_addr: SocketAddr,
+    connections: Arc<AtomicUsize>,
+    /// HTTP versions of the received `CONNECT` requests.
+    versions: Arc<Mutex<Vec<Version>>>,
+    conn_handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl TlsProxy {
+    async fn new(alpn_protocols: &[&[u8]]) -> Self {
+        install_crypto_provider();
+        let certs = rustls_pemfile::certs(&mut &PROXY_PEM[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = rustls_pemfile::private_key(&mut &PROXY_KEY[..])
+            .unwrap()
+            .unwrap();
+        let mut config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap();
+        config.alpn_protocols = alpn_protocols.iter().map(|p| p.to_vec()).collect();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let local_addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let versions = Arc::new(Mutex::new(Vec::new()));
+        let conn_handles = Arc::new(Mutex::new(Vec::new()));
+
+        let accept_handle = tokio::spawn({
+            let connections = connections.clone();
+            let versions = versions.clone();
+            let conn_handles = conn_handles.clone();
+            async move {
+                loop {
+                    let (stream, _addr) = listener.accept().await.unwrap();
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    let acceptor = acceptor.clone();
+                    let versions = versions.clone();
+                    let handle = tokio::spawn(async move {
+                        let stream = acceptor.accept(stream).await.unwrap();
+                        let service = service_fn(move |r| {
+                            versions.lock().unwrap().push(r.version());
+                            async move { Ok::<_, Infallible>(echo_tunnel(r)) }
+                        });
+                        auto::Builder::new(TokioExecutor::new())
+                            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                            .await
+                            .ok();
+                    });
+                    conn_handles.lock().unwrap().push(handle);
+                }
+            }
+        });
+
+        Self {
+            accept_handle,
+            local_addr,
+            connections,
+            versions,
+            conn_handles,
+        }
+    }
+
+    fn proxy(&self) -> Proxy {
+        Proxy::Https(HostAndPort::new("127.0.0.1", self.local_addr.port()))
+    }
+
+    /// Number of accepted connections.
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    fn versions(&self) -> Vec<Version> {
+        self.versions.lock().unwrap().clone()
+    }
+
+    /// Drop all connections to the proxy, including their tunnels.
+    async fn disconnect(&self) {
+        let handles = std::mem::take(&mut *self.conn_handles.lock().unwrap());
+        for handle in handles {
+            handle.abort();
+            handle.await.ok();
+        }
+    }
+
+    async fn shutdown(self) {
+        self.disconnect().await;
+        self.accept_handle.abort();
+        self.accept_handle.await.ok();
+    }
+}
+
+fn echo_tunnel(r: Request<hyper::body::Incoming>) -> Response<Empty<Bytes>> {
+    assert_eq!(r.method(), Method::CONNECT);
+    tokio::spawn(async move {
+        let mut upgraded = TokioIo::new(hyper::upgrade::on(r).await.unwrap());
+        let mut buf = [0u8; 4];
+        while upgraded.read_exact(&mut buf).await.is_ok() {
+            upgraded.write_all(&buf).await.unwrap();
+        }
+    });
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Empty::new())
+        .unwrap()
+}
+
+fn install_crypto_provider() {
+    INIT.call_once(|| {
+        rustls::crypto::CryptoProvider::install_default(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        )
+        .expect("CryptoProvider::install_default");
+    });
+}
+
+fn tls_config() -> Arc<rustls::ClientConfig> {
+    install_crypto_provider();
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut &CA_PEM[..]) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let cfg = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(cfg)
+}
+
+async fn tunnel(proxy: &TlsProxy, sessions: &h2::Sessions, message: &[u8; 4]) {
+    let mut conn = Connection::http_tunnel(
+        proxy.proxy(),
+        tls_config(),
+        AuthenticatorFactory::none(),
+        HostAndPort::new("example.org", 443),
+    )
+    .with_h2_sessions(sessions.clone())
+    .await
+    .unwrap();
+    conn.write_all(message).await.unwrap();
+    let mut buf = [0u8; 4];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, message);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn h2_tunnels_share_one_session() {
+    let proxy = TlsProxy::new(&[b"h2", b"http/1.1"]).await;
+    let sessions = h2::Sessions::new();
+
+    // concurrent first connects must not open one session each
+    tokio::join!(
+        tunnel(&proxy, &sessions, b"PING"),
+        tunnel(&proxy, &sessions, b"PONG"),
+    );
+    tunnel(&proxy, &sessions, b"PANG").await;
+
+    assert_eq!(proxy.connections(), 1);
+    assert_eq!(proxy.versions(), vec![Version::HTTP_2; 3]);
+
+    proxy.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn h2_session_reconnect_after_close() {
+    let proxy = TlsProxy::new(&[b"h2", b"http/1.1"]).await;
+    let sessions = h2::Sessions::new();
+
+    tunnel(&proxy, &sessions, b"PING").await;
+    proxy.disconnect().await;
+    // give the client time to notice the closed session
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    tunnel(&proxy, &sessions, b"PONG").await;
+
+    assert_eq!(proxy.connections(), 2);
+    assert_eq!(proxy.versions(), vec![Version::HTTP_2; 2]);
+
+    proxy.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn h2_not_offered_by_proxy() {
+    let proxy = TlsProxy::new(&[b"http/1.1"]).await;
+    let sessions = h2::Sessions::new();
+
+    tokio::join!(
+        tunnel(&proxy, &sessions, b"PING"),
+        tunnel(&proxy, &sessions, b"PONG"),
+    );
+
+    // each tunnel needs its own HTTP/1.1 connection
+    assert_eq!(proxy.connections(), 2);
+    assert_eq!(proxy.versions(), vec![Version::HTTP_11; 2]);
+
+    proxy.shutdown().await;
+}
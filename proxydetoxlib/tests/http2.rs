@@ -0,0 +1,101 @@
+mod environment;
+
+use crate::environment::{Environment, httpd, read_to_string, tcp};
+use http::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn h2c_handshake(
+    env: &Environment,
+) -> hyper::client::conn::http2::SendRequest<crate::environment::Body> {
+    let stream = TcpStream::connect(env.proxy_addr()).await.unwrap();
+    let (request_sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+    tokio::spawn(connection);
+    request_sender
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn http2_get_direct() {
+    let http1 = httpd::Server::new(|r| {
+        assert_eq!(r.method(), http::method::Method::GET);
+        assert_eq!(r.version(), http::Version::HTTP_11);
+        assert_eq!(r.uri().path(), "/text1.html");
+        assert!(r.headers().contains_key(http::header::HOST));
+        Response::builder()
+            .body(crate::environment::full(String::from("Hello World!")))
+            .unwrap()
+    })
+    .await;
+    let env = Environment::new().await;
+    let mut request_sender = h2c_handshake(&env).await;
+
+    let req = Request::get(http1.uri().path_and_query("/text1.html").build().unwrap())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = request_sender.send_request(req).await.unwrap();
+
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    assert_eq!(resp.version(), http::Version::HTTP_2);
+    let body = read_to_string(resp.into_body()).await;
+    assert_eq!(body, "Hello World!");
+
+    tokio::join!(env.shutdown(), http1.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn http2_multiplexed_connect() {
+    let echo = tcp::Server::new(|mut s| async move {
+        let mut buf = [0u8; 4];
+        s.read_exact(&mut buf).await.unwrap();
+        s.write_all(&buf).await.unwrap();
+    })
+    .await;
+    let env = Environment::new().await;
+    let request_sender = h2c_handshake(&env).await;
+
+    let tunnel = |message: &'static [u8; 4]| {
+        let mut request_sender = request_sender.clone();
+        let req = Request::connect(echo.origin())
+            .body(crate::environment::empty())
+            .unwrap();
+        async move {
+            let resp = request_sender.send_request(req).await.unwrap();
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let mut upgraded = TokioIo::new(hyper::upgrade::on(resp).await.unwrap());
+            upgraded.write_all(message).await.unwrap();
+            let mut buf = [0u8; 4];
+            upgraded.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, message);
+        }
+    };
+
+    // both tunnels share the same HTTP/2 connection
+    tokio::join!(tunnel(b"PING"), tunnel(b"PONG"));
+
+    tokio::join!(env.shutdown(), echo.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn http2_extended_connect_not_enabled() {
+    let env = Environment::new().await;
+    let mut request_sender = h2c_handshake(&env).await;
+
+    // let the client receive the SETTINGS frame of the proxy
+    let req = Request::get("/").body(crate::environment::empty()).unwrap();
+    let resp = request_sender.send_request(req).await.unwrap();
+    assert_eq!(resp.version(), http::Version::HTTP_2);
+
+    // SETTINGS_ENABLE_CONNECT_PROTOCOL is not announced, the client refuses to send it
+    let mut req = Request::connect("https://example.org/chat")
+        .body(crate::environment::empty())
+        .unwrap();
+    req.extensions_mut()
+        .insert(hyper::ext::Protocol::from_static("websocket"));
+    assert!(request_sender.send_request(req).await.is_err());
+
+    env.shutdown().await;
+}
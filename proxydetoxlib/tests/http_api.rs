@@ -2,13 +2,15 @@ mod environment;
 
 use std::io::Read;
 
-use crate::environment::Environment;
+use crate::environment::{Environment, tcp};
 use bytes::Buf;
 use http::{
     Request,
     header::{CONTENT_TYPE, HOST},
 };
 use http_body_util::BodyExt;
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn api_get_request() {
@@ -80,3 +82,256 @@ async fn api_get_proxy_pac() {
 
     env.shutdown().await;
 }
+
+async fn read_json(resp: http::Response<hyper::body::Incoming>) -> serde_json::Value {
+    let body = crate::environment::read_to_string(resp.into_body()).await;
+    serde_json::from_str(&body).expect("JSON body")
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_v1_direct_mode() {
+    let env = Environment::new().await;
+
+    let req = Request::put("/api/v1/direct-mode")
+        .header(HOST, env.proxy_addr().to_string())
+        .header(CONTENT_TYPE, "application/json")
+        .body(crate::environment::full(r#"{"enabled": true}"#))
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    assert_eq!(read_json(resp).await["enabled"], true);
+
+    let req = Request::get("/api/v1/config")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    assert_eq!(read_json(resp).await["direct_mode"], true);
+
+    env.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_v1_my_ip_address() {
+    let env = Environment::new().await;
+
+    let req = Request::put("/api/v1/my-ip-address")
+        .header(HOST, env.proxy_addr().to_string())
+        .header(CONTENT_TYPE, "application/json")
+        .body(crate::environment::full(r#"{"address": "192.0.2.1"}"#))
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+
+    let req = Request::get("/api/v1/my-ip-address")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    assert_eq!(read_json(resp).await["address"], "192.0.2.1");
+
+    env.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_v1_pac_status() {
+    let env = Environment::new().await;
+
+    let req = Request::get("/api/v1/pac")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    assert_eq!(
+        resp.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok()),
+        Some("application/json")
+    );
+    assert!(read_json(resp).await["error"].is_null());
+
+    env.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_v1_rejected_requests() {
+    let env = Environment::new().await;
+
+    // a form post of a web page must not change the state
+    let req = Request::put("/api/v1/direct-mode")
+        .header(HOST, env.proxy_addr().to_string())
+        .header(CONTENT_TYPE, "text/plain")
+        .body(crate::environment::full(r#"{"enabled": true}"#))
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // DNS rebinding
+    let req = Request::get("/api/v1/config")
+        .header(HOST, "evil.example.org")
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+
+    let req = Request::delete("/api/v1/pac")
+        .header(HOST, env.proxy_addr().to_string())
+        .header(CONTENT_TYPE, "application/json")
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+
+    let req = Request::get("/api/v1/unknown")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+    env.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_get_metrics() {
+    let http1 = crate::environment::httpd::Server::new(|_r| {
+        http::Response::builder()
+            .body(crate::environment::full("Hello World!"))
+            .unwrap()
+    })
+    .await;
+    let env = Environment::new().await;
+
+    let req = Request::get(http1.uri().path_and_query("/").build().unwrap())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+
+    let req = Request::get("/metrics")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let body = crate::environment::read_to_string(resp.into_body()).await;
+    assert!(body.contains("proxydetox_requests_total{method=\"GET\",status=\"200\"} 1\n"));
+    assert!(body.contains("proxydetox_connect_attempts_total{proxy=\"DIRECT\"} 1\n"));
+    assert!(body.contains("proxydetox_pac_eval_duration_seconds_count 1\n"));
+    assert!(body.contains("proxydetox_tunnels_active 0\n"));
+
+    tokio::join!(env.shutdown(), http1.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_v1_status() {
+    let env = Environment::new().await;
+
+    let req = Request::get("/api/v1/status")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let status = read_json(resp).await;
+    assert_eq!(status["direct_mode"], false);
+    assert_eq!(status["tunnels_active"], 0);
+    assert_eq!(status["error_rate"]["errors"], 0);
+    assert!(status["proxies"].is_array());
+
+    let req = Request::get("/")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let body = crate::environment::read_to_string(resp.into_body()).await;
+    assert!(body.contains("/api/v1/status/events"));
+
+    env.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_v1_status_open_tunnels() {
+    let echo = tcp::Server::new(|mut s| async move {
+        let mut buf = [0u8; 4];
+        while s.read_exact(&mut buf).await.is_ok() {
+            s.write_all(&buf).await.unwrap();
+        }
+    })
+    .await;
+    let env = Environment::new().await;
+
+    let req = Request::connect(echo.origin())
+        .body(crate::environment::empty())
+        .unwrap();
+    let (status, _headers, upgraded) = env.connect(req).await;
+    assert_eq!(status, http::StatusCode::OK);
+    let mut upgraded = TokioIo::new(upgraded.expect("upgraded"));
+    upgraded.write_all(b"PING").await.unwrap();
+    let mut buf = [0u8; 4];
+    upgraded.read_exact(&mut buf).await.unwrap();
+
+    // the bytes of the open tunnel are already counted
+    let req = Request::get("/api/v1/status")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let status = read_json(env.send(req).await).await;
+    assert_eq!(status["tunnels_active"], 1);
+    let tunnel = &status["tunnels"][0];
+    assert_eq!(tunnel["destination"], echo.origin());
+    assert_eq!(tunnel["proxy"], "DIRECT");
+    assert_eq!(tunnel["bytes_sent"], 4);
+    assert_eq!(tunnel["bytes_received"], 4);
+    assert_eq!(status["tunnel_bytes_sent"], 4);
+
+    drop(upgraded);
+    tokio::join!(env.shutdown(), echo.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_v1_find_proxy() {
+    let http1 = crate::environment::httpd::Server::new(|_r| {
+        http::Response::builder()
+            .body(crate::environment::empty())
+            .unwrap()
+    })
+    .await;
+    let env = Environment::new().await;
+
+    let url = http1.uri().path_and_query("/").build().unwrap().to_string();
+    let req = Request::get(format!(
+        "/api/v1/find-proxy?url={}&connect=true",
+        url.replace(':', "%3A").replace('/', "%2F")
+    ))
+    .header(HOST, env.proxy_addr().to_string())
+    .body(crate::environment::empty())
+    .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let result = read_json(resp).await;
+    assert_eq!(result["url"], url);
+    assert_eq!(result["proxies"][0]["proxy"], "DIRECT");
+    assert_eq!(result["proxies"][0]["connect"]["ok"], true);
+
+    let req = Request::get("/metrics")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    let body = crate::environment::read_to_string(resp.into_body()).await;
+    assert!(!body.contains("proxydetox_connect_attempts_total{"));
+
+    let req = Request::get("/api/v1/find-proxy?url=example.org")
+        .header(HOST, env.proxy_addr().to_string())
+        .body(crate::environment::empty())
+        .unwrap();
+    let resp = env.send(req).await;
+    assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+    tokio::join!(env.shutdown(), http1.shutdown());
+}
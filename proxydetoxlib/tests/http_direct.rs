@@ -83,3 +83,50 @@ async fn pac_script_invalid_result() {
 
     tokio::join!(env.shutdown(), http1.shutdown());
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn http_post_request_body_is_streamed() {
+    let http1 = httpd::Server::new(|r| {
+        assert_eq!(r.method(), http::method::Method::POST);
+        // answer before the request body is complete
+        Response::builder()
+            .body(crate::environment::full(String::from("Hello World!")))
+            .unwrap()
+    })
+    .await;
+    let env = Environment::new().await;
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<_, hyper::Error>>(1);
+    let body = http_body_util::StreamBody::new(tokio_stream::wrappers::ReceiverStream::new(rx));
+    let req = Request::post(http1.uri().path_and_query("/upload").build().unwrap())
+        .body(http_body_util::BodyExt::boxed(body))
+        .unwrap();
+    tx.send(Ok(hyper::body::Frame::data(bytes::Bytes::from("hello "))))
+        .await
+        .unwrap();
+
+    let stream = tokio::net::TcpStream::connect(env.proxy_addr())
+        .await
+        .unwrap();
+    let (mut request_sender, connection) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+            .await
+            .unwrap();
+    tokio::spawn(connection);
+
+    // the request body is still open, the proxy must not wait for its end
+    let resp = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        request_sender.send_request(req),
+    )
+    .await
+    .expect("response before the end of the request body")
+    .unwrap();
+
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let body = read_to_string(resp.into_body()).await;
+    assert_eq!(body, "Hello World!");
+    drop(tx);
+
+    tokio::join!(env.shutdown(), http1.shutdown());
+}
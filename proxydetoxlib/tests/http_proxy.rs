@@ -1,7 +1,14 @@
 mod environment;
 
 use crate::environment::{Environment, httpd, read_to_string};
-use http::{Request, Response, Uri, header::PROXY_AUTHORIZATION};
+use detox_auth::AuthenticatorFactory;
+use http::{
+    Request, Response, Uri,
+    header::{PROXY_AUTHENTICATE, PROXY_AUTHORIZATION},
+};
+use http_body_util::BodyExt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
 async fn http_get_via_proxy_request() {
@@ -36,6 +43,43 @@ async fn http_get_via_proxy_request() {
     tokio::join!(env.shutdown(), http1.shutdown());
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn http_get_via_proxy_reuses_connection() {
+    let proxy1 = httpd::Server::new(|r| {
+        assert_eq!(r.method(), http::method::Method::GET);
+        assert!(r.uri().authority().is_some());
+        Response::builder()
+            .body(crate::environment::full(String::from("Hello World!")))
+            .unwrap()
+    })
+    .await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"PROXY {}\"; }}",
+            proxy1.uri().build().unwrap().authority().unwrap()
+        )))
+        .build()
+        .await;
+
+    for _ in 0..3 {
+        let req = Request::get("http://example.org/text1.html".parse::<Uri>().unwrap())
+            .body(crate::environment::empty())
+            .unwrap();
+
+        let resp = env.send(req).await;
+
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body = read_to_string(resp.into_body()).await;
+        assert_eq!(body, "Hello World!");
+        // give the connection time to return into the pool
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+
+    assert_eq!(proxy1.connections(), 1);
+
+    tokio::join!(env.shutdown(), proxy1.shutdown());
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
 async fn http_get_via_proxy_with_auth_request() {
     let proxy1 = httpd::Server::new(|r| {
@@ -80,6 +124,196 @@ async fn http_get_via_proxy_with_auth_request() {
     tokio::join!(env.shutdown(), proxy1.shutdown());
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn http_get_via_proxy_with_ntlm_auth_request() {
+    let proxy1 = httpd::Server::new(|r| {
+        assert_eq!(r.method(), http::method::Method::GET);
+        assert_eq!(r.uri().path(), "/text1.html");
+        let auth = r
+            .headers()
+            .get(PROXY_AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if auth.starts_with("NTLM TlRMTVNTUAAB") {
+            // negotiate message, answer with a challenge message
+            Response::builder()
+                .status(http::StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .header(
+                    PROXY_AUTHENTICATE,
+                    "NTLM TlRMTVNTUAACAAAAAAAAADAAAAABAgAAASNFZ4mrze8AAAAAAAAAAAAAAAAwAAAA",
+                )
+                .body(crate::environment::empty())
+                .unwrap()
+        } else {
+            // authenticate message
+            assert!(auth.starts_with("NTLM TlRMTVNTUAAD"));
+            Response::builder()
+                .body(crate::environment::full(String::from("Hello World!")))
+                .unwrap()
+        }
+    })
+    .await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"PROXY {}\"; }}",
+            proxy1.uri().build().unwrap().authority().unwrap()
+        )))
+        .netrc_content(Some(format!(
+            "machine {}\nlogin {}\npassword {}\n",
+            proxy1.uri().build().unwrap().host().unwrap(),
+            "DOMAIN\\hello",
+            "world"
+        )))
+        .authenticator(AuthenticatorFactory::ntlm)
+        .build()
+        .await;
+
+    let req = Request::get("http://example.org/text1.html".parse::<Uri>().unwrap())
+        .body(crate::environment::empty())
+        .unwrap();
+
+    let resp = env.send(req).await;
+
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let body = read_to_string(resp.into_body()).await;
+    assert_eq!(body, "Hello World!");
+    // the handshake is done on a single connection
+    assert_eq!(proxy1.connections(), 1);
+
+    tokio::join!(env.shutdown(), proxy1.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn http_get_via_proxy_with_ntlm_auth_reuses_authenticated_connection() {
+    let negotiations = Arc::new(AtomicUsize::new(0));
+    let proxy1 = httpd::Server::new({
+        let negotiations = negotiations.clone();
+        move |r| {
+            let auth = r
+                .headers()
+                .get(PROXY_AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            if auth.starts_with("NTLM TlRMTVNTUAAB") {
+                negotiations.fetch_add(1, Ordering::SeqCst);
+                Response::builder()
+                    .status(http::StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                    .header(
+                        PROXY_AUTHENTICATE,
+                        "NTLM TlRMTVNTUAACAAAAAAAAADAAAAABAgAAASNFZ4mrze8AAAAAAAAAAAAAAAAwAAAA",
+                    )
+                    .body(crate::environment::empty())
+                    .unwrap()
+            } else {
+                // authenticate message, or a request on the authenticated connection
+                assert!(auth.is_empty() || auth.starts_with("NTLM TlRMTVNTUAAD"));
+                Response::builder()
+                    .body(crate::environment::full(String::from("Hello World!")))
+                    .unwrap()
+            }
+        }
+    })
+    .await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"PROXY {}\"; }}",
+            proxy1.uri().build().unwrap().authority().unwrap()
+        )))
+        .netrc_content(Some(format!(
+            "machine {}\nlogin {}\npassword {}\n",
+            proxy1.uri().build().unwrap().host().unwrap(),
+            "DOMAIN\\hello",
+            "world"
+        )))
+        .authenticator(AuthenticatorFactory::ntlm)
+        .build()
+        .await;
+
+    for _ in 0..3 {
+        let req = Request::get("http://example.org/text1.html".parse::<Uri>().unwrap())
+            .body(crate::environment::empty())
+            .unwrap();
+
+        let resp = env.send(req).await;
+
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body = read_to_string(resp.into_body()).await;
+        assert_eq!(body, "Hello World!");
+        // give the connection time to return into the pool
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+
+    // the handshake is done once for the pooled connection
+    assert_eq!(proxy1.connections(), 1);
+    assert_eq!(negotiations.load(Ordering::SeqCst), 1);
+
+    tokio::join!(env.shutdown(), proxy1.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn http_get_via_proxy_with_auto_auth_request() {
+    let proxy1 = httpd::Server::new(|r| {
+        assert_eq!(r.method(), http::method::Method::GET);
+        assert_eq!(r.uri().path(), "/text1.html");
+        let auth = r
+            .headers()
+            .get(PROXY_AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if auth.is_empty() {
+            Response::builder()
+                .status(http::StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .header(PROXY_AUTHENTICATE, "Basic realm=\"proxy\"")
+                .header(PROXY_AUTHENTICATE, "NTLM")
+                .body(crate::environment::empty())
+                .unwrap()
+        } else if auth.starts_with("NTLM TlRMTVNTUAAB") {
+            Response::builder()
+                .status(http::StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .header(
+                    PROXY_AUTHENTICATE,
+                    "NTLM TlRMTVNTUAACAAAAAAAAADAAAAABAgAAASNFZ4mrze8AAAAAAAAAAAAAAAAwAAAA",
+                )
+                .body(crate::environment::empty())
+                .unwrap()
+        } else {
+            assert!(auth.starts_with("NTLM TlRMTVNTUAAD"));
+            Response::builder()
+                .body(crate::environment::full(String::from("Hello World!")))
+                .unwrap()
+        }
+    })
+    .await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"PROXY {}\"; }}",
+            proxy1.uri().build().unwrap().authority().unwrap()
+        )))
+        .netrc_content(Some(format!(
+            "machine {}\nlogin {}\npassword {}\n",
+            proxy1.uri().build().unwrap().host().unwrap(),
+            "hello",
+            "world"
+        )))
+        .authenticator(AuthenticatorFactory::auto)
+        .build()
+        .await;
+
+    for _ in 0..2 {
+        let req = Request::get("http://example.org/text1.html".parse::<Uri>().unwrap())
+            .body(crate::environment::empty())
+            .unwrap();
+
+        let resp = env.send(req).await;
+
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body = read_to_string(resp.into_body()).await;
+        assert_eq!(body, "Hello World!");
+    }
+
+    tokio::join!(env.shutdown(), proxy1.shutdown());
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
 async fn http_get_via_proxy_with_default_auth_request() {
     let proxy1 = httpd::Server::new(|r| {
@@ -159,3 +393,98 @@ async fn http_get_via_proxy_with_auth_request_407() {
 
     tokio::join!(env.shutdown(), proxy1.shutdown());
 }
+
+/// Proxy which asks for NTLM authentication and echos the body of the authenticated request.
+async fn ntlm_echo_proxy() -> httpd::Server {
+    httpd::Server::new(|r| {
+        assert_eq!(r.method(), http::method::Method::POST);
+        let auth = r
+            .headers()
+            .get(PROXY_AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if auth.is_empty() {
+            Response::builder()
+                .status(http::StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .header(PROXY_AUTHENTICATE, "NTLM")
+                .body(crate::environment::empty())
+                .unwrap()
+        } else if auth.starts_with("NTLM TlRMTVNTUAAB") {
+            Response::builder()
+                .status(http::StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .header(
+                    PROXY_AUTHENTICATE,
+                    "NTLM TlRMTVNTUAACAAAAAAAAADAAAAABAgAAASNFZ4mrze8AAAAAAAAAAAAAAAAwAAAA",
+                )
+                .body(crate::environment::empty())
+                .unwrap()
+        } else {
+            assert!(auth.starts_with("NTLM TlRMTVNTUAAD"));
+            Response::new(r.into_body().boxed())
+        }
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn http_post_via_proxy_with_auto_auth_request() {
+    let proxy1 = ntlm_echo_proxy().await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"PROXY {}\"; }}",
+            proxy1.uri().build().unwrap().authority().unwrap()
+        )))
+        .netrc_content(Some(format!(
+            "machine {}\nlogin {}\npassword {}\n",
+            proxy1.uri().build().unwrap().host().unwrap(),
+            "hello",
+            "world"
+        )))
+        .authenticator(AuthenticatorFactory::auto)
+        .build()
+        .await;
+
+    let req = Request::post("http://example.org/form".parse::<Uri>().unwrap())
+        .body(crate::environment::full(String::from("Hello World!")))
+        .unwrap();
+
+    let resp = env.send(req).await;
+
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let body = read_to_string(resp.into_body()).await;
+    assert_eq!(body, "Hello World!");
+
+    tokio::join!(env.shutdown(), proxy1.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn http_post_via_proxy_with_auto_auth_request_too_large() {
+    let proxy1 = ntlm_echo_proxy().await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"PROXY {}\"; }}",
+            proxy1.uri().build().unwrap().authority().unwrap()
+        )))
+        .netrc_content(Some(format!(
+            "machine {}\nlogin {}\npassword {}\n",
+            proxy1.uri().build().unwrap().host().unwrap(),
+            "hello",
+            "world"
+        )))
+        .authenticator(AuthenticatorFactory::auto)
+        .request_buffer_size(4)
+        .build()
+        .await;
+
+    let req = Request::post("http://example.org/form".parse::<Uri>().unwrap())
+        .body(crate::environment::full(String::from("Hello World!")))
+        .unwrap();
+
+    let resp = env.send(req).await;
+
+    assert_eq!(resp.status(), http::StatusCode::BAD_GATEWAY);
+    let body = read_to_string(resp.into_body()).await;
+    assert!(body.contains("larger than 4 bytes"));
+
+    tokio::join!(env.shutdown(), proxy1.shutdown());
+}
@@ -0,0 +1,186 @@
+mod environment;
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use crate::environment::httpd;
+use detox_auth::AuthenticatorFactory;
+use detox_net::PathOrUri;
+use http::{
+    Response, StatusCode,
+    header::{ETAG, IF_NONE_MATCH, LOCATION, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION},
+};
+use paclib::Proxy;
+use proxydetoxlib::integrity::Sha256;
+
+const PAC_SCRIPT: &str = "function FindProxyForURL(url, host) { return \"DIRECT\"; }";
+
+static INIT: std::sync::Once = std::sync::Once::new();
+
+fn init() {
+    INIT.call_once(|| {
+        rustls::crypto::CryptoProvider::install_default(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        )
+        .expect("CryptoProvider::install_default");
+    });
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pac_file_not_modified_and_backup() {
+    init();
+    let not_modified = Arc::new(AtomicUsize::new(0));
+    let http1 = httpd::Server::new({
+        let not_modified = not_modified.clone();
+        move |r| {
+            if r.headers()
+                .get(IF_NONE_MATCH)
+                .is_some_and(|v| v == "\"v1\"")
+            {
+                not_modified.fetch_add(1, Ordering::SeqCst);
+                Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(crate::environment::empty())
+                    .unwrap()
+            } else {
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(ETAG, "\"v1\"")
+                    .body(crate::environment::full(PAC_SCRIPT))
+                    .unwrap()
+            }
+        }
+    })
+    .await;
+    let uri = http1.uri().path_and_query("/proxy.pac").build().unwrap();
+    let backup =
+        std::env::temp_dir().join(format!("proxydetox-test-{}/proxy.pac", std::process::id()));
+
+    let context = proxydetoxlib::Context::builder()
+        .pac_backup_file(Some(backup.clone()))
+        .build();
+    context
+        .load_pac_file(&Some(PathOrUri::Uri(uri.clone())))
+        .await
+        .unwrap();
+    let content = std::fs::read_to_string(&backup).unwrap();
+    assert!(content.ends_with(PAC_SCRIPT));
+
+    context.refresh_pac_file().await.unwrap();
+    assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+
+    // direct mode is not undone by the refresh
+    context.load_pac_file(&None).await.unwrap();
+    context.refresh_pac_file().await.unwrap();
+    assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+
+    http1.shutdown().await;
+
+    // the PAC server is unreachable after a restart
+    let context = proxydetoxlib::Context::builder()
+        .pac_backup_file(Some(backup.clone()))
+        .build();
+    context
+        .load_pac_file(&Some(PathOrUri::Uri(uri.clone())))
+        .await
+        .unwrap();
+    // but the backup of another URL is not used
+    let other = "http://127.0.0.1:1/other.pac".parse().unwrap();
+    assert!(
+        context
+            .load_pac_file(&Some(PathOrUri::Uri(other)))
+            .await
+            .is_err()
+    );
+
+    std::fs::remove_dir_all(backup.parent().unwrap()).ok();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pac_file_via_bootstrap_proxy() {
+    init();
+    // the proxy serves the PAC file of an otherwise unreachable host
+    let proxy = httpd::Server::new(|r| {
+        if r.headers().get(PROXY_AUTHORIZATION).is_none() {
+            return Response::builder()
+                .status(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .header(PROXY_AUTHENTICATE, "Basic realm=\"proxy\"")
+                .body(crate::environment::empty())
+                .unwrap();
+        }
+        assert_eq!(r.uri().host(), Some("pac.example.invalid"));
+        match r.uri().path() {
+            "/old.pac" => Response::builder()
+                .status(StatusCode::FOUND)
+                .header(LOCATION, "proxy.pac")
+                .body(crate::environment::empty())
+                .unwrap(),
+            "/proxy.pac" => Response::builder()
+                .status(StatusCode::OK)
+                .body(crate::environment::full(PAC_SCRIPT))
+                .unwrap(),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(crate::environment::empty())
+                .unwrap(),
+        }
+    })
+    .await;
+    let netrc = format!(
+        "machine {} login user password secret\n",
+        proxy.uri().build().unwrap().host().unwrap()
+    );
+    let store = detox_auth::netrc::Store::new(netrc.as_bytes()).unwrap();
+
+    let context = proxydetoxlib::Context::builder()
+        .authenticator_factory(Some(AuthenticatorFactory::basic(store)))
+        .pac_proxy(Some(Proxy::Http(proxy.host_and_port().parse().unwrap())))
+        .pac_backup_file(None)
+        .build();
+    let uri = "http://pac.example.invalid/old.pac".parse().unwrap();
+    context
+        .load_pac_file(&Some(PathOrUri::Uri(uri)))
+        .await
+        .unwrap();
+
+    proxy.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pac_file_digest_mismatch() {
+    init();
+    let server = httpd::Server::new(|r| {
+        let body = match r.uri().path() {
+            "/good.pac" => PAC_SCRIPT,
+            _ => "function FindProxyForURL(url, host) { return \"PROXY evil.example.org:8080\"; }",
+        };
+        Response::builder()
+            .status(StatusCode::OK)
+            .body(crate::environment::full(body))
+            .unwrap()
+    })
+    .await;
+    let good = server.uri().path_and_query("/good.pac").build().unwrap();
+    let evil = server.uri().path_and_query("/evil.pac").build().unwrap();
+
+    let context = proxydetoxlib::Context::builder()
+        .pac_sha256(vec![Sha256::digest(PAC_SCRIPT.as_bytes())])
+        .pac_backup_file(None)
+        .build();
+    context
+        .load_pac_file(&Some(PathOrUri::Uri(good)))
+        .await
+        .unwrap();
+    assert_eq!(context.pac_refused(), None);
+
+    let err = context
+        .load_pac_file(&Some(PathOrUri::Uri(evil)))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(context.pac_refused().unwrap().contains("SHA-256"));
+
+    server.shutdown().await;
+}
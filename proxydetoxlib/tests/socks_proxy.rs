@@ -0,0 +1,258 @@
+mod environment;
+
+use crate::environment::{Environment, httpd, read_to_string, tcp};
+use detox_net::{HostAndPort, socks};
+use http::{Request, Response, header::PROXY_AUTHORIZATION};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Minimal SOCKS4a/SOCKS5 server which expects `login:password` for SOCKS5 when given.
+async fn socks_server(mut stream: TcpStream, login: Option<(&'static str, &'static str)>) {
+    let version = stream.read_u8().await.unwrap();
+    let (host, port) = if version == 5 {
+        let n = stream.read_u8().await.unwrap();
+        let mut methods = vec![0u8; n as usize];
+        stream.read_exact(&mut methods).await.unwrap();
+        if let Some((login, password)) = login {
+            assert!(methods.contains(&2));
+            stream.write_all(&[5, 2]).await.unwrap();
+            assert_eq!(stream.read_u8().await.unwrap(), 1);
+            let n = stream.read_u8().await.unwrap();
+            let mut user = vec![0u8; n as usize];
+            stream.read_exact(&mut user).await.unwrap();
+            let n = stream.read_u8().await.unwrap();
+            let mut pass = vec![0u8; n as usize];
+            stream.read_exact(&mut pass).await.unwrap();
+            assert_eq!(user, login.as_bytes());
+            assert_eq!(pass, password.as_bytes());
+            stream.write_all(&[1, 0]).await.unwrap();
+        } else {
+            stream.write_all(&[5, 0]).await.unwrap();
+        }
+        let mut req = [0u8; 4];
+        stream.read_exact(&mut req).await.unwrap();
+        assert_eq!(req[1], 1);
+        let host = match req[3] {
+            1 => {
+                let mut ip = [0u8; 4];
+                stream.read_exact(&mut ip).await.unwrap();
+                std::net::Ipv4Addr::from(ip).to_string()
+            }
+            3 => {
+                let n = stream.read_u8().await.unwrap();
+                let mut host = vec![0u8; n as usize];
+                stream.read_exact(&mut host).await.unwrap();
+                String::from_utf8(host).unwrap()
+            }
+            atyp => panic!("unexpected address type {atyp}"),
+        };
+        let port = stream.read_u16().await.unwrap();
+        stream
+            .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        (host, port)
+    } else {
+        assert_eq!(version, 4);
+        assert_eq!(stream.read_u8().await.unwrap(), 1);
+        let port = stream.read_u16().await.unwrap();
+        let mut ip = [0u8; 4];
+        stream.read_exact(&mut ip).await.unwrap();
+        // user id and (for SOCKS4a) host name are null terminated
+        let mut fields = Vec::new();
+        let n = if ip[..3] == [0, 0, 0] { 2 } else { 1 };
+        while fields.len() < n {
+            let mut field = Vec::new();
+            loop {
+                let c = stream.read_u8().await.unwrap();
+                if c == 0 {
+                    break;
+                }
+                field.push(c);
+            }
+            fields.push(String::from_utf8(field).unwrap());
+        }
+        stream.write_all(&[0, 90, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        let host = fields
+            .pop()
+            .filter(|_| n == 2)
+            .unwrap_or_else(|| std::net::Ipv4Addr::from(ip).to_string());
+        (host, port)
+    };
+
+    let mut upstream = TcpStream::connect((host, port)).await.unwrap();
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream)
+        .await
+        .ok();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn http_get_via_socks5_with_auth() {
+    let http1 = httpd::Server::new(|r| {
+        assert_eq!(r.method(), http::method::Method::GET);
+        assert!(r.uri().authority().is_none());
+        assert_eq!(r.uri().path(), "/text1.html");
+        assert!(r.headers().get(PROXY_AUTHORIZATION).is_none());
+        Response::builder()
+            .body(crate::environment::full(String::from("Hello World!")))
+            .unwrap()
+    })
+    .await;
+    let socks = tcp::Server::new(|s| socks_server(s, Some(("hello", "world")))).await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"SOCKS5 {}\"; }}",
+            socks.origin()
+        )))
+        .netrc_content(Some(String::from(
+            "machine 127.0.0.1\nlogin hello\npassword world\n",
+        )))
+        .build()
+        .await;
+
+    let req = Request::get(http1.uri().path_and_query("/text1.html").build().unwrap())
+        .body(crate::environment::empty())
+        .unwrap();
+
+    let resp = env.send(req).await;
+
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let body = read_to_string(resp.into_body()).await;
+    assert_eq!(body, "Hello World!");
+
+    tokio::join!(env.shutdown(), http1.shutdown(), socks.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn connect_via_socks4() {
+    let echo = tcp::Server::new(|mut s| async move {
+        let mut buf = [0u8; 4];
+        s.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"PING");
+        s.write_all(b"PONG").await.unwrap();
+    })
+    .await;
+    let socks = tcp::Server::new(|s| socks_server(s, None)).await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"SOCKS {}\"; }}",
+            socks.origin()
+        )))
+        .build()
+        .await;
+
+    let req = Request::connect(echo.origin())
+        .body(crate::environment::empty())
+        .unwrap();
+
+    let (status, _headers, upgraded) = env.connect(req).await;
+    assert_eq!(status, http::StatusCode::OK);
+    let mut upgraded = TokioIo::new(upgraded.expect("upgraded"));
+    upgraded.write_all(b"PING").await.unwrap();
+    let mut buf = [0u8; 4];
+    upgraded.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"PONG");
+    upgraded.shutdown().await.unwrap();
+
+    tokio::join!(env.shutdown(), echo.shutdown(), socks.shutdown());
+}
+
+async fn ping_pong_server() -> tcp::Server {
+    tcp::Server::new(|mut s| async move {
+        let mut buf = [0u8; 4];
+        s.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"PING");
+        s.write_all(b"PONG").await.unwrap();
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn socks5_client_direct() {
+    let echo = ping_pong_server().await;
+    let env = Environment::new().await;
+
+    // SOCKS clients are detected on the HTTP listener
+    let mut stream = TcpStream::connect(env.proxy_addr()).await.unwrap();
+    let dst = echo.origin().parse::<HostAndPort>().unwrap();
+    socks::socks5_connect(&mut stream, &dst, None)
+        .await
+        .unwrap();
+    stream.write_all(b"PING").await.unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"PONG");
+    drop(stream);
+
+    tokio::join!(env.shutdown(), echo.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn socks5_client_during_shutdown() {
+    let echo = ping_pong_server().await;
+    let env = Environment::new().await;
+
+    let mut stream = TcpStream::connect(env.proxy_addr()).await.unwrap();
+    let dst = echo.origin().parse::<HostAndPort>().unwrap();
+    socks::socks5_connect(&mut stream, &dst, None)
+        .await
+        .unwrap();
+
+    // established tunnels are served until the graceful shutdown timeout
+    env.request_shutdown();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    stream.write_all(b"PING").await.unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"PONG");
+    drop(stream);
+
+    tokio::join!(env.shutdown(), echo.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn socks4a_client_via_socks5() {
+    let echo = ping_pong_server().await;
+    let socks = tcp::Server::new(|s| socks_server(s, None)).await;
+    let env = Environment::builder()
+        .pac_script(Some(format!(
+            "function FindProxyForURL(url, host) {{ return \"SOCKS5 {}\"; }}",
+            socks.origin()
+        )))
+        .build()
+        .await;
+
+    let mut stream = TcpStream::connect(env.proxy_addr()).await.unwrap();
+    let dst = HostAndPort::new(
+        "localhost",
+        echo.origin().parse::<HostAndPort>().unwrap().port(),
+    );
+    socks::socks4a_connect(&mut stream, &dst, None)
+        .await
+        .unwrap();
+    stream.write_all(b"PING").await.unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"PONG");
+    drop(stream);
+
+    tokio::join!(env.shutdown(), echo.shutdown(), socks.shutdown());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn socks5_client_connection_failed() {
+    let env = Environment::builder()
+        .pac_script(Some(String::from(
+            "function FindProxyForURL(url, host) { return \"PROXY 127.0.0.1:1\"; }",
+        )))
+        .build()
+        .await;
+
+    let mut stream = TcpStream::connect(env.proxy_addr()).await.unwrap();
+    let dst = HostAndPort::new("example.org", 443);
+    let result = socks::socks5_connect(&mut stream, &dst, None).await;
+    assert!(matches!(result, Err(socks::Error::Socks5Rejected(_))));
+
+    env.shutdown().await;
+}
@@ -1,5 +1,5 @@
 use libgssapi::{
-    context::{ClientCtx, CtxFlags},
+    context::{ClientCtx, CtxFlags, SecurityContext},
     name::Name,
     oid::{GSS_MECH_SPNEGO, GSS_NT_HOSTBASED_SERVICE},
 };
@@ -44,4 +44,8 @@ impl Context {
             Err(err) => Err(err),
         }
     }
+
+    pub(super) fn is_complete(&self) -> bool {
+        self.cx.is_complete()
+    }
 }
@@ -36,6 +36,12 @@ impl Context {
             .step(server_token)
             .map_err(|inner| Error { inner })
     }
+
+    /// Returns `true` once the handshake completed. Mutual authentication is always requested,
+    /// so a complete context has also authenticated the server.
+    pub fn is_complete(&self) -> bool {
+        self.inner.is_complete()
+    }
 }
 
 #[derive(Debug)]
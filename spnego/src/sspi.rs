@@ -1,5 +1,6 @@
 use std::ffi::c_void;
 
+use windows::Win32::Foundation::SEC_E_OK;
 use windows::Win32::Security::{
     Authentication::Identity::{
         AcquireCredentialsHandleW, ISC_REQ_MUTUAL_AUTH, InitializeSecurityContextW,
@@ -21,6 +22,7 @@ pub(super) struct Context {
     target: String,
     spn: Vec<u16>,
     expiry: TimeStamp,
+    complete: bool,
 }
 
 impl std::fmt::Debug for Context {
@@ -29,6 +31,7 @@ impl std::fmt::Debug for Context {
             .field("target", &self.target)
             .field("spn", &self.spn)
             .field("expiry", &self.expiry)
+            .field("complete", &self.complete)
             .finish()
     }
 }
@@ -76,15 +79,15 @@ impl Context {
             spn,
             cred,
             expiry,
+            complete: false,
         })
     }
 
-    // TODO: server token is not used right now.
     // https://docs.microsoft.com/en-us/openspecs/office_protocols/ms-grvhenc/b9e676e7-e787-4020-9840-7cfe7c76044a?redirectedfrom=MSDN
     // https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-server-2003/cc772815(v=ws.10)
     pub(super) fn step(
         &mut self,
-        _server_token: Option<&[u8]>,
+        server_token: Option<&[u8]>,
     ) -> std::result::Result<Option<Vec<u8>>, windows::core::Error> {
         let mut buf = Vec::with_capacity(MAX_TOKEN_SIZE);
         buf.resize(MAX_TOKEN_SIZE, 0);
@@ -100,16 +103,31 @@ impl Context {
         };
         let mut cx_attrs = 0u32;
 
+        // Continuation steps pass the server token and the context of the previous step.
+        let mut input_buffer = server_token.map(|token| {
+            [SecBuffer {
+                BufferType: SECBUFFER_TOKEN,
+                cbBuffer: token.len() as u32,
+                pvBuffer: token.as_ptr() as *mut c_void,
+            }]
+        });
+        let input_desc = input_buffer.as_mut().map(|b| SecBufferDesc {
+            ulVersion: SECBUFFER_VERSION,
+            cBuffers: b.len() as u32,
+            pBuffers: b.as_mut_ptr(),
+        });
+        let prev_cx = self.cx;
+
         // https://docs.microsoft.com/en-us/windows/win32/api/sspi/nf-sspi-initializesecuritycontexta
         let status = unsafe {
             InitializeSecurityContextW(
                 Some(&mut self.cred),
-                None,
+                input_desc.as_ref().map(|_| &prev_cx as *const SecHandle),
                 Some(self.spn.as_ptr()),
                 ISC_REQ_MUTUAL_AUTH,
                 0,
                 SECURITY_NATIVE_DREP,
-                None,
+                input_desc.as_ref().map(|d| d as *const SecBufferDesc),
                 0,
                 Some(&mut self.cx),
                 Some(&mut buffer_desc),
@@ -118,6 +136,8 @@ impl Context {
             )
         };
         status.ok()?;
+        // SEC_I_CONTINUE_NEEDED and friends are successful, but need another step
+        self.complete = status == SEC_E_OK;
 
         // Shrink buffer to acutall token size
         buf.resize(sec_buffer[0].cbBuffer as usize, 0);
@@ -128,4 +148,8 @@ impl Context {
             Ok(None)
         }
     }
+
+    pub(super) fn is_complete(&self) -> bool {
+        self.complete
+    }
 }